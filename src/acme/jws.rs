@@ -0,0 +1,271 @@
+use openssl::bn::BigNumContext;
+use openssl::ecdsa::EcdsaSig;
+use openssl::error::ErrorStack;
+use openssl::hash::{hash, MessageDigest};
+use openssl::nid::Nid;
+use openssl::pkey::{HasPublic, Id, PKey, PKeyRef, Private};
+use openssl::sign::Signer;
+use serde_json::{json, Value};
+
+use crate::client::eab::EabCredentials;
+use crate::error;
+use crate::error::Result;
+use crate::util::hmac_sha256;
+
+// JSON Web Signature (RFC 7515) in the flattened JSON serialization used by ACME
+
+pub(crate) fn b64(data: &[u8]) -> String {
+    base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+#[cfg(any(test, feature = "testing"))]
+pub(crate) fn b64_decode(data: &str) -> Option<Vec<u8>> {
+    base64::decode_config(data, base64::URL_SAFE_NO_PAD).ok()
+}
+
+/// The JWS algorithm for an account key: RS256, ES256 or ES384.
+pub fn key_alg<T: HasPublic>(key: &PKeyRef<T>) -> Result<&'static str> {
+    match key.id() {
+        Id::RSA => Ok("RS256"),
+        Id::EC => {
+            let curve = key.ec_key()
+                .map_err(|e| error::openssl(e, None))?
+                .group()
+                .curve_name();
+            match curve {
+                Some(Nid::X9_62_PRIME256V1) => Ok("ES256"),
+                Some(Nid::SECP384R1) => Ok("ES384"),
+                _ => Err(error::validation("unsupported account key curve (use P-256 or P-384)")),
+            }
+        }
+        _ => Err(error::validation("unsupported account key type (use RSA or EC)")),
+    }
+}
+
+/// The public part of `key` as a JWK. Members are sorted, as required for
+/// thumbprints.
+pub fn jwk<T: HasPublic>(key: &PKeyRef<T>) -> Result<Value> {
+    let jwk = match key_alg(key)? {
+        "RS256" => {
+            let rsa = key.rsa().map_err(|e| error::openssl(e, None))?;
+            json!({
+                "e": b64(&rsa.e().to_vec()),
+                "kty": "RSA",
+                "n": b64(&rsa.n().to_vec()),
+            })
+        }
+        alg => {
+            let (crv, len) = if alg == "ES256" { ("P-256", 32) } else { ("P-384", 48) };
+            let (x, y) = ec_coordinates(key, len)
+                .map_err(|e| error::openssl(e, None))?;
+            json!({
+                "crv": crv,
+                "kty": "EC",
+                "x": b64(&x),
+                "y": b64(&y),
+            })
+        }
+    };
+
+    Ok(jwk)
+}
+
+/// The RFC 7638 thumbprint of a JWK, base64url encoded.
+pub fn jwk_thumbprint(jwk: &Value) -> Result<String> {
+    // serde_json keeps object members sorted, so this is the canonical form
+    let digest = hash(MessageDigest::sha256(), jwk.to_string().as_bytes())
+        .map_err(|e| error::openssl(e, None))?;
+
+    Ok(b64(&digest))
+}
+
+/// Signs `payload` (`None` for POST-as-GET) for `url`, identifying the
+/// account by `kid` or, before it exists, by its JWK.
+pub(crate) fn sign(key: &PKey<Private>, kid: Option<&str>, nonce: &str, url: &str,
+                   payload: Option<&Value>) -> Result<Value> {
+    let alg = key_alg(key)?;
+    let mut protected = json!({
+        "alg": alg,
+        "nonce": nonce,
+        "url": url,
+    });
+    match kid {
+        Some(kid) => protected["kid"] = json!(kid),
+        None => protected["jwk"] = jwk(key)?,
+    }
+
+    let protected = b64(protected.to_string().as_bytes());
+    let payload = payload
+        .map(|p| b64(p.to_string().as_bytes()))
+        .unwrap_or_default();
+    let signature = sign_bytes(key, alg, format!("{}.{}", protected, payload).as_bytes())
+        .map_err(|e| error::openssl(e, Some("failed to sign request".to_string())))?;
+
+    Ok(json!({
+        "protected": protected,
+        "payload": payload,
+        "signature": b64(&signature),
+    }))
+}
+
+/// The `externalAccountBinding` of a new account request (RFC 8555, 7.3.4).
+pub(crate) fn external_account_binding(key: &PKey<Private>, eab: &EabCredentials, url: &str) -> Result<Value> {
+    let protected = json!({
+        "alg": "HS256",
+        "kid": eab.kid,
+        "url": url,
+    });
+
+    let protected = b64(protected.to_string().as_bytes());
+    let payload = b64(jwk(key)?.to_string().as_bytes());
+    let signature = hmac_sha256(&eab.hmac_key, format!("{}.{}", protected, payload).as_bytes())
+        .map_err(|e| error::openssl(e, Some("failed to sign external account binding".to_string())))?;
+
+    Ok(json!({
+        "protected": protected,
+        "payload": payload,
+        "signature": b64(&signature),
+    }))
+}
+
+fn sign_bytes(key: &PKey<Private>, alg: &str, data: &[u8]) -> std::result::Result<Vec<u8>, ErrorStack> {
+    let (digest, len) = match alg {
+        "ES256" => (MessageDigest::sha256(), 32),
+        "ES384" => (MessageDigest::sha384(), 48),
+        _ => (MessageDigest::sha256(), 0),
+    };
+
+    let mut signer = Signer::new(digest, key)?;
+    signer.update(data)?;
+    let signature = signer.sign_to_vec()?;
+    if len == 0 {
+        return Ok(signature);
+    }
+
+    // JWS wants the raw r || s instead of DER
+    let signature = EcdsaSig::from_der(&signature)?;
+    let mut raw = signature.r().to_vec_padded(len)?;
+    raw.extend(signature.s().to_vec_padded(len)?);
+
+    Ok(raw)
+}
+
+fn ec_coordinates<T: HasPublic>(key: &PKeyRef<T>, len: i32) -> std::result::Result<(Vec<u8>, Vec<u8>), ErrorStack> {
+    let ec = key.ec_key()?;
+    let mut ctx = BigNumContext::new()?;
+    let mut x = openssl::bn::BigNum::new()?;
+    let mut y = openssl::bn::BigNum::new()?;
+    ec.public_key().affine_coordinates_gfp(ec.group(), &mut x, &mut y, &mut ctx)?;
+
+    Ok((x.to_vec_padded(len)?, y.to_vec_padded(len)?))
+}
+
+// Verification, only needed by the fake ACME server
+
+#[cfg(any(test, feature = "testing"))]
+pub(crate) fn public_key_from_jwk(jwk: &Value) -> Option<PKey<openssl::pkey::Public>> {
+    use openssl::bn::BigNum;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::rsa::Rsa;
+
+    let component = |name: &str| jwk[name].as_str()
+        .and_then(b64_decode)
+        .and_then(|b| BigNum::from_slice(&b).ok());
+
+    match jwk["kty"].as_str()? {
+        "RSA" => {
+            let rsa = Rsa::from_public_components(component("n")?, component("e")?).ok()?;
+            PKey::from_rsa(rsa).ok()
+        }
+        "EC" => {
+            let nid = match jwk["crv"].as_str()? {
+                "P-256" => Nid::X9_62_PRIME256V1,
+                "P-384" => Nid::SECP384R1,
+                _ => return None,
+            };
+            let group = EcGroup::from_curve_name(nid).ok()?;
+            let (x, y) = (component("x")?, component("y")?);
+            let ec = EcKey::from_public_key_affine_coordinates(&group, &x, &y).ok()?;
+            PKey::from_ec_key(ec).ok()
+        }
+        _ => None,
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+pub(crate) fn verify(key: &PKey<openssl::pkey::Public>, alg: &str, data: &[u8], signature: &[u8]) -> bool {
+    use openssl::bn::BigNum;
+    use openssl::sign::Verifier;
+
+    let (digest, signature) = match alg {
+        "RS256" => (MessageDigest::sha256(), signature.to_vec()),
+        "ES256" | "ES384" => {
+            let digest = if alg == "ES256" { MessageDigest::sha256() } else { MessageDigest::sha384() };
+            let half = signature.len() / 2;
+            let der = BigNum::from_slice(&signature[..half])
+                .and_then(|r| Ok((r, BigNum::from_slice(&signature[half..])?)))
+                .and_then(|(r, s)| EcdsaSig::from_private_components(r, s))
+                .and_then(|sig| sig.to_der());
+            match der {
+                Ok(der) => (digest, der),
+                Err(_) => return false,
+            }
+        }
+        _ => return false,
+    };
+
+    Verifier::new(digest, key)
+        .and_then(|mut v| {
+            v.update(data)?;
+            v.verify(&signature)
+        })
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::nid::Nid;
+    use openssl::pkey::PKey;
+    use openssl::rsa::Rsa;
+    use serde_json::json;
+
+    use crate::acme::jws::{b64_decode, jwk, jwk_thumbprint, key_alg, public_key_from_jwk, sign, verify};
+
+    #[test]
+    fn jwk_thumbprint_test() {
+        // RFC 7638, section 3.1
+        let jwk = json!({
+            "kty": "RSA",
+            "n": "0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_BJECPebWKRXjBZCiFV4n3oknjhMstn64tZ_2W-5JsGY4Hc5n9yBXArwl93lqt7_RN5w6Cf0h4QyQ5v-65YGjQR0_FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQvRL5hajrn1n91CbOpbISD08qNLyrdkt-bFTWhAI4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csFCur-kEgU8awapJzKnqDKgw",
+            "e": "AQAB",
+        });
+
+        assert_eq!(jwk_thumbprint(&jwk).unwrap(), "NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs");
+    }
+
+    #[test]
+    fn sign_verify_test() {
+        let keys = vec![
+            PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap(),
+            PKey::from_ec_key(EcKey::generate(&EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap()).unwrap()).unwrap(),
+            PKey::from_ec_key(EcKey::generate(&EcGroup::from_curve_name(Nid::SECP384R1).unwrap()).unwrap()).unwrap(),
+        ];
+
+        for key in keys {
+            let alg = key_alg(&key).unwrap();
+            let jws = sign(&key, None, "nonce", "https://acme.test/new-account", Some(&json!({}))).unwrap();
+
+            let protected = b64_decode(jws["protected"].as_str().unwrap()).unwrap();
+            let protected: serde_json::Value = serde_json::from_slice(&protected).unwrap();
+            assert_eq!(protected["alg"], alg);
+            assert_eq!(protected["jwk"], jwk(&key).unwrap());
+
+            let public = public_key_from_jwk(&protected["jwk"]).unwrap();
+            let data = format!("{}.{}", jws["protected"].as_str().unwrap(), jws["payload"].as_str().unwrap());
+            let signature = b64_decode(jws["signature"].as_str().unwrap()).unwrap();
+            assert!(verify(&public, alg, data.as_bytes(), &signature), "{}", alg);
+            assert!(!verify(&public, alg, b"tampered", &signature), "{}", alg);
+        }
+    }
+}
@@ -0,0 +1,514 @@
+//! A client for ZeroSSL's ACME (RFC 8555) endpoint.
+//!
+//! Unlike the REST API in [`client`](crate::client), ACME has no rate limits
+//! on 90-day certificates and supports wildcards. ZeroSSL requires External
+//! Account Binding, see [`Client::generate_eab_credentials`](crate::client::Client::generate_eab_credentials).
+use std::sync::Mutex;
+use std::time::Duration;
+
+use openssl::pkey::{PKey, Private};
+use openssl::x509::X509Req;
+use reqwest::header::{HeaderMap, ACCEPT, CONTENT_TYPE, LOCATION};
+use reqwest::Response;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+use crate::acme::types::{Account, Authorization, AuthorizationStatus, Challenge, Directory, Identifier, Order, OrderStatus, Problem};
+use crate::certs::csr::{Csr, generate_csr};
+use crate::client::builder::DEFAULT_USER_AGENT;
+use crate::client::eab::EabCredentials;
+use crate::client::wait::WaitOptions;
+use crate::error as error;
+use crate::error::Result;
+
+pub mod jws;
+pub mod types;
+
+pub const ZEROSSL_DIRECTORY_URL: &str = "https://acme.zerossl.com/v2/DV90";
+
+// How often a request is re-signed after the server rejected its nonce
+const BAD_NONCE_RETRIES: u32 = 3;
+
+pub struct AcmeClient {
+    http: reqwest::Client,
+    directory: Directory,
+    account_key: PKey<Private>,
+    account_url: Option<String>,
+    nonce: Mutex<Option<String>>,
+}
+
+impl AcmeClient {
+    /// Fetches the directory at `directory_url` (e.g. `ZEROSSL_DIRECTORY_URL`).
+    /// `account_key` must be RSA, P-256 or P-384.
+    pub async fn new(directory_url: &str, account_key: PKey<Private>) -> Result<Self> {
+        let http = reqwest::Client::builder()
+            .user_agent(DEFAULT_USER_AGENT)
+            .build()
+            .map_err(|e| error::request(e, Some("failed to build http client".to_string())))?;
+
+        Self::with_http_client(http, directory_url, account_key).await
+    }
+
+    pub async fn with_http_client(http: reqwest::Client, directory_url: &str, account_key: PKey<Private>) -> Result<Self> {
+        jws::key_alg(&account_key)?;
+
+        let res = http.get(directory_url)
+            .send()
+            .await
+            .map_err(|e| error::request(e, Some("failed to fetch ACME directory".to_string())))?;
+        let directory = parse_json::<Directory>(res).await?;
+
+        Ok(Self {
+            http,
+            directory,
+            account_key,
+            account_url: None,
+            nonce: Mutex::new(None),
+        })
+    }
+
+    /// Uses an account registered earlier instead of calling `register_account`.
+    pub fn with_account_url(&mut self, account_url: String) -> &mut Self {
+        self.account_url = Some(account_url);
+        self
+    }
+
+    // Accessors
+    pub fn directory(&self) -> &Directory {
+        &self.directory
+    }
+
+    pub fn account_url(&self) -> Option<String> {
+        self.account_url.clone()
+    }
+
+    /// The key authorization for `challenge` (RFC 8555, 8.1), served as is
+    /// for `http-01`.
+    pub fn key_authorization(&self, challenge: &Challenge) -> Result<String> {
+        let thumbprint = jws::jwk_thumbprint(&jws::jwk(&self.account_key)?)?;
+
+        Ok(format!("{}.{}", challenge.token, thumbprint))
+    }
+
+    /// The TXT record value for a `dns-01` challenge.
+    pub fn dns01_txt_value(&self, challenge: &Challenge) -> Result<String> {
+        let key_authorization = self.key_authorization(challenge)?;
+        let digest = openssl::hash::hash(openssl::hash::MessageDigest::sha256(), key_authorization.as_bytes())
+            .map_err(|e| error::openssl(e, None))?;
+
+        Ok(jws::b64(&digest))
+    }
+
+    // Actions
+    /// Registers the account key, agreeing to the terms of service. Returns
+    /// the existing account if the key is already registered.
+    pub async fn register_account(&mut self, contact: Vec<String>, eab: Option<&EabCredentials>) -> Result<Account> {
+        let url = self.directory.new_account.clone();
+        let mut payload = json!({
+            "termsOfServiceAgreed": true,
+            "contact": contact,
+        });
+        match eab {
+            Some(eab) => payload["externalAccountBinding"] = jws::external_account_binding(&self.account_key, eab, &url)?,
+            None if self.directory.external_account_required() =>
+                return Err(error::validation("the ACME server requires external account binding")),
+            None => {}
+        }
+
+        let res = self.post(&url, Some(&payload)).await?;
+        let account_url = location(res.headers())?;
+        let mut account = parse_json::<Account>(res).await?;
+
+        account.url = account_url.clone();
+        self.account_url = Some(account_url);
+
+        Ok(account)
+    }
+
+    pub async fn new_order(&self, domains: Vec<String>) -> Result<Order> {
+        if domains.is_empty() {
+            return Err(error::validation("an order needs at least one domain"));
+        }
+
+        let identifiers: Vec<Identifier> = domains.into_iter()
+            .map(Identifier::dns)
+            .collect();
+        let url = self.directory.new_order.clone();
+
+        let res = self.post(&url, Some(&json!({ "identifiers": identifiers }))).await?;
+        let order_url = location(res.headers())?;
+        let mut order = parse_json::<Order>(res).await?;
+        order.url = order_url;
+
+        Ok(order)
+    }
+
+    pub async fn order(&self, url: &str) -> Result<Order> {
+        let res = self.post(url, None).await?;
+        let mut order = parse_json::<Order>(res).await?;
+        order.url = url.to_string();
+
+        Ok(order)
+    }
+
+    pub async fn authorization(&self, url: &str) -> Result<Authorization> {
+        let res = self.post(url, None).await?;
+        let mut authorization = parse_json::<Authorization>(res).await?;
+        authorization.url = url.to_string();
+
+        Ok(authorization)
+    }
+
+    pub async fn authorizations(&self, order: &Order) -> Result<Vec<Authorization>> {
+        let mut authorizations = Vec::new();
+        for url in order.authorizations.iter() {
+            authorizations.push(self.authorization(url).await?);
+        }
+
+        Ok(authorizations)
+    }
+
+    /// Tells the server the challenge is ready to be validated. The record or
+    /// file must be in place before calling this.
+    pub async fn respond_challenge(&self, challenge: &Challenge) -> Result<Challenge> {
+        let res = self.post(&challenge.url, Some(&json!({}))).await?;
+
+        parse_json::<Challenge>(res).await
+    }
+
+    /// Polls the authorization until it is no longer pending. An invalid
+    /// authorization is returned as an error with the challenge's problem.
+    pub async fn wait_for_authorization(&self, url: &str, opts: &WaitOptions) -> Result<Authorization> {
+        let started = tokio::time::Instant::now();
+        let mut interval = opts.initial_interval();
+
+        loop {
+            let authorization = self.authorization(url).await?;
+
+            match authorization.status {
+                AuthorizationStatus::Valid => return Ok(authorization),
+                AuthorizationStatus::Pending => {}
+                status => {
+                    let problem = authorization.challenges.iter()
+                        .find_map(|c| c.error.clone());
+                    return Err(match problem {
+                        Some(problem) => error::acme(problem),
+                        None => error::validation(format!(
+                            "authorization for {} is {:?}", authorization.identifier.value, status)),
+                    });
+                }
+            }
+
+            interval = self.sleep_or_timeout(started, interval, opts,
+                                             format!("authorization for {} still pending", authorization.identifier.value)).await?;
+        }
+    }
+
+    /// Finalizes a ready order with a CSR covering exactly its identifiers.
+    pub async fn finalize(&self, order: &Order, csr: &X509Req) -> Result<Order> {
+        let der = csr.to_der()
+            .map_err(|e| error::openssl(e, None))?;
+
+        let res = self.post(&order.finalize, Some(&json!({ "csr": jws::b64(&der) }))).await?;
+        let mut finalized = parse_json::<Order>(res).await?;
+        finalized.url = order.url.clone();
+
+        Ok(finalized)
+    }
+
+    /// Finalizes with a CSR for the order's domains built by `generate_csr`,
+    /// using the first domain as common name.
+    pub async fn finalize_with_key(&self, order: &Order, pkey: &PKey<Private>) -> Result<Order> {
+        let domains = order.domains();
+        let common_name = domains.first()
+            .ok_or_else(|| error::validation("order has no identifiers"))?;
+
+        let mut csr = Csr::new(common_name.clone());
+        csr.with_alt_names(domains.clone());
+        let req = generate_csr(pkey, &csr)
+            .map_err(|e| error::openssl(e, Some("failed to generate csr".to_string())))?;
+
+        self.finalize(order, &req).await
+    }
+
+    /// Polls the order until it is valid (certificate issued) or invalid.
+    pub async fn wait_for_order(&self, order: &Order, opts: &WaitOptions) -> Result<Order> {
+        let started = tokio::time::Instant::now();
+        let mut interval = opts.initial_interval();
+        let mut order = order.clone();
+
+        loop {
+            match order.status {
+                OrderStatus::Valid => return Ok(order),
+                OrderStatus::Invalid => {
+                    return Err(match order.error {
+                        Some(problem) => error::acme(problem),
+                        None => error::validation(format!("order {} is invalid", order.url)),
+                    });
+                }
+                _ => {}
+            }
+
+            interval = self.sleep_or_timeout(started, interval, opts,
+                                             format!("order {} still {:?}", order.url, order.status)).await?;
+            order = self.order(&order.url).await?;
+        }
+    }
+
+    /// Downloads the PEM chain (leaf first) of a valid order.
+    pub async fn download_certificate(&self, order: &Order) -> Result<String> {
+        let url = order.certificate.as_ref()
+            .ok_or_else(|| error::validation(format!("order {} has no certificate yet", order.url)))?;
+
+        let res = self.post(url, None).await?;
+        res.text()
+            .await
+            .map_err(|e| error::decode(e, None))
+    }
+
+    // Requests
+    // Sends a signed POST (POST-as-GET without payload), fetching a fresh
+    // nonce and re-signing when the server rejects the nonce.
+    async fn post(&self, url: &str, payload: Option<&Value>) -> Result<Response> {
+        let mut attempt: u32 = 1;
+
+        loop {
+            let nonce = self.nonce().await?;
+            let body = jws::sign(&self.account_key, self.account_url.as_deref(), &nonce, url, payload)?;
+
+            let res = self.http.post(url)
+                .header(CONTENT_TYPE, "application/jose+json")
+                .header(ACCEPT, "application/json, application/pem-certificate-chain")
+                .body(body.to_string())
+                .send()
+                .await
+                .map_err(|e| error::request(e, None))?;
+            self.save_nonce(res.headers());
+
+            if res.status().is_success() {
+                return Ok(res);
+            }
+
+            let err = problem_to_err(res).await;
+            let bad_nonce = err.acme_problem().map(|p| p.is_bad_nonce()).unwrap_or(false);
+            if !bad_nonce || attempt >= BAD_NONCE_RETRIES {
+                return Err(err);
+            }
+
+            attempt += 1;
+        }
+    }
+
+    async fn nonce(&self) -> Result<String> {
+        if let Some(nonce) = self.nonce.lock().unwrap().take() {
+            return Ok(nonce);
+        }
+
+        let res = self.http.head(&self.directory.new_nonce)
+            .send()
+            .await
+            .map_err(|e| error::request(e, Some("failed to fetch nonce".to_string())))?;
+
+        res.headers().get("replay-nonce")
+            .and_then(|n| n.to_str().ok())
+            .map(|n| n.to_string())
+            .ok_or_else(|| error::decode("missing Replay-Nonce header", None))
+    }
+
+    fn save_nonce(&self, headers: &HeaderMap) {
+        if let Some(nonce) = headers.get("replay-nonce").and_then(|n| n.to_str().ok()) {
+            *self.nonce.lock().unwrap() = Some(nonce.to_string());
+        }
+    }
+
+    // Returns the next interval, or a timeout error once the deadline has passed.
+    async fn sleep_or_timeout(&self, started: tokio::time::Instant, interval: Duration, opts: &WaitOptions,
+                              msg: String) -> Result<Duration> {
+        let elapsed = started.elapsed();
+        let remaining = opts.deadline().saturating_sub(elapsed);
+        if remaining.is_zero() {
+            return Err(error::timeout(format!("{} after {:?}", msg, elapsed)));
+        }
+
+        tokio::time::sleep(interval.min(remaining)).await;
+        Ok(opts.next_interval(interval))
+    }
+}
+
+async fn parse_json<T: DeserializeOwned>(res: Response) -> Result<T> {
+    if !res.status().is_success() {
+        return Err(problem_to_err(res).await);
+    }
+
+    res.json::<T>()
+        .await
+        .map_err(|e| error::decode(e, None))
+}
+
+async fn problem_to_err(res: Response) -> error::Error {
+    let status = res.status();
+    let body = res.text()
+        .await.unwrap_or("no body returned".to_string());
+
+    match serde_json::from_str::<Problem>(&body) {
+        Ok(problem) => error::acme(problem).with_status(status),
+        Err(_) => error::http(status, Some(body)),
+    }
+}
+
+fn location(headers: &HeaderMap) -> Result<String> {
+    headers.get(LOCATION)
+        .and_then(|l| l.to_str().ok())
+        .map(|l| l.to_string())
+        .ok_or_else(|| error::decode("missing Location header", None))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::nid::Nid;
+    use openssl::pkey::{PKey, Private};
+    use openssl::stack::Stack;
+    use openssl::x509::{X509, X509StoreContext};
+    use openssl::x509::store::X509StoreBuilder;
+
+    use crate::acme::AcmeClient;
+    use crate::acme::types::{CHALLENGE_DNS_01, CHALLENGE_HTTP_01, OrderStatus};
+    use crate::certs::csr::generate_rsa_2048_priv_key;
+    use crate::client::eab::EabCredentials;
+    use crate::client::wait::WaitOptions;
+    use crate::testing::acme::FakeAcme;
+
+    fn account_key() -> PKey<Private> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+    }
+
+    fn wait_opts() -> WaitOptions {
+        let mut opts = WaitOptions::default();
+        opts.with_initial_interval(Duration::from_millis(1))
+            .with_deadline(Duration::from_secs(5));
+        opts
+    }
+
+    async fn registered_client(fake: &FakeAcme) -> AcmeClient {
+        let eab = fake.generate_eab_credentials();
+        let mut client = AcmeClient::new(&fake.directory_url(), account_key()).await
+            .expect("failed to fetch directory");
+        client.register_account(vec!["mailto:ops@example.com".to_string()], Some(&eab)).await
+            .expect("failed to register account");
+        client
+    }
+
+    #[tokio::test]
+    async fn issue_http01_test() {
+        let fake = FakeAcme::start().await.unwrap();
+        let client = registered_client(&fake).await;
+        assert!(client.directory().external_account_required());
+
+        let order = client.new_order(vec!["example.com".to_string(), "www.example.com".to_string()]).await
+            .expect("failed to create order");
+        assert_eq!(order.status, OrderStatus::Pending);
+
+        for authorization in client.authorizations(&order).await.unwrap() {
+            let challenge = authorization.challenge(CHALLENGE_HTTP_01).unwrap();
+            let key_authorization = client.key_authorization(challenge).unwrap();
+            fake.serve_http01(&authorization.identifier.value, &challenge.token, &key_authorization);
+
+            client.respond_challenge(challenge).await.unwrap();
+            client.wait_for_authorization(&authorization.url, &wait_opts()).await
+                .expect("authorization failed");
+        }
+
+        let order = client.order(&order.url).await.unwrap();
+        assert_eq!(order.status, OrderStatus::Ready);
+
+        let pkey = generate_rsa_2048_priv_key().unwrap();
+        let order = client.finalize_with_key(&order, &pkey).await
+            .expect("failed to finalize");
+        let order = client.wait_for_order(&order, &wait_opts()).await.unwrap();
+
+        let chain = client.download_certificate(&order).await.unwrap();
+        let chain = X509::stack_from_pem(chain.as_bytes()).unwrap();
+        assert_eq!(chain.len(), 2);
+        assert!(chain[0].public_key().unwrap().public_eq(&pkey));
+
+        let mut store = X509StoreBuilder::new().unwrap();
+        store.add_cert(fake.ca()).unwrap();
+        let store = store.build();
+        let mut ctx = X509StoreContext::new().unwrap();
+        assert!(ctx.init(&store, &chain[0], &Stack::new().unwrap(), |c| c.verify_cert()).unwrap());
+    }
+
+    #[tokio::test]
+    async fn issue_wildcard_dns01_test() {
+        let fake = FakeAcme::start().await.unwrap();
+        let client = registered_client(&fake).await;
+
+        let order = client.new_order(vec!["*.example.com".to_string()]).await.unwrap();
+        let authorization = client.authorizations(&order).await.unwrap().remove(0);
+        assert!(authorization.is_wildcard());
+        assert!(authorization.challenge(CHALLENGE_HTTP_01).is_none());
+
+        let challenge = authorization.challenge(CHALLENGE_DNS_01).unwrap();
+        fake.serve_dns01("example.com", &client.dns01_txt_value(challenge).unwrap());
+        client.respond_challenge(challenge).await.unwrap();
+        client.wait_for_authorization(&authorization.url, &wait_opts()).await.unwrap();
+
+        let order = client.finalize_with_key(&order, &generate_rsa_2048_priv_key().unwrap()).await.unwrap();
+        let order = client.wait_for_order(&order, &wait_opts()).await.unwrap();
+        assert!(order.certificate.is_some());
+    }
+
+    #[tokio::test]
+    async fn failed_challenge_test() {
+        let fake = FakeAcme::start().await.unwrap();
+        let client = registered_client(&fake).await;
+
+        let order = client.new_order(vec!["example.com".to_string()]).await.unwrap();
+        let authorization = client.authorizations(&order).await.unwrap().remove(0);
+        let challenge = authorization.challenge(CHALLENGE_HTTP_01).unwrap();
+
+        // Nothing served
+        client.respond_challenge(challenge).await.unwrap();
+        let err = client.wait_for_authorization(&authorization.url, &wait_opts()).await.unwrap_err();
+        assert!(err.is_acme());
+        assert_eq!(err.acme_problem().unwrap().typ, "urn:ietf:params:acme:error:unauthorized");
+
+        let err = client.finalize_with_key(&order, &generate_rsa_2048_priv_key().unwrap()).await.unwrap_err();
+        assert_eq!(err.acme_problem().unwrap().typ, "urn:ietf:params:acme:error:orderNotReady");
+    }
+
+    #[tokio::test]
+    async fn account_test() {
+        let fake = FakeAcme::start().await.unwrap();
+        let key = account_key();
+
+        let mut client = AcmeClient::new(&fake.directory_url(), key.clone()).await.unwrap();
+        assert!(client.register_account(vec![], None).await.unwrap_err().is_validation());
+
+        let bogus = EabCredentials::from_encoded("unknown".to_string(), "c2VjcmV0").unwrap();
+        let err = client.register_account(vec![], Some(&bogus)).await.unwrap_err();
+        assert_eq!(err.acme_problem().unwrap().typ, "urn:ietf:params:acme:error:unauthorized");
+
+        let eab = fake.generate_eab_credentials();
+        let account = client.register_account(vec![], Some(&eab)).await.unwrap();
+        assert_eq!(account.status, "valid");
+
+        // Same key, same account
+        let mut client = AcmeClient::new(&fake.directory_url(), key).await.unwrap();
+        assert_eq!(client.register_account(vec![], Some(&eab)).await.unwrap().url, account.url);
+    }
+
+    #[tokio::test]
+    async fn bad_nonce_test() {
+        let fake = FakeAcme::start().await.unwrap();
+        let client = registered_client(&fake).await;
+
+        fake.reject_next_nonce();
+        client.new_order(vec!["example.com".to_string()]).await
+            .expect("bad nonce should be retried");
+    }
+}
@@ -0,0 +1,187 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+pub const CHALLENGE_HTTP_01: &str = "http-01";
+pub const CHALLENGE_DNS_01: &str = "dns-01";
+
+pub const PROBLEM_BAD_NONCE: &str = "urn:ietf:params:acme:error:badNonce";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Directory {
+    pub new_nonce: String,
+    pub new_account: String,
+    pub new_order: String,
+    pub revoke_cert: Option<String>,
+    pub key_change: Option<String>,
+    pub meta: Option<DirectoryMeta>,
+}
+
+impl Directory {
+    pub fn external_account_required(&self) -> bool {
+        self.meta.as_ref()
+            .and_then(|m| m.external_account_required)
+            .unwrap_or(false)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DirectoryMeta {
+    pub terms_of_service: Option<String>,
+    pub website: Option<String>,
+    pub caa_identities: Option<Vec<String>>,
+    pub external_account_required: Option<bool>,
+}
+
+/// An RFC 7807 problem document.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub detail: Option<String>,
+    pub status: Option<u16>,
+    pub identifier: Option<Identifier>,
+    pub subproblems: Option<Vec<Problem>>,
+}
+
+impl Problem {
+    pub fn is_bad_nonce(&self) -> bool {
+        self.typ == PROBLEM_BAD_NONCE
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.typ)?;
+
+        if let Some(detail) = &self.detail {
+            write!(f, ": {}", detail)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Identifier {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub value: String,
+}
+
+impl Identifier {
+    pub fn dns(value: String) -> Self {
+        Self {
+            typ: "dns".to_string(),
+            value,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Account {
+    /// The account URL, used as `kid` in later requests.
+    #[serde(skip)]
+    pub url: String,
+    pub status: String,
+    pub contact: Option<Vec<String>>,
+    pub orders: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OrderStatus {
+    Pending,
+    Ready,
+    Processing,
+    Valid,
+    Invalid,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Order {
+    #[serde(skip)]
+    pub url: String,
+    pub status: OrderStatus,
+    pub expires: Option<String>,
+    pub identifiers: Vec<Identifier>,
+    pub authorizations: Vec<String>,
+    pub finalize: String,
+    pub certificate: Option<String>,
+    pub error: Option<Problem>,
+}
+
+impl Order {
+    pub fn domains(&self) -> Vec<String> {
+        self.identifiers.iter()
+            .map(|i| i.value.clone())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthorizationStatus {
+    Pending,
+    Valid,
+    Invalid,
+    Deactivated,
+    Expired,
+    Revoked,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Authorization {
+    #[serde(skip)]
+    pub url: String,
+    pub identifier: Identifier,
+    pub status: AuthorizationStatus,
+    pub expires: Option<String>,
+    pub challenges: Vec<Challenge>,
+    pub wildcard: Option<bool>,
+}
+
+impl Authorization {
+    /// The challenge of type `typ` (e.g. `CHALLENGE_HTTP_01`), if offered.
+    pub fn challenge(&self, typ: &str) -> Option<&Challenge> {
+        self.challenges.iter().find(|c| c.typ == typ)
+    }
+
+    pub fn is_wildcard(&self) -> bool {
+        self.wildcard.unwrap_or(false)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChallengeStatus {
+    Pending,
+    Processing,
+    Valid,
+    Invalid,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Challenge {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub url: String,
+    pub status: ChallengeStatus,
+    pub token: String,
+    pub validated: Option<String>,
+    pub error: Option<Problem>,
+}
+
+impl Challenge {
+    /// Where the key authorization must be served for `http-01`.
+    pub fn http01_path(&self) -> String {
+        format!("/.well-known/acme-challenge/{}", self.token)
+    }
+}
+
+/// The name of the TXT record for a `dns-01` challenge on `domain`.
+pub fn dns01_record_name(domain: &str) -> String {
+    format!("_acme-challenge.{}", domain.trim_start_matches("*."))
+}
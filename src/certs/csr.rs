@@ -1,97 +1,341 @@
-use openssl::asn1::Asn1Time;
+use std::fmt;
+use std::net::IpAddr;
+
+use openssl::asn1::{Asn1Object, Asn1Time};
 use openssl::bn::{BigNum, MsbOption};
 use openssl::error::ErrorStack;
 use openssl::hash::MessageDigest;
+use openssl::ec::{EcGroup, EcKey};
 use openssl::nid::Nid;
-use openssl::pkey::{PKey, Private};
+use openssl::pkey::{HasPublic, Id, PKey, PKeyRef, Private};
 use openssl::rsa::{Rsa};
 use openssl::stack::Stack;
-use openssl::x509::{X509, X509Name, X509Ref, X509Req};
+use openssl::x509::{GeneralName, X509, X509Name, X509NameRef, X509Ref, X509Req, X509ReqRef};
 use openssl::x509::extension::{AuthorityKeyIdentifier, BasicConstraints, KeyUsage, SubjectAlternativeName, SubjectKeyIdentifier};
 
+/// The private keys `generate_private_key` can create. ZeroSSL only issues
+/// for RSA and the two EC curves; EdDSA keys are for local CAs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum KeySpec {
+    #[default]
+    Rsa2048,
+    Rsa3072,
+    Rsa4096,
+    /// ECDSA on NIST P-256 (prime256v1)
+    EcP256,
+    /// ECDSA on NIST P-384 (secp384r1)
+    EcP384,
+    Ed25519,
+    Ed448,
+}
+
+pub fn generate_private_key(spec: KeySpec) -> Result<PKey<Private>, ErrorStack> {
+    match spec {
+        KeySpec::Rsa2048 => PKey::from_rsa(Rsa::generate(2048)?),
+        KeySpec::Rsa3072 => PKey::from_rsa(Rsa::generate(3072)?),
+        KeySpec::Rsa4096 => PKey::from_rsa(Rsa::generate(4096)?),
+        KeySpec::EcP256 => generate_ec_key(Nid::X9_62_PRIME256V1),
+        KeySpec::EcP384 => generate_ec_key(Nid::SECP384R1),
+        KeySpec::Ed25519 => PKey::generate_ed25519(),
+        KeySpec::Ed448 => PKey::generate_ed448(),
+    }
+}
+
+// EdDSA keys can only sign, so they must not claim keyEncipherment (RFC 8410)
+fn is_eddsa<T>(pkey: &PKeyRef<T>) -> bool {
+    matches!(pkey.id(), Id::ED25519 | Id::ED448)
+}
+
+fn generate_ec_key(curve: Nid) -> Result<PKey<Private>, ErrorStack> {
+    let group = EcGroup::from_curve_name(curve)?;
+    PKey::from_ec_key(EcKey::generate(&group)?)
+}
+
 pub fn generate_rsa_2048_priv_key() -> Result<PKey<Private>, ErrorStack> {
-    PKey::from_rsa(Rsa::generate(2048)?)
+    generate_private_key(KeySpec::Rsa2048)
 }
 
-pub struct Csr {
-    common_name: String,
-    alt_names: Option<Vec<String>>,
-    alt_name_is_ip: bool,
-    country: Option<String>,
-    org_name: Option<String>,
-    org_unit: Option<String>,
-    description: Option<String>,
+/// The digest to sign with `pkey`: SHA-256, for EC keys the one matching
+/// the curve size, and none for EdDSA, which hashes internally.
+pub fn signature_digest<T: HasPublic>(pkey: &PKeyRef<T>) -> Result<MessageDigest, ErrorStack> {
+    let digest = match pkey.id() {
+        Id::ED25519 | Id::ED448 => MessageDigest::null(),
+        Id::EC => match pkey.ec_key()?.group().curve_name() {
+            Some(Nid::SECP384R1) => MessageDigest::sha384(),
+            Some(Nid::SECP521R1) => MessageDigest::sha512(),
+            _ => MessageDigest::sha256(),
+        },
+        _ => MessageDigest::sha256(),
+    };
+
+    Ok(digest)
 }
 
-impl Csr {
-    pub fn default() -> Self {
-        Self {
-            common_name: "".to_string(),
-            alt_names: None,
-            alt_name_is_ip: false,
-            country: None,
-            org_name: None,
-            org_unit: None,
-            description: None,
+/// An entry of the subjectAltName extension.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SubjectAltName {
+    Dns(String),
+    Ip(IpAddr),
+    Email(String),
+    /// e.g. a SPIFFE ID (`spiffe://example.org/service`)
+    Uri(String),
+}
+
+impl SubjectAltName {
+    /// Guesses the type of `name`: an IP address, a URI (`scheme://...` or
+    /// `urn:...`), an email address (`user@host`) or else a DNS name.
+    pub fn detect(name: &str) -> Self {
+        let name = name.trim();
+
+        if let Ok(ip) = name.parse::<IpAddr>() {
+            SubjectAltName::Ip(ip)
+        } else if name.contains("://") || name.to_ascii_lowercase().starts_with("urn:") {
+            SubjectAltName::Uri(name.to_string())
+        } else if name.contains('@') {
+            SubjectAltName::Email(name.to_string())
+        } else {
+            SubjectAltName::Dns(name.to_string())
         }
     }
 
+    /// Whether ZeroSSL treats this as a certificate domain (DNS names and IPs).
+    pub fn is_domain(&self) -> bool {
+        matches!(self, SubjectAltName::Dns(_) | SubjectAltName::Ip(_))
+    }
+}
+
+impl fmt::Display for SubjectAltName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubjectAltName::Dns(name) | SubjectAltName::Email(name) | SubjectAltName::Uri(name) => f.write_str(name),
+            SubjectAltName::Ip(ip) => write!(f, "{}", ip),
+        }
+    }
+}
+
+impl From<&str> for SubjectAltName {
+    fn from(name: &str) -> Self {
+        SubjectAltName::detect(name)
+    }
+}
+
+impl From<String> for SubjectAltName {
+    fn from(name: String) -> Self {
+        SubjectAltName::detect(&name)
+    }
+}
+
+impl From<IpAddr> for SubjectAltName {
+    fn from(ip: IpAddr) -> Self {
+        SubjectAltName::Ip(ip)
+    }
+}
+
+// ISO 3166-1 alpha-2, for the countryName attribute
+pub static COUNTRY_CODES: [&str; 249] = [
+    "AD", "AE", "AF", "AG", "AI", "AL", "AM", "AO", "AQ", "AR", "AS", "AT", "AU", "AW", "AX", "AZ",
+    "BA", "BB", "BD", "BE", "BF", "BG", "BH", "BI", "BJ", "BL", "BM", "BN", "BO", "BQ", "BR", "BS",
+    "BT", "BV", "BW", "BY", "BZ", "CA", "CC", "CD", "CF", "CG", "CH", "CI", "CK", "CL", "CM", "CN",
+    "CO", "CR", "CU", "CV", "CW", "CX", "CY", "CZ", "DE", "DJ", "DK", "DM", "DO", "DZ", "EC", "EE",
+    "EG", "EH", "ER", "ES", "ET", "FI", "FJ", "FK", "FM", "FO", "FR", "GA", "GB", "GD", "GE", "GF",
+    "GG", "GH", "GI", "GL", "GM", "GN", "GP", "GQ", "GR", "GS", "GT", "GU", "GW", "GY", "HK", "HM",
+    "HN", "HR", "HT", "HU", "ID", "IE", "IL", "IM", "IN", "IO", "IQ", "IR", "IS", "IT", "JE", "JM",
+    "JO", "JP", "KE", "KG", "KH", "KI", "KM", "KN", "KP", "KR", "KW", "KY", "KZ", "LA", "LB", "LC",
+    "LI", "LK", "LR", "LS", "LT", "LU", "LV", "LY", "MA", "MC", "MD", "ME", "MF", "MG", "MH", "MK",
+    "ML", "MM", "MN", "MO", "MP", "MQ", "MR", "MS", "MT", "MU", "MV", "MW", "MX", "MY", "MZ", "NA",
+    "NC", "NE", "NF", "NG", "NI", "NL", "NO", "NP", "NR", "NU", "NZ", "OM", "PA", "PE", "PF", "PG",
+    "PH", "PK", "PL", "PM", "PN", "PR", "PS", "PT", "PW", "PY", "QA", "RE", "RO", "RS", "RU", "RW",
+    "SA", "SB", "SC", "SD", "SE", "SG", "SH", "SI", "SJ", "SK", "SL", "SM", "SN", "SO", "SR", "SS",
+    "ST", "SV", "SX", "SY", "SZ", "TC", "TD", "TF", "TG", "TH", "TJ", "TK", "TL", "TM", "TN", "TO",
+    "TR", "TT", "TV", "TW", "TZ", "UA", "UG", "UM", "US", "UY", "UZ", "VA", "VC", "VE", "VG", "VI",
+    "VN", "VU", "WF", "WS", "YE", "YT", "ZA", "ZM", "ZW",
+];
+
+#[derive(Default)]
+pub struct Csr {
+    // Subject attributes in RDN order, common name included
+    subject: Vec<(Nid, String)>,
+    alt_names: Vec<SubjectAltName>,
+}
+
+impl Csr {
     pub fn new(common_name: String) -> Self {
         Self {
-            common_name,
+            subject: vec![(Nid::COMMONNAME, common_name)],
             ..Csr::default()
         }
     }
 
-    pub fn with_alt_names(&mut self, alt_names: Vec<String>, is_ip: bool) -> &mut Self {
-        self.alt_names = Some(alt_names);
-        self.alt_name_is_ip = is_ip;
+    /// The subject and SANs requested by `req`, e.g. to submit a CSR made
+    /// elsewhere.
+    pub fn from_x509_req(req: &X509ReqRef) -> Result<Self, ErrorStack> {
+        Self::from_parts(req.subject_name(), req_subject_alt_names(req)?)
+    }
+
+    /// The subject and SANs of `cert`, e.g. to renew it.
+    pub fn from_x509(cert: &X509Ref) -> Result<Self, ErrorStack> {
+        Self::from_parts(cert.subject_name(), cert.subject_alt_names())
+    }
+
+    fn from_parts(subject: &X509NameRef, alt_names: Option<Stack<GeneralName>>) -> Result<Self, ErrorStack> {
+        let mut csr = Csr::default();
+
+        for alt_name in alt_names.iter().flatten() {
+            if let Some(dns) = alt_name.dnsname() {
+                csr.alt_names.push(SubjectAltName::Dns(dns.to_string()));
+            } else if let Some(ip) = alt_name.ipaddress().and_then(ip_from_bytes) {
+                csr.alt_names.push(SubjectAltName::Ip(ip));
+            } else if let Some(email) = alt_name.email() {
+                csr.alt_names.push(SubjectAltName::Email(email.to_string()));
+            } else if let Some(uri) = alt_name.uri() {
+                csr.alt_names.push(SubjectAltName::Uri(uri.to_string()));
+            }
+        }
+
+        for entry in subject.entries() {
+            let nid = entry.object().nid();
+            // Attributes OpenSSL doesn't know couldn't be written back
+            if nid != Nid::UNDEF {
+                csr.subject.push((nid, entry.data().as_utf8()?.to_string()));
+            }
+        }
+
+        // CSRs with only SANs (or an empty CN) are common, ZeroSSL then takes the first domain
+        let common_name = csr.attribute(Nid::COMMONNAME);
+        if common_name.as_deref().unwrap_or("").is_empty() {
+            let first_domain = csr.alt_names.iter()
+                .find(|n| n.is_domain())
+                .map(|n| n.to_string())
+                .unwrap_or_default();
+            match common_name {
+                Some(_) => { csr.with_attribute(Nid::COMMONNAME, first_domain); }
+                None => csr.subject.insert(0, (Nid::COMMONNAME, first_domain)),
+            }
+        }
+
+        Ok(csr)
+    }
+
+    /// Sets the single value of `nid`, in place of the first existing one.
+    pub fn with_attribute(&mut self, nid: Nid, value: String) -> &mut Self {
+        let mut value = Some(value);
+        self.subject.retain_mut(|(n, v)| {
+            if *n != nid {
+                return true;
+            }
+            match value.take() {
+                Some(value) => {
+                    *v = value;
+                    true
+                }
+                None => false,
+            }
+        });
+
+        if let Some(value) = value {
+            self.subject.push((nid, value));
+        }
+        self
+    }
+
+    /// Appends a value of `nid`, keeping existing ones (e.g. several OUs).
+    pub fn add_attribute(&mut self, nid: Nid, value: String) -> &mut Self {
+        self.subject.push((nid, value));
         self
     }
 
-    pub fn with_country(&mut self, country: String) -> &mut Self {
-        self.country = Some(country);
+    /// Replaces the SANs. Plain strings are typed with `SubjectAltName::detect`.
+    pub fn with_alt_names<I, T>(&mut self, alt_names: I) -> &mut Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<SubjectAltName>,
+    {
+        self.alt_names = alt_names.into_iter().map(Into::into).collect();
         self
     }
 
-    pub fn with_org_name(&mut self, org_name: String) -> &mut Self {
-        self.org_name = Some(org_name);
+    pub fn with_alt_name<T: Into<SubjectAltName>>(&mut self, alt_name: T) -> &mut Self {
+        self.alt_names.push(alt_name.into());
         self
     }
 
+    /// An ISO 3166-1 alpha-2 code, checked by `validate`.
+    pub fn with_country(&mut self, country: String) -> &mut Self {
+        self.with_attribute(Nid::COUNTRYNAME, country.to_ascii_uppercase())
+    }
+
+    pub fn with_state(&mut self, state: String) -> &mut Self {
+        self.with_attribute(Nid::STATEORPROVINCENAME, state)
+    }
+
+    pub fn with_locality(&mut self, locality: String) -> &mut Self {
+        self.with_attribute(Nid::LOCALITYNAME, locality)
+    }
+
+    pub fn with_org_name(&mut self, org_name: String) -> &mut Self {
+        self.with_attribute(Nid::ORGANIZATIONNAME, org_name)
+    }
+
     pub fn with_org_unit(&mut self, org_unit: String) -> &mut Self {
-        self.org_unit = Some(org_unit);
+        self.with_attribute(Nid::ORGANIZATIONALUNITNAME, org_unit)
+    }
+
+    /// Replaces the OUs, appended in order.
+    pub fn with_org_units(&mut self, org_units: Vec<String>) -> &mut Self {
+        self.subject.retain(|(n, _)| *n != Nid::ORGANIZATIONALUNITNAME);
+        for org_unit in org_units {
+            self.add_attribute(Nid::ORGANIZATIONALUNITNAME, org_unit);
+        }
         self
     }
 
+    pub fn with_email(&mut self, email: String) -> &mut Self {
+        self.with_attribute(Nid::PKCS9_EMAILADDRESS, email)
+    }
+
+    pub fn with_serial_number(&mut self, serial_number: String) -> &mut Self {
+        self.with_attribute(Nid::SERIALNUMBER, serial_number)
+    }
+
     pub fn with_description(&mut self, description: String) -> &mut Self {
-        self.description = Some(description);
-        self
+        self.with_attribute(Nid::DESCRIPTION, description)
     }
 
     // Accessors
     pub fn common_name(&self) -> String {
-        self.common_name.clone()
+        self.attribute(Nid::COMMONNAME).unwrap_or_default()
     }
 
-    pub fn alt_names(&self) -> Option<Vec<String>> {
-        self.alt_names.clone()
+    /// The subject attributes in RDN order.
+    pub fn attributes(&self) -> Vec<(Nid, String)> {
+        self.subject.clone()
     }
 
-    pub fn alt_name_is_ip(&self) -> bool {
-        self.alt_name_is_ip
+    /// The first value of `nid`.
+    pub fn attribute(&self, nid: Nid) -> Option<String> {
+        self.subject.iter()
+            .find(|(n, _)| *n == nid)
+            .map(|(_, v)| v.clone())
     }
 
+    pub fn alt_names(&self) -> Vec<SubjectAltName> {
+        self.alt_names.clone()
+    }
+
+    /// The certificate domains ZeroSSL expects: the common name followed by
+    /// the DNS and IP SANs. Email and URI SANs are not domains.
     pub fn all_names(&self) -> Vec<String> {
         let mut names: Vec<String> = Vec::new();
 
-        names.push(self.common_name.clone());
+        let common_name = self.common_name();
+        if !common_name.is_empty() {
+            names.push(common_name);
+        }
 
-        if let Some(alt_names) = self.alt_names.as_ref() {
-            for name in alt_names.iter() {
-                if !name.eq_ignore_ascii_case(self.common_name.as_ref()) {
-                    names.push(name.clone());
-                }
+        for name in self.alt_names.iter().filter(|n| n.is_domain()) {
+            let name = name.to_string();
+            if !names.iter().any(|n| n.eq_ignore_ascii_case(&name)) {
+                names.push(name);
             }
         }
 
@@ -99,36 +343,74 @@ impl Csr {
     }
 
     pub fn country(&self) -> Option<String> {
-        self.country.clone()
+        self.attribute(Nid::COUNTRYNAME)
+    }
+
+    pub fn state(&self) -> Option<String> {
+        self.attribute(Nid::STATEORPROVINCENAME)
+    }
+
+    pub fn locality(&self) -> Option<String> {
+        self.attribute(Nid::LOCALITYNAME)
     }
 
     pub fn org_name(&self) -> Option<String> {
-        self.org_name.clone()
+        self.attribute(Nid::ORGANIZATIONNAME)
     }
 
     pub fn org_unit(&self) -> Option<String> {
-        self.org_unit.clone()
+        self.attribute(Nid::ORGANIZATIONALUNITNAME)
+    }
+
+    pub fn org_units(&self) -> Vec<String> {
+        self.subject.iter()
+            .filter(|(n, _)| *n == Nid::ORGANIZATIONALUNITNAME)
+            .map(|(_, v)| v.clone())
+            .collect()
+    }
+
+    pub fn email(&self) -> Option<String> {
+        self.attribute(Nid::PKCS9_EMAILADDRESS)
+    }
+
+    pub fn serial_number(&self) -> Option<String> {
+        self.attribute(Nid::SERIALNUMBER)
     }
 
     pub fn description(&self) -> Option<String> {
-        self.description.clone()
+        self.attribute(Nid::DESCRIPTION)
+    }
+
+    /// Checks what OpenSSL accepts but ZeroSSL would reject: the country
+    /// must be a 2-letter ISO 3166 code.
+    pub fn validate(&self) -> crate::error::Result<()> {
+        for (_, country) in self.subject.iter().filter(|(n, _)| *n == Nid::COUNTRYNAME) {
+            if !COUNTRY_CODES.contains(&country.as_str()) {
+                return Err(crate::error::validation(
+                    format!("country must be a 2-letter ISO 3166 code, got {:?}", country)));
+            }
+        }
+
+        Ok(())
     }
 
     // Util
+    // A single extension holding every name, as an extension may only appear once
     pub fn subject_alt_names(&self) -> Vec<SubjectAlternativeName> {
         let mut res: Vec<SubjectAlternativeName> = Vec::new();
 
-        if let Some(alt_names) = self.alt_names.as_ref() {
-            for alt in alt_names {
-                let mut subject_alt_name = SubjectAlternativeName::new();
-                if self.alt_name_is_ip {
-                    subject_alt_name.ip(alt);
-                } else {
-                    subject_alt_name.dns(alt);
-                }
-
-                res.push(subject_alt_name);
+        if !self.alt_names.is_empty() {
+            let mut subject_alt_name = SubjectAlternativeName::new();
+            for alt in self.alt_names.iter() {
+                match alt {
+                    SubjectAltName::Dns(name) => subject_alt_name.dns(name),
+                    SubjectAltName::Ip(ip) => subject_alt_name.ip(&ip.to_string()),
+                    SubjectAltName::Email(email) => subject_alt_name.email(email),
+                    SubjectAltName::Uri(uri) => subject_alt_name.uri(uri),
+                };
             }
+
+            res.push(subject_alt_name);
         }
 
         res
@@ -137,19 +419,8 @@ impl Csr {
 
 pub fn extract_name_from_csr(csr: &Csr) -> Result<X509Name, ErrorStack> {
     let mut name = X509Name::builder()?;
-    name.append_entry_by_nid(Nid::COMMONNAME, csr.common_name.as_str())?;
-
-    if let Some(country) = csr.country.as_ref() {
-        name.append_entry_by_nid(Nid::COUNTRYNAME, country.as_str())?;
-    }
-    if let Some(org_name) = csr.org_name.as_ref() {
-        name.append_entry_by_nid(Nid::ORGANIZATIONNAME, org_name.as_str())?;
-    }
-    if let Some(org_unit) = csr.org_unit.as_ref() {
-        name.append_entry_by_nid(Nid::ORGANIZATIONALUNITNAME, org_unit.as_str())?;
-    }
-    if let Some(description) = csr.description.as_ref() {
-        name.append_entry_by_nid(Nid::DESCRIPTION, description.as_str())?;
+    for (nid, value) in csr.subject.iter() {
+        name.append_entry_by_nid(*nid, value.as_str())?;
     }
 
     Ok(name.build())
@@ -164,14 +435,15 @@ pub fn generate_csr(
     let mut builder = X509Req::builder()?;
     builder.set_version(2)?;
     builder.set_subject_name(&name)?;
-    builder.set_pubkey(&pkey)?;
+    builder.set_pubkey(pkey)?;
 
     let mut extensions = Stack::new()?;
-    let key_usage = KeyUsage::new()
-        .digital_signature()
-        .key_encipherment()
-        .build()?;
-    extensions.push(key_usage).unwrap();
+    let mut key_usage = KeyUsage::new();
+    key_usage.digital_signature();
+    if !is_eddsa(pkey) {
+        key_usage.key_encipherment();
+    }
+    extensions.push(key_usage.build()?).unwrap();
 
     for subject_alt_name in csr.subject_alt_names() {
         extensions.push(subject_alt_name
@@ -180,7 +452,7 @@ pub fn generate_csr(
 
     builder.add_extensions(&extensions)?;
 
-    builder.sign(&pkey, MessageDigest::sha256())?;
+    builder.sign(pkey, signature_digest(pkey)?)?;
 
     Ok(builder.build())
 }
@@ -202,7 +474,7 @@ pub fn generate_ca(
     builder.set_serial_number(&serial_number)?;
     builder.set_subject_name(&name)?;
     builder.set_issuer_name(&name)?;
-    builder.set_pubkey(&pkey)?;
+    builder.set_pubkey(pkey)?;
     let not_before = Asn1Time::days_from_now(0)?;
     builder.set_not_before(&not_before)?;
     let not_after = Asn1Time::days_from_now(days.unwrap_or(365))?;
@@ -221,15 +493,35 @@ pub fn generate_ca(
         SubjectKeyIdentifier::new().build(&builder.x509v3_context(None, None))?;
     builder.append_extension(subject_key_identifier)?;
 
-    builder.sign(&pkey, MessageDigest::sha256())?;
+    builder.sign(pkey, signature_digest(pkey)?)?;
 
     Ok(builder.build())
 }
 
+/// Issues a certificate for `pkey` from `ca`, signed with the CA's private
+/// key `ca_key`, which has to match `ca`.
 pub fn generate_ca_signed_cert(
     pkey: &PKey<Private>,
     csr: &Csr,
     ca: &X509Ref,
+    ca_key: &PKey<Private>,
+    days: Option<u32>
+) -> crate::error::Result<X509> {
+    let ca_public_key = ca.public_key()
+        .map_err(|e| crate::error::openssl(e, Some("failed to read the CA public key".to_string())))?;
+    if !ca_public_key.public_eq(ca_key) {
+        return Err(crate::error::validation("ca_key is not the private key of ca"));
+    }
+
+    sign_with_ca(pkey, csr, ca, ca_key, days)
+        .map_err(|e| crate::error::openssl(e, Some("failed to sign certificate".to_string())))
+}
+
+fn sign_with_ca(
+    pkey: &PKey<Private>,
+    csr: &Csr,
+    ca: &X509Ref,
+    ca_key: &PKey<Private>,
     days: Option<u32>
 ) -> Result<X509, ErrorStack>{
     let req = generate_csr(pkey, csr)?;
@@ -244,7 +536,7 @@ pub fn generate_ca_signed_cert(
     builder.set_serial_number(&serial_number)?;
     builder.set_subject_name(req.subject_name())?;
     builder.set_issuer_name(ca.subject_name())?;
-    builder.set_pubkey(&pkey)?;
+    builder.set_pubkey(pkey)?;
     let not_before = Asn1Time::days_from_now(0)?;
     builder.set_not_before(&not_before)?;
     let not_after = Asn1Time::days_from_now(days.unwrap_or(365))?;
@@ -252,14 +544,14 @@ pub fn generate_ca_signed_cert(
 
     builder.append_extension(BasicConstraints::new().build()?)?;
 
-    builder.append_extension(
-        KeyUsage::new()
-            .critical()
-            .non_repudiation()
-            .digital_signature()
-            .key_encipherment()
-            .build()?,
-    )?;
+    let mut key_usage = KeyUsage::new();
+    key_usage.critical()
+        .non_repudiation()
+        .digital_signature();
+    if !is_eddsa(pkey) {
+        key_usage.key_encipherment();
+    }
+    builder.append_extension(key_usage.build()?)?;
 
     let subject_key_identifier =
         SubjectKeyIdentifier::new().build(&builder.x509v3_context(Some(ca), None))?;
@@ -276,14 +568,279 @@ pub fn generate_ca_signed_cert(
             .build(&builder.x509v3_context(Some(ca), None))?)?;
     }
 
-    builder.sign(pkey, MessageDigest::sha256())?;
+    builder.sign(ca_key, signature_digest(ca_key)?)?;
 
     Ok(builder.build())
 }
 
+// Lint
+
+// TLDs that public CAs (and therefore ZeroSSL) refuse to issue for
+pub static INTERNAL_TLDS: [&str; 12] = ["local", "localhost", "internal", "intranet", "lan", "home", "corp",
+    "private", "localdomain", "test", "invalid", "example"];
+
+pub const MIN_RSA_BITS: u32 = 2048;
+
+/// A problem with a CSR that would make ZeroSSL reject it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CsrFinding {
+    InvalidSignature,
+    UnsupportedKeyType(String),
+    KeyTooSmall { bits: u32, min: u32 },
+    UnsupportedCurve(String),
+    WeakSignatureAlgorithm(String),
+    UnknownSignatureAlgorithm,
+    MissingCommonName,
+    CommonNameNotInSans(String),
+    InternalName(String),
+}
+
+impl fmt::Display for CsrFinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CsrFinding::InvalidSignature => f.write_str("signature does not match the public key"),
+            CsrFinding::UnsupportedKeyType(typ) => write!(f, "unsupported key type: {}", typ),
+            CsrFinding::KeyTooSmall { bits, min } => write!(f, "key too small: {} bits (min {})", bits, min),
+            CsrFinding::UnsupportedCurve(curve) => write!(f, "unsupported curve: {}", curve),
+            CsrFinding::WeakSignatureAlgorithm(alg) => write!(f, "weak signature algorithm: {}", alg),
+            CsrFinding::UnknownSignatureAlgorithm => f.write_str("unknown signature algorithm"),
+            CsrFinding::MissingCommonName => f.write_str("missing common name"),
+            CsrFinding::CommonNameNotInSans(cn) => write!(f, "common name not in subject alt names: {}", cn),
+            CsrFinding::InternalName(name) => write!(f, "internal name: {}", name),
+        }
+    }
+}
+
+/// Checks `req` offline for what ZeroSSL enforces. An empty list means no problems were found.
+pub fn lint_csr(req: &X509Req) -> Result<Vec<CsrFinding>, ErrorStack> {
+    let mut findings: Vec<CsrFinding> = Vec::new();
+
+    // Key
+    let pubkey = req.public_key()?;
+    if !req.verify(&pubkey)? {
+        findings.push(CsrFinding::InvalidSignature);
+    }
+
+    let mut key_supported = true;
+    match pubkey.id() {
+        Id::RSA => {
+            if pubkey.bits() < MIN_RSA_BITS {
+                findings.push(CsrFinding::KeyTooSmall { bits: pubkey.bits(), min: MIN_RSA_BITS });
+            }
+        }
+        Id::EC => {
+            let curve = pubkey.ec_key()?.group().curve_name();
+            match curve {
+                Some(Nid::X9_62_PRIME256V1) | Some(Nid::SECP384R1) => {}
+                Some(nid) => findings.push(CsrFinding::UnsupportedCurve(
+                    nid.short_name().unwrap_or("unknown").to_string())),
+                None => findings.push(CsrFinding::UnsupportedCurve("unnamed".to_string())),
+            }
+        }
+        other => {
+            key_supported = false;
+            findings.push(CsrFinding::UnsupportedKeyType(key_type_name(other)));
+        }
+    }
+
+    // Signature algorithm, implied by the key type when that's already unsupported (e.g. EdDSA)
+    match req_signature_algorithm(req)? {
+        _ if !key_supported => {}
+        Some(Nid::SHA256WITHRSAENCRYPTION) | Some(Nid::SHA384WITHRSAENCRYPTION) | Some(Nid::SHA512WITHRSAENCRYPTION)
+        | Some(Nid::ECDSA_WITH_SHA256) | Some(Nid::ECDSA_WITH_SHA384) | Some(Nid::ECDSA_WITH_SHA512) => {}
+        Some(nid) => findings.push(CsrFinding::WeakSignatureAlgorithm(
+            nid.long_name().unwrap_or("unknown").to_string())),
+        None => findings.push(CsrFinding::UnknownSignatureAlgorithm),
+    }
+
+    // Names
+    let common_name = req.subject_name().entries_by_nid(Nid::COMMONNAME).next()
+        .and_then(|e| e.data().as_utf8().ok())
+        .map(|cn| cn.to_string());
+
+    let mut sans: Vec<String> = Vec::new();
+    if let Some(alt_names) = req_subject_alt_names(req)? {
+        for alt_name in alt_names.iter() {
+            if let Some(dns) = alt_name.dnsname() {
+                sans.push(dns.to_string());
+            } else if let Some(ip) = alt_name.ipaddress().and_then(ip_from_bytes) {
+                sans.push(ip.to_string());
+            }
+        }
+    }
+
+    match common_name.as_ref() {
+        None => findings.push(CsrFinding::MissingCommonName),
+        Some(cn) => {
+            if !sans.is_empty() && !sans.iter().any(|s| s.eq_ignore_ascii_case(cn)) {
+                findings.push(CsrFinding::CommonNameNotInSans(cn.clone()));
+            }
+        }
+    }
+
+    let mut names: Vec<String> = common_name.into_iter().collect();
+    for san in sans {
+        if !names.iter().any(|n| n.eq_ignore_ascii_case(&san)) {
+            names.push(san);
+        }
+    }
+    for name in names {
+        if is_internal_name(&name) {
+            findings.push(CsrFinding::InternalName(name));
+        }
+    }
+
+    Ok(findings)
+}
+
+fn is_internal_name(name: &str) -> bool {
+    if let Ok(ip) = name.parse::<IpAddr>() {
+        return match ip {
+            IpAddr::V4(ip) => ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_unspecified(),
+            IpAddr::V6(ip) => ip.is_loopback() || ip.is_unspecified()
+                || (ip.segments()[0] & 0xfe00) == 0xfc00
+                || (ip.segments()[0] & 0xffc0) == 0xfe80,
+        };
+    }
+
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    match name.rsplit_once('.') {
+        Some((_, tld)) => INTERNAL_TLDS.contains(&tld),
+        None => true,
+    }
+}
+
+fn key_type_name(id: Id) -> String {
+    match id {
+        Id::DSA => "DSA".to_string(),
+        Id::DH => "DH".to_string(),
+        Id::ED25519 => "Ed25519".to_string(),
+        Id::ED448 => "Ed448".to_string(),
+        other => format!("{}", other.as_raw()),
+    }
+}
+
+pub(crate) fn ip_from_bytes(bytes: &[u8]) -> Option<IpAddr> {
+    match bytes.len() {
+        4 => <[u8; 4]>::try_from(bytes).ok().map(IpAddr::from),
+        16 => <[u8; 16]>::try_from(bytes).ok().map(IpAddr::from),
+        _ => None,
+    }
+}
+
+/// The SANs requested by `req`. `X509Req` has no accessor for them, so the
+/// extensions are copied onto a throwaway `X509` and read back from there.
+pub(crate) fn req_subject_alt_names(req: &X509ReqRef) -> Result<Option<Stack<GeneralName>>, ErrorStack> {
+    let extensions = match req.extensions() {
+        Ok(extensions) => extensions,
+        // No extension attributes at all
+        Err(_) => return Ok(None),
+    };
+
+    let mut builder = X509::builder()?;
+    for extension in extensions {
+        builder.append_extension(extension)?;
+    }
+
+    Ok(builder.build().subject_alt_names())
+}
+
+// Reads the signatureAlgorithm OID from the CertificationRequest DER
+fn req_signature_algorithm(req: &X509ReqRef) -> Result<Option<Nid>, ErrorStack> {
+    let der = req.to_der()?;
+
+    let oid = der_read(&der, 0x30)
+        .and_then(|(body, _)| der_read(body, 0x30).map(|(_, rest)| rest))
+        .and_then(|rest| der_read(rest, 0x30))
+        .and_then(|(alg, _)| der_read(alg, 0x06))
+        .and_then(|(oid, _)| oid_to_string(oid));
+
+    Ok(oid
+        .and_then(|oid| Asn1Object::from_str(&oid).ok())
+        .map(|obj| obj.nid())
+        .filter(|nid| *nid != Nid::UNDEF))
+}
+
+// Returns the contents of the element with `tag` at the start of `data` and what follows it
+fn der_read(data: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+    if data.len() < 2 || data[0] != tag {
+        return None;
+    }
+
+    let (len, offset) = match data[1] {
+        n if n < 0x80 => (n as usize, 2),
+        n => {
+            let count = (n & 0x7f) as usize;
+            if count == 0 || count > 4 || data.len() < 2 + count {
+                return None;
+            }
+            let len = data[2..2 + count].iter().fold(0usize, |acc, b| (acc << 8) | *b as usize);
+            (len, 2 + count)
+        }
+    };
+
+    if data.len() < offset + len {
+        return None;
+    }
+
+    Some((&data[offset..offset + len], &data[offset + len..]))
+}
+
+fn oid_to_string(oid: &[u8]) -> Option<String> {
+    let mut subidentifiers: Vec<u64> = Vec::new();
+    let mut value: u64 = 0;
+    for b in oid {
+        if value > u64::MAX >> 7 {
+            return None;
+        }
+        value = (value << 7) | u64::from(b & 0x7f);
+        if b & 0x80 == 0 {
+            subidentifiers.push(value);
+            value = 0;
+        }
+    }
+    // Empty, or the last subidentifier is cut off
+    if subidentifiers.is_empty() || oid.last()? & 0x80 != 0 {
+        return None;
+    }
+
+    // The first subidentifier packs two arcs, the second one unbounded under arc 2
+    let first = subidentifiers[0];
+    let mut parts = match first {
+        0..=39 => vec![0, first],
+        40..=79 => vec![1, first - 40],
+        _ => vec![2, first - 80],
+    };
+    parts.extend_from_slice(&subidentifiers[1..]);
+
+    Some(parts.iter().map(|p| p.to_string()).collect::<Vec<String>>().join("."))
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::certs::csr::{Csr, generate_csr, generate_rsa_2048_priv_key};
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::{Id, PKey};
+    use openssl::rsa::Rsa;
+    use openssl::x509::X509Req;
+
+    use openssl::x509::store::X509StoreBuilder;
+    use openssl::x509::X509StoreContext;
+
+    use crate::certs::csr::{Csr, CsrFinding, extract_name_from_csr, generate_ca, generate_ca_signed_cert, generate_csr,
+                            generate_private_key, generate_rsa_2048_priv_key, KeySpec, lint_csr, oid_to_string,
+                            req_signature_algorithm, req_subject_alt_names, SubjectAltName};
+
+    fn assert_same_csr(a: &Csr, b: &Csr) {
+        assert_eq!(a.common_name(), b.common_name());
+        assert_eq!(a.alt_names(), b.alt_names());
+        assert_eq!(a.all_names(), b.all_names());
+        assert_eq!(a.country(), b.country());
+        assert_eq!(a.org_name(), b.org_name());
+        assert_eq!(a.org_unit(), b.org_unit());
+        assert_eq!(a.description(), b.description());
+    }
 
     #[test]
     fn generate_rsa_2048_priv_key_test() {
@@ -291,6 +848,93 @@ mod tests {
     }
 
     #[test]
+    fn generate_private_key_test() {
+        let specs = vec![
+            (KeySpec::Rsa2048, Nid::SHA256WITHRSAENCRYPTION, 2048),
+            (KeySpec::Rsa3072, Nid::SHA256WITHRSAENCRYPTION, 3072),
+            (KeySpec::Rsa4096, Nid::SHA256WITHRSAENCRYPTION, 4096),
+            (KeySpec::EcP256, Nid::ECDSA_WITH_SHA256, 256),
+            (KeySpec::EcP384, Nid::ECDSA_WITH_SHA384, 384),
+        ];
+
+        for (spec, signature_algorithm, bits) in specs {
+            let pkey = generate_private_key(spec).unwrap();
+            assert_eq!(pkey.bits(), bits, "{:?}", spec);
+
+            let mut csr = Csr::new("example.com".to_string());
+            let csr = csr.with_alt_names(vec!["example.com".to_string(), "www.example.com".to_string()]);
+
+            let req = generate_csr(&pkey, csr).unwrap();
+            let req = X509Req::from_pem(&req.to_pem().unwrap()).unwrap();
+            assert!(req.verify(&req.public_key().unwrap()).unwrap(), "{:?}", spec);
+            assert!(req.public_key().unwrap().public_eq(&pkey), "{:?}", spec);
+            assert_eq!(req_signature_algorithm(&req).unwrap(), Some(signature_algorithm), "{:?}", spec);
+            assert_eq!(lint_csr(&req).unwrap(), vec![], "{:?}", spec);
+
+            let ca = generate_ca(&pkey, csr, None).unwrap();
+            assert!(ca.verify(&pkey).unwrap(), "{:?}", spec);
+            assert_eq!(ca.signature_algorithm().object().nid(), signature_algorithm, "{:?}", spec);
+        }
+    }
+
+    #[test]
+    fn generate_eddsa_test() {
+        // The EdDSA key ids are also their signature algorithm NIDs
+        for (spec, id) in [(KeySpec::Ed25519, Id::ED25519), (KeySpec::Ed448, Id::ED448)] {
+            let signature_algorithm = Nid::from_raw(id.as_raw());
+            let pkey = generate_private_key(spec).unwrap();
+
+            let req = generate_csr(&pkey, &Csr::new("node1.mesh.internal".to_string())).unwrap();
+            assert!(req.verify(&pkey).unwrap(), "{:?}", spec);
+            assert_eq!(req_signature_algorithm(&req).unwrap(), Some(signature_algorithm), "{:?}", spec);
+
+            let ca = generate_ca(&pkey, &Csr::new("Mesh CA".to_string()), None).unwrap();
+            assert!(ca.verify(&pkey).unwrap(), "{:?}", spec);
+            assert_eq!(ca.signature_algorithm().object().nid(), signature_algorithm, "{:?}", spec);
+        }
+    }
+
+    #[test]
+    fn generate_ca_signed_cert_mixed_test() {
+        let chains = vec![
+            (KeySpec::Rsa2048, KeySpec::Ed25519),
+            (KeySpec::Ed25519, KeySpec::Rsa2048),
+            (KeySpec::Ed448, KeySpec::EcP256),
+            (KeySpec::EcP384, KeySpec::Ed448),
+        ];
+
+        for (ca_spec, leaf_spec) in chains {
+            let ca_key = generate_private_key(ca_spec).unwrap();
+            let ca = generate_ca(&ca_key, &Csr::new("Mesh CA".to_string()), None).unwrap();
+
+            let pkey = generate_private_key(leaf_spec).unwrap();
+            let mut csr = Csr::new("node1.mesh.internal".to_string());
+            let csr = csr.with_alt_names(vec!["node1.mesh.internal".to_string()]);
+            let cert = generate_ca_signed_cert(&pkey, csr, &ca, &ca_key, Some(30)).unwrap();
+
+            assert!(cert.public_key().unwrap().public_eq(&pkey), "{:?} -> {:?}", ca_spec, leaf_spec);
+            assert!(cert.verify(&ca_key).unwrap(), "{:?} -> {:?}", ca_spec, leaf_spec);
+
+            let mut store = X509StoreBuilder::new().unwrap();
+            store.add_cert(ca.clone()).unwrap();
+            let store = store.build();
+            let chain = openssl::stack::Stack::new().unwrap();
+            let mut ctx = X509StoreContext::new().unwrap();
+            let verified = ctx.init(&store, &cert, &chain, |c| {
+                let ok = c.verify_cert()?;
+                assert_eq!(c.error(), openssl::x509::X509VerifyResult::OK, "{:?} -> {:?}", ca_spec, leaf_spec);
+                Ok(ok)
+            }).unwrap();
+            assert!(verified, "{:?} -> {:?}", ca_spec, leaf_spec);
+
+            // Signing with a key that isn't the CA's would produce an unverifiable chain
+            let err = generate_ca_signed_cert(&pkey, csr, &ca, &pkey, Some(30)).unwrap_err();
+            assert!(err.is_validation(), "{:?} -> {:?}", ca_spec, leaf_spec);
+        }
+    }
+
+    #[test]
+    #[allow(clippy::vec_init_then_push, clippy::needless_borrow, clippy::len_zero)]
     fn generate_csr_ip_test() {
         let pkey = generate_rsa_2048_priv_key().unwrap();
 
@@ -299,7 +943,7 @@ mod tests {
         alt_names.push("172.33.33.34".to_string());
 
         let mut csr = Csr::new("172.33.33.33".to_string());
-        let csr = csr.with_alt_names(alt_names, true)
+        let csr = csr.with_alt_names(alt_names)
             .with_country("AU".to_string())
             .with_org_name("Lit".to_string())
             .with_org_unit("Node Devs".to_string());
@@ -313,6 +957,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::vec_init_then_push, clippy::needless_borrow, clippy::len_zero)]
     fn generate_csr_dns_test() {
         let pkey = generate_rsa_2048_priv_key().unwrap();
 
@@ -321,7 +966,7 @@ mod tests {
         alt_names.push("www2.example.com".to_string());
 
         let mut csr = Csr::new("example.com".to_string());
-        let csr = csr.with_alt_names(alt_names, false)
+        let csr = csr.with_alt_names(alt_names)
             .with_country("AU".to_string())
             .with_org_name("Lit".to_string())
             .with_org_unit("Node Devs".to_string());
@@ -333,4 +978,197 @@ mod tests {
 
         assert!(csr_pem.len() > 0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn subject_alt_name_detect_test() {
+        assert_eq!(SubjectAltName::from("node1.example.com"), SubjectAltName::Dns("node1.example.com".to_string()));
+        assert_eq!(SubjectAltName::from("*.example.com"), SubjectAltName::Dns("*.example.com".to_string()));
+        assert_eq!(SubjectAltName::from("10.0.0.5"), SubjectAltName::Ip("10.0.0.5".parse().unwrap()));
+        assert_eq!(SubjectAltName::from("::1"), SubjectAltName::Ip("::1".parse().unwrap()));
+        assert_eq!(SubjectAltName::from("ops@example.com"), SubjectAltName::Email("ops@example.com".to_string()));
+        assert_eq!(SubjectAltName::from("spiffe://example.org/ns/prod/sa/api"),
+                   SubjectAltName::Uri("spiffe://example.org/ns/prod/sa/api".to_string()));
+        assert_eq!(SubjectAltName::from("urn:uuid:f81d4fae-7dec-11d0-a765-00a0c91e6bf6"),
+                   SubjectAltName::Uri("urn:uuid:f81d4fae-7dec-11d0-a765-00a0c91e6bf6".to_string()));
+    }
+
+    #[test]
+    fn mixed_alt_names_test() {
+        let mut csr = Csr::new("node1.example.com".to_string());
+        let csr = csr.with_alt_names(vec!["node1.example.com", "10.0.0.5", "ops@example.com"])
+            .with_alt_name(SubjectAltName::Uri("spiffe://example.com/node1".to_string()));
+
+        assert_eq!(csr.all_names(), vec!["node1.example.com".to_string(), "10.0.0.5".to_string()]);
+
+        let ca_key = generate_rsa_2048_priv_key().unwrap();
+        let ca = generate_ca(&ca_key, &Csr::new("Internal CA".to_string()), None).unwrap();
+        let pkey = generate_private_key(KeySpec::EcP256).unwrap();
+        let cert = generate_ca_signed_cert(&pkey, csr, &ca, &ca_key, None).unwrap();
+
+        let sans = cert.subject_alt_names().unwrap();
+        assert_eq!(sans.len(), 4);
+        assert_eq!(sans[0].dnsname(), Some("node1.example.com"));
+        assert_eq!(sans[1].ipaddress(), Some([10u8, 0, 0, 5].as_slice()));
+        assert_eq!(sans[2].email(), Some("ops@example.com"));
+        assert_eq!(sans[3].uri(), Some("spiffe://example.com/node1"));
+
+        let req = generate_csr(&pkey, csr).unwrap();
+        assert_eq!(req_subject_alt_names(&req).unwrap().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn from_x509_req_test() {
+        let pkey = generate_private_key(KeySpec::EcP256).unwrap();
+        let mut csr = Csr::new("node1.example.com".to_string());
+        let csr = csr.with_alt_names(vec!["node1.example.com", "10.0.0.5", "ops@example.com", "spiffe://example.com/node1"])
+            .with_country("AU".to_string())
+            .with_org_name("Lit".to_string())
+            .with_org_unit("Node Devs".to_string())
+            .with_description("node one".to_string());
+
+        let req = generate_csr(&pkey, csr).unwrap();
+        let req = X509Req::from_pem(&req.to_pem().unwrap()).unwrap();
+        assert_same_csr(&Csr::from_x509_req(&req).unwrap(), csr);
+
+        let ca_key = generate_rsa_2048_priv_key().unwrap();
+        let ca = generate_ca(&ca_key, &Csr::new("Internal CA".to_string()), None).unwrap();
+        let cert = generate_ca_signed_cert(&pkey, csr, &ca, &ca_key, None).unwrap();
+        assert_same_csr(&Csr::from_x509(&cert).unwrap(), csr);
+
+        // Without a common name the first domain stands in
+        let mut builder = X509Req::builder().unwrap();
+        builder.set_pubkey(&pkey).unwrap();
+        let mut extensions = openssl::stack::Stack::new().unwrap();
+        for subject_alt_name in Csr::default().with_alt_names(vec!["a.example.com", "b.example.com"]).subject_alt_names() {
+            extensions.push(subject_alt_name.build(&builder.x509v3_context(None)).unwrap()).unwrap();
+        }
+        builder.add_extensions(&extensions).unwrap();
+        builder.sign(&pkey, MessageDigest::sha256()).unwrap();
+        let req = builder.build();
+        let parsed = Csr::from_x509_req(&req).unwrap();
+        assert_eq!(parsed.all_names(), vec!["a.example.com".to_string(), "b.example.com".to_string()]);
+    }
+
+    #[test]
+    fn subject_attributes_test() {
+        let pkey = generate_rsa_2048_priv_key().unwrap();
+        let mut csr = Csr::new("example.com".to_string());
+        let csr = csr.with_country("au".to_string())
+            .with_state("Victoria".to_string())
+            .with_locality("Melbourne".to_string())
+            .with_org_name("Lit".to_string())
+            .with_org_units(vec!["Node Devs".to_string(), "Platform".to_string()])
+            .add_attribute(Nid::ORGANIZATIONALUNITNAME, "Security".to_string())
+            .with_email("ops@example.com".to_string())
+            .with_serial_number("1234".to_string())
+            .with_org_name("Lit Protocol".to_string());
+
+        let expected = vec![
+            (Nid::COMMONNAME, "example.com".to_string()),
+            (Nid::COUNTRYNAME, "AU".to_string()),
+            (Nid::STATEORPROVINCENAME, "Victoria".to_string()),
+            (Nid::LOCALITYNAME, "Melbourne".to_string()),
+            (Nid::ORGANIZATIONNAME, "Lit Protocol".to_string()),
+            (Nid::ORGANIZATIONALUNITNAME, "Node Devs".to_string()),
+            (Nid::ORGANIZATIONALUNITNAME, "Platform".to_string()),
+            (Nid::ORGANIZATIONALUNITNAME, "Security".to_string()),
+            (Nid::PKCS9_EMAILADDRESS, "ops@example.com".to_string()),
+            (Nid::SERIALNUMBER, "1234".to_string()),
+        ];
+        assert_eq!(csr.attributes(), expected);
+        assert_eq!(csr.org_units(), vec!["Node Devs".to_string(), "Platform".to_string(), "Security".to_string()]);
+        csr.validate().unwrap();
+
+        let req = generate_csr(&pkey, csr).unwrap();
+        let nids: Vec<Nid> = req.subject_name().entries().map(|e| e.object().nid()).collect();
+        assert_eq!(nids, expected.iter().map(|(n, _)| *n).collect::<Vec<Nid>>());
+        assert_eq!(Csr::from_x509_req(&req).unwrap().attributes(), expected);
+
+        // Setting a single-valued attribute again keeps its position
+        csr.with_org_unit("Infra".to_string());
+        assert_eq!(csr.attributes()[5], (Nid::ORGANIZATIONALUNITNAME, "Infra".to_string()));
+        assert_eq!(csr.org_units(), vec!["Infra".to_string()]);
+
+        for country in ["Australia", "A", "XX", "1U"] {
+            csr.with_country(country.to_string());
+            assert!(csr.validate().unwrap_err().is_validation(), "{}", country);
+        }
+    }
+
+    fn sign_req(pkey: &PKey<openssl::pkey::Private>, csr: &Csr, digest: MessageDigest) -> X509Req {
+        let mut builder = X509Req::builder().unwrap();
+        builder.set_subject_name(&extract_name_from_csr(csr).unwrap()).unwrap();
+        builder.set_pubkey(pkey).unwrap();
+
+        let mut extensions = openssl::stack::Stack::new().unwrap();
+        for subject_alt_name in csr.subject_alt_names() {
+            extensions.push(subject_alt_name.build(&builder.x509v3_context(None)).unwrap()).unwrap();
+        }
+        builder.add_extensions(&extensions).unwrap();
+        builder.sign(pkey, digest).unwrap();
+
+        builder.build()
+    }
+
+    #[test]
+    fn lint_csr_ok_test() {
+        let pkey = generate_rsa_2048_priv_key().unwrap();
+
+        let mut csr = Csr::new("example.com".to_string());
+        let csr = csr.with_alt_names(vec!["example.com".to_string(), "www.example.com".to_string()]);
+
+        let req = generate_csr(&pkey, csr).unwrap();
+        assert_eq!(lint_csr(&req).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn lint_csr_findings_test() {
+        let pkey = PKey::from_rsa(Rsa::generate(1024).unwrap()).unwrap();
+
+        let mut csr = Csr::new("host.internal".to_string());
+        let csr = csr.with_alt_names(vec!["www.example.com".to_string()]);
+
+        let req = sign_req(&pkey, csr, MessageDigest::sha1());
+        assert_eq!(lint_csr(&req).unwrap(), vec![
+            CsrFinding::KeyTooSmall { bits: 1024, min: 2048 },
+            CsrFinding::WeakSignatureAlgorithm("sha1WithRSAEncryption".to_string()),
+            CsrFinding::CommonNameNotInSans("host.internal".to_string()),
+            CsrFinding::InternalName("host.internal".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn lint_csr_ip_and_curve_test() {
+        let group = EcGroup::from_curve_name(Nid::SECP521R1).unwrap();
+        let pkey = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+
+        let mut csr = Csr::new("10.0.0.5".to_string());
+        let csr = csr.with_alt_names(vec!["10.0.0.5".to_string(), "8.8.8.8".to_string()]);
+
+        let req = sign_req(&pkey, csr, MessageDigest::sha256());
+        assert_eq!(lint_csr(&req).unwrap(), vec![
+            CsrFinding::UnsupportedCurve("secp521r1".to_string()),
+            CsrFinding::InternalName("10.0.0.5".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn lint_csr_eddsa_test() {
+        let pkey = generate_private_key(KeySpec::Ed25519).unwrap();
+        let mut csr = Csr::new("example.com".to_string());
+        let csr = csr.with_alt_names(vec!["example.com".to_string()]);
+
+        let req = generate_csr(&pkey, csr).unwrap();
+        assert_eq!(lint_csr(&req).unwrap(), vec![CsrFinding::UnsupportedKeyType("Ed25519".to_string())]);
+    }
+
+    #[test]
+    fn oid_to_string_test() {
+        assert_eq!(oid_to_string(&[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d]).as_deref(), Some("1.2.840.113549"));
+        assert_eq!(oid_to_string(&[0x55, 0x04, 0x03]).as_deref(), Some("2.5.4.3"));
+        // The first subidentifier can span several bytes under arc 2
+        assert_eq!(oid_to_string(&[0x88, 0x37, 0x03]).as_deref(), Some("2.999.3"));
+        assert_eq!(oid_to_string(&[0x2a, 0x86]), None);
+        assert_eq!(oid_to_string(&[]), None);
+    }
+}
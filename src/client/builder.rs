@@ -0,0 +1,125 @@
+use std::time::Duration;
+
+use crate::client::{API_URL, Client};
+use crate::client::retry::RetryPolicy;
+use crate::error as error;
+use crate::error::Result;
+
+pub const DEFAULT_USER_AGENT: &str = concat!("zerossl-rust/", env!("CARGO_PKG_VERSION"));
+
+#[derive(Debug, Clone)]
+pub struct ClientBuilder {
+    api_key: String,
+    api_url: String,
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    user_agent: String,
+    http_client: Option<reqwest::Client>,
+    retry_policy: RetryPolicy,
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        Self {
+            api_key: "".to_string(),
+            api_url: API_URL.to_string(),
+            connect_timeout: None,
+            timeout: None,
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            http_client: None,
+            retry_policy: RetryPolicy::default(),
+        }
+    }
+}
+
+impl ClientBuilder {
+    pub fn new(api_key: String) -> Self {
+        Self {
+            api_key,
+            ..Self::default()
+        }
+    }
+
+    /// Overrides the API base URL (e.g. a proxy, staging or fake server).
+    pub fn with_api_url(&mut self, api_url: String) -> &mut Self {
+        self.api_url = api_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn with_connect_timeout(&mut self, connect_timeout: Duration) -> &mut Self {
+        self.connect_timeout = Some(connect_timeout);
+        self
+    }
+
+    /// Total time allowed for a single request, including reading the body.
+    pub fn with_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_user_agent(&mut self, user_agent: String) -> &mut Self {
+        self.user_agent = user_agent;
+        self
+    }
+
+    /// Uses an existing `reqwest::Client` as is. The timeouts and user agent
+    /// set on this builder are ignored in that case.
+    pub fn with_http_client(&mut self, http_client: reqwest::Client) -> &mut Self {
+        self.http_client = Some(http_client);
+        self
+    }
+
+    /// Use `RetryPolicy::none()` to disable retries.
+    pub fn with_retry_policy(&mut self, retry_policy: RetryPolicy) -> &mut Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn build(&self) -> Result<Client> {
+        let http = match self.http_client.as_ref() {
+            Some(http_client) => http_client.clone(),
+            None => {
+                let mut builder = reqwest::Client::builder()
+                    .user_agent(self.user_agent.clone());
+
+                if let Some(connect_timeout) = self.connect_timeout {
+                    builder = builder.connect_timeout(connect_timeout);
+                }
+                if let Some(timeout) = self.timeout {
+                    builder = builder.timeout(timeout);
+                }
+
+                builder.build()
+                    .map_err(|e| error::request(e, Some("failed to build http client".to_string())))?
+            }
+        };
+
+        Ok(Client {
+            api_key: self.api_key.clone(),
+            api_url: self.api_url.clone(),
+            http,
+            retry_policy: self.retry_policy.clone(),
+        })
+    }
+
+    // Accessors
+    pub fn api_url(&self) -> String {
+        self.api_url.clone()
+    }
+
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        self.connect_timeout
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    pub fn user_agent(&self) -> String {
+        self.user_agent.clone()
+    }
+
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+}
@@ -1,9 +1,15 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Cursor, Read};
+
 use openssl::pkey::{PKey, Private};
-use serde::{Deserialize, Serialize};
+use openssl::x509::X509Req;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
 use crate::certs::csr::{Csr, generate_csr};
 
 use crate::client::result::{ErrorMsg, Resp, ResultStatus};
-use crate::client::validation::{ValidationOptions, ValidationType};
+use crate::client::validation::{Challenge, FileChallenge, ValidationOptions, ValidationType};
 
 // Create Certificate
 
@@ -29,7 +35,8 @@ impl CreateCertificateReq {
     }
 
     pub fn from_csr(pkey: &PKey<Private>, csr: &Csr) -> crate::error::Result<Self> {
-        let x509_req = generate_csr(&pkey, &csr)
+        csr.validate()?;
+        let x509_req = generate_csr(pkey, csr)
             .map_err(|e| crate::error::openssl(e, None))?;
 
         let csr_pem = x509_req.to_pem()
@@ -41,6 +48,23 @@ impl CreateCertificateReq {
         Ok(Self::new(csr.all_names(), csr_pem_str))
     }
 
+    /// Submits an existing PEM CSR, taking the domains from the names it
+    /// requests.
+    pub fn from_pem_csr(pem: &str) -> crate::error::Result<Self> {
+        let x509_req = X509Req::from_pem(pem.as_bytes())
+            .map_err(|e| crate::error::openssl(e, Some("invalid PEM CSR".to_string())))?;
+        let csr = Csr::from_x509_req(&x509_req)
+            .map_err(|e| crate::error::openssl(e, Some("failed to read CSR names".to_string())))?;
+        csr.validate()?;
+
+        let domains = csr.all_names();
+        if domains.is_empty() {
+            return Err(crate::error::validation("CSR requests no domain"));
+        }
+
+        Ok(Self::new(domains, pem.to_string()))
+    }
+
     pub fn with_certificate_validity_days(&mut self, days: u8) -> &mut Self {
         self.certificate_validity_days = Some(days);
         self
@@ -48,7 +72,7 @@ impl CreateCertificateReq {
 
     pub fn with_strict_domains(&mut self, strict_domains: bool) -> &mut Self {
         let strict_domains = if strict_domains {
-            Some(1 as u8)
+            Some(1_u8)
         } else {
             None
         };
@@ -77,17 +101,49 @@ impl CreateCertificateRes {
 
 impl Resp for CreateCertificateRes {
     fn is_ok(&self) -> bool {
-        return self.result_status.is_ok();
+        self.result_status.is_ok()
     }
 
     fn err_msg(&self) -> Option<ErrorMsg> {
-        return self.result_status.err_msg();
+        self.result_status.err_msg()
     }
 }
 
-// List Certificates
+// Get Certificate
 
 #[derive(Debug, Serialize, Deserialize)]
+pub struct GetCertificateRes {
+    #[serde(flatten)]
+    pub(crate) result_status: ResultStatus,
+
+    // Actual response
+    #[serde(flatten)]
+    pub(crate) certificate: Certificate,
+}
+
+impl GetCertificateRes {
+    pub fn certificate(&self) -> &Certificate {
+        &self.certificate
+    }
+
+    pub fn into_certificate(self) -> Certificate {
+        self.certificate
+    }
+}
+
+impl Resp for GetCertificateRes {
+    fn is_ok(&self) -> bool {
+        self.result_status.is_ok()
+    }
+
+    fn err_msg(&self) -> Option<ErrorMsg> {
+        self.result_status.err_msg()
+    }
+}
+
+// List Certificates
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct ListCertificatesReq {
     certificate_status: Option<String>,
     certificate_type: Option<String>,
@@ -97,16 +153,6 @@ pub struct ListCertificatesReq {
 }
 
 impl ListCertificatesReq {
-    pub fn default() -> Self {
-        Self {
-            certificate_status: None,
-            certificate_type: None,
-            search: None,
-            limit: None,
-            page: None
-        }
-    }
-
     pub fn new(certificate_status: Option<String>,
                certificate_type: Option<String>,
                search: Option<String>,
@@ -117,7 +163,6 @@ impl ListCertificatesReq {
             certificate_status,
             certificate_type,
             search, limit, page,
-            ..Self::default()
         }
     }
 
@@ -128,10 +173,33 @@ impl ListCertificatesReq {
         }
     }
 
-    pub fn with_status(&mut self, status: Vec<&str>) -> &mut Self {
+    pub fn with_status(&mut self, status: Vec<CertificateStatus>) -> &mut Self {
+        let status: Vec<&str> = status.iter()
+            .map(|s| s.as_str())
+            .collect();
+
         self.certificate_status = Some(status.join(","));
         self
     }
+
+    pub fn with_limit(&mut self, limit: u32) -> &mut Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn with_page(&mut self, page: u32) -> &mut Self {
+        self.page = Some(page);
+        self
+    }
+
+    // Accessors
+    pub fn limit(&self) -> Option<u32> {
+        self.limit
+    }
+
+    pub fn page(&self) -> Option<u32> {
+        self.page
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -140,6 +208,16 @@ pub struct ListCertificatesRes {
     pub(crate) result_status: ResultStatus,
 
     // Actual response
+    #[serde(default, deserialize_with = "de_opt_u32")]
+    pub(crate) total_count: Option<u32>,
+    #[serde(default, deserialize_with = "de_opt_u32")]
+    pub(crate) result_count: Option<u32>,
+    // ZeroSSL returns the page as a string
+    #[serde(default, deserialize_with = "de_opt_u32")]
+    pub(crate) page: Option<u32>,
+    #[serde(default, deserialize_with = "de_opt_u32")]
+    pub(crate) limit: Option<u32>,
+    #[serde(default)]
     pub(crate) results: Vec<Certificate>,
 }
 
@@ -147,15 +225,36 @@ impl ListCertificatesRes {
     pub fn results(&self) -> &Vec<Certificate> {
         &self.results
     }
+
+    pub fn into_results(self) -> Vec<Certificate> {
+        self.results
+    }
+
+    /// Number of certificates matching the request, across all pages.
+    pub fn total_count(&self) -> Option<u32> {
+        self.total_count
+    }
+
+    pub fn result_count(&self) -> Option<u32> {
+        self.result_count
+    }
+
+    pub fn page(&self) -> Option<u32> {
+        self.page
+    }
+
+    pub fn limit(&self) -> Option<u32> {
+        self.limit
+    }
 }
 
 impl Resp for ListCertificatesRes {
     fn is_ok(&self) -> bool {
-        return self.result_status.is_ok();
+        self.result_status.is_ok()
     }
 
     fn err_msg(&self) -> Option<ErrorMsg> {
-        return self.result_status.err_msg();
+        self.result_status.err_msg()
     }
 }
 
@@ -177,6 +276,39 @@ impl VerifyCertificateReq {
             validation_email
         }
     }
+
+    /// Email validation with one approver per domain, in the same order as the
+    /// certificate's domains (see `Certificate::domains`).
+    pub fn for_email(validation_emails: Vec<String>) -> Self {
+        Self::new(ValidationType::Email, Some(validation_emails.join(",")))
+    }
+
+    /// Email validation using an approver picked for each of `cert`'s domains
+    /// (see `ValidationOptions::pick_email_approver`, an empty `preference`
+    /// uses `DEFAULT_APPROVER_PREFERENCE`).
+    pub fn for_email_approvers(cert: &Certificate, preference: &[&str]) -> crate::error::Result<Self> {
+        let validation = cert.validation.as_ref()
+            .ok_or_else(|| crate::error::validation("certificate has no validation options"))?;
+
+        let mut validation_emails: Vec<String> = Vec::new();
+        for domain in cert.domains() {
+            let email = validation.pick_email_approver(&domain, preference)
+                .ok_or_else(|| crate::error::validation(format!("no email approver offered for {}", domain)))?;
+            validation_emails.push(email);
+        }
+
+        Ok(Self::for_email(validation_emails))
+    }
+
+    pub fn validation_method(&self) -> &ValidationType {
+        &self.validation_method
+    }
+
+    pub fn validation_emails(&self) -> Vec<String> {
+        self.validation_email.as_ref()
+            .map(|e| e.split(',').map(|e| e.to_string()).collect())
+            .unwrap_or_default()
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -187,15 +319,39 @@ pub struct VerifyCertificateRes {
 
 impl Resp for VerifyCertificateRes {
     fn is_ok(&self) -> bool {
-        return self.result_status.is_ok();
+        self.result_status.is_ok()
     }
 
     fn err_msg(&self) -> Option<ErrorMsg> {
-        return self.result_status.err_msg();
+        self.result_status.err_msg()
     }
 }
 
 // Download Certificate
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct DownloadOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    include_cross_signed: Option<u8>,
+}
+
+impl DownloadOptions {
+    /// Appends the cross-signed root to the CA bundle, for clients that do
+    /// not trust the newer root yet.
+    pub fn with_include_cross_signed(&mut self, include_cross_signed: bool) -> &mut Self {
+        self.include_cross_signed = if include_cross_signed {
+            Some(1_u8)
+        } else {
+            None
+        };
+        self
+    }
+
+    pub fn include_cross_signed(&self) -> bool {
+        self.include_cross_signed == Some(1)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DownloadCertificateRes {
     #[serde(flatten)]
@@ -220,17 +376,156 @@ impl DownloadCertificateRes {
 
 impl Resp for DownloadCertificateRes {
     fn is_ok(&self) -> bool {
-        return self.result_status.is_ok();
+        self.result_status.is_ok()
     }
 
     fn err_msg(&self) -> Option<ErrorMsg> {
-        return self.result_status.err_msg();
+        self.result_status.err_msg()
+    }
+}
+
+// Certificates and bundles are a few KB, anything bigger isn't one
+const ZIP_ENTRY_LIMIT: u64 = 1024 * 1024;
+
+/// The ZIP archive returned by `/certificates/{id}/download` and its text files.
+#[derive(Debug, Clone)]
+pub struct DownloadCertificateZip {
+    bytes: Vec<u8>,
+    files: HashMap<String, String>,
+}
+
+impl DownloadCertificateZip {
+    pub fn from_bytes(bytes: Vec<u8>) -> crate::error::Result<Self> {
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes.as_slice()))
+            .map_err(|e| crate::error::decode(e, Some("invalid certificate zip".to_string())))?;
+
+        let mut files: HashMap<String, String> = HashMap::new();
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i)
+                .map_err(|e| crate::error::decode(e, Some("invalid certificate zip".to_string())))?;
+            if file.is_dir() {
+                continue;
+            }
+
+            let name = file.name().to_string();
+            let mut content = String::new();
+            file.by_ref().take(ZIP_ENTRY_LIMIT + 1).read_to_string(&mut content)
+                .map_err(|e| crate::error::decode(e, Some(format!("failed to read {}", name))))?;
+            if content.len() as u64 > ZIP_ENTRY_LIMIT {
+                return Err(crate::error::decode(format!("{} is larger than {} bytes", name, ZIP_ENTRY_LIMIT), None));
+            }
+            files.insert(name, content);
+        }
+
+        Ok(Self { bytes, files })
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// File name to content, e.g. `certificate.crt` and `ca_bundle.crt`.
+    pub fn files(&self) -> &HashMap<String, String> {
+        &self.files
+    }
+
+    pub fn certificate_crt(&self) -> Option<&String> {
+        self.files.get("certificate.crt")
+    }
+
+    pub fn ca_bundle_crt(&self) -> Option<&String> {
+        self.files.get("ca_bundle.crt")
     }
 }
 
 // Common
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CertificateStatus {
+    Draft,
+    PendingValidation,
+    Issued,
+    Cancelled,
+    Revoked,
+    Expired,
+    /// A status this crate does not know about yet.
+    Unknown(String),
+}
+
+impl CertificateStatus {
+    pub fn as_str(&self) -> &str {
+        match self {
+            CertificateStatus::Draft => "draft",
+            CertificateStatus::PendingValidation => "pending_validation",
+            CertificateStatus::Issued => "issued",
+            CertificateStatus::Cancelled => "cancelled",
+            CertificateStatus::Revoked => "revoked",
+            CertificateStatus::Expired => "expired",
+            CertificateStatus::Unknown(status) => status.as_str(),
+        }
+    }
+
+    /// Not yet issued, can still be validated or cancelled.
+    pub fn is_pending(&self) -> bool {
+        matches!(self, CertificateStatus::Draft | CertificateStatus::PendingValidation)
+    }
+
+    pub fn is_active(&self) -> bool {
+        matches!(self, CertificateStatus::Issued)
+    }
+
+    /// No further status changes are possible.
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, CertificateStatus::Cancelled | CertificateStatus::Revoked | CertificateStatus::Expired)
+    }
+
+    pub fn pending() -> Vec<CertificateStatus> {
+        vec![CertificateStatus::Draft, CertificateStatus::PendingValidation]
+    }
+
+    pub fn active() -> Vec<CertificateStatus> {
+        vec![CertificateStatus::Issued]
+    }
+}
+
+impl From<&str> for CertificateStatus {
+    fn from(status: &str) -> Self {
+        match status {
+            "draft" => CertificateStatus::Draft,
+            "pending_validation" => CertificateStatus::PendingValidation,
+            "issued" => CertificateStatus::Issued,
+            "cancelled" => CertificateStatus::Cancelled,
+            "revoked" => CertificateStatus::Revoked,
+            "expired" => CertificateStatus::Expired,
+            other => CertificateStatus::Unknown(other.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for CertificateStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for CertificateStatus {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for CertificateStatus {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let status = String::deserialize(deserializer)?;
+        Ok(CertificateStatus::from(status.as_str()))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Certificate {
     pub id: Option<String>,
     #[serde(rename = "type")]
@@ -239,19 +534,228 @@ pub struct Certificate {
     pub additional_domains: Option<String>,
     pub created: Option<String>,
     pub expires: Option<String>,
-    pub status: Option<String>,
+    pub status: Option<CertificateStatus>,
     pub validation_type: Option<ValidationType>,
     pub validation_emails: Option<String>,
     pub replacement_for: Option<String>,
+    pub fingerprint_sha1: Option<String>,
+    // Returned as either an object or null, depending on the certificate type
+    pub brand_validation: Option<Value>,
+    pub signature_algorithm_properties: Option<SignatureAlgorithmProperties>,
     pub validation: Option<ValidationOptions>,
 }
 
 impl Certificate {
-    pub fn file_validation(&self, domain: &String) -> Option<(String, Vec<String>)> {
+    /// The common name followed by any additional domains.
+    pub fn domains(&self) -> Vec<String> {
+        let mut domains: Vec<String> = Vec::new();
+
+        if let Some(common_name) = self.common_name.as_ref() {
+            domains.push(common_name.clone());
+        }
+        if let Some(additional_domains) = self.additional_domains.as_ref() {
+            for domain in additional_domains.split(',') {
+                let domain = domain.trim();
+                if !domain.is_empty() && !domains.iter().any(|d| d.eq_ignore_ascii_case(domain)) {
+                    domains.push(domain.to_string());
+                }
+            }
+        }
+
+        domains
+    }
+
+    pub fn file_validation(&self, domain: &str) -> Option<FileChallenge> {
+        self.validation.as_ref()
+            .and_then(|validation| validation.file_validation(domain))
+    }
+
+    /// The challenges offered for every domain, grouped by domain in the
+    /// order of `domains()`.
+    pub fn challenges(&self) -> Vec<Challenge> {
+        let validation = match self.validation.as_ref() {
+            Some(validation) => validation,
+            None => return Vec::new(),
+        };
+
+        self.domains().iter()
+            .flat_map(|domain| validation.challenges(domain))
+            .collect()
+    }
+
+    pub fn email_approvers(&self, domain: &str) -> Vec<String> {
         if let Some(validation) = self.validation.as_ref() {
-            return validation.file_validation(domain);
+            return validation.email_approvers(domain);
         }
 
-        None
+        Vec::new()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignatureAlgorithmProperties {
+    pub signature_algorithm: Option<String>,
+    pub key_type: Option<String>,
+    pub key_size: Option<Value>,
+    // Anything not covered above
+    #[serde(flatten)]
+    pub other: HashMap<String, Value>,
+}
+
+// Accepts numbers that may be sent as strings (e.g. "page": "1")
+fn de_opt_u32<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u32>, D::Error> {
+    match Option::<Value>::deserialize(deserializer)? {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::Number(n)) => n.as_u64()
+            .and_then(|n| u32::try_from(n).ok())
+            .map(Some)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid number: {}", n))),
+        Some(Value::String(s)) => s.trim().parse::<u32>()
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+        Some(other) => Err(serde::de::Error::custom(format!("expected a number, got: {}", other))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use openssl::asn1::Asn1Type;
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::stack::Stack;
+    use openssl::x509::{X509Name, X509Req};
+
+    use crate::certs::csr::{Csr, generate_csr, generate_rsa_2048_priv_key};
+    use crate::client::certificates::{Certificate, CertificateStatus, CreateCertificateReq, DownloadCertificateZip,
+                                      ListCertificatesReq, ListCertificatesRes, ZIP_ENTRY_LIMIT};
+
+    #[test]
+    fn certificate_status_serde_test() {
+        let status: Vec<CertificateStatus> = serde_json::from_str(r#"["draft", "issued", "on_hold"]"#)
+            .unwrap();
+
+        assert_eq!(status, vec![
+            CertificateStatus::Draft,
+            CertificateStatus::Issued,
+            CertificateStatus::Unknown("on_hold".to_string()),
+        ]);
+        assert_eq!(serde_json::to_string(&status).unwrap(), r#"["draft","issued","on_hold"]"#);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn certificate_status_predicates_test() {
+        assert!(CertificateStatus::Draft.is_pending());
+        assert!(CertificateStatus::PendingValidation.is_pending());
+        assert!(CertificateStatus::Issued.is_active());
+        assert!(!CertificateStatus::Issued.is_pending());
+        assert!(CertificateStatus::Revoked.is_terminal());
+        assert!(!CertificateStatus::Unknown("on_hold".to_string()).is_active());
+    }
+
+    #[test]
+    fn certificate_deserialize_test() {
+        let cert: Certificate = serde_json::from_str(r#"{
+            "id": "4b0d8a34e1fa6d6d0ecbd73d1b3c8c54",
+            "type": "1",
+            "common_name": "example.com",
+            "additional_domains": "www.example.com,example.com",
+            "created": "2020-04-02 10:06:48",
+            "expires": "2020-07-01 00:00:00",
+            "status": "issued",
+            "validation_type": "HTTP_CSR_HASH",
+            "validation_emails": "",
+            "replacement_for": "",
+            "fingerprint_sha1": "8D25F53DF8AD8A9BC61E0C0A4C3EE9D1B1FBB9B5",
+            "brand_validation": null,
+            "signature_algorithm_properties": {
+                "signature_algorithm": "sha256WithRSAEncryption",
+                "key_type": "RSA",
+                "key_size": 2048,
+                "curve": null
+            },
+            "validation": {
+                "email_validation": {"example.com": ["admin@example.com"]},
+                "other_methods": {}
+            }
+        }"#).unwrap();
+
+        assert_eq!(cert.status, Some(CertificateStatus::Issued));
+        assert_eq!(cert.domains(), vec!["example.com".to_string(), "www.example.com".to_string()]);
+        assert_eq!(cert.fingerprint_sha1.as_deref(), Some("8D25F53DF8AD8A9BC61E0C0A4C3EE9D1B1FBB9B5"));
+
+        let props = cert.signature_algorithm_properties.unwrap();
+        assert_eq!(props.key_type.as_deref(), Some("RSA"));
+        assert!(props.other.contains_key("curve"));
+    }
+
+    #[test]
+    fn list_certificates_res_test() {
+        let res: ListCertificatesRes = serde_json::from_str(
+            r#"{"total_count":250,"result_count":100,"page":"2","limit":100,"results":[]}"#).unwrap();
+
+        assert_eq!(res.total_count(), Some(250));
+        assert_eq!(res.result_count(), Some(100));
+        assert_eq!(res.page(), Some(2));
+        assert_eq!(res.limit(), Some(100));
+    }
+
+    #[test]
+    fn list_certificates_req_with_status_test() {
+        let mut req = ListCertificatesReq::default();
+        req.with_status(CertificateStatus::pending());
+
+        assert_eq!(serde_urlencoded::to_string(&req).unwrap(),
+                   "certificate_status=draft%2Cpending_validation");
+    }
+
+    #[test]
+    fn create_certificate_req_from_pem_csr_test() {
+        let pkey = generate_rsa_2048_priv_key().unwrap();
+        let mut csr = Csr::new("example.com".to_string());
+        let csr = csr.with_alt_names(vec!["example.com", "www.example.com", "ops@example.com"]);
+        let pem = String::from_utf8(generate_csr(&pkey, csr).unwrap().to_pem().unwrap()).unwrap();
+
+        let req = CreateCertificateReq::from_pem_csr(&pem).unwrap();
+        assert_eq!(req.certificate_domains, "example.com,www.example.com");
+        assert_eq!(req.certificate_csr, pem);
+
+        assert!(CreateCertificateReq::from_pem_csr("not a csr").is_err());
+
+        // An empty common name is skipped like a missing one
+        let mut builder = X509Req::builder().unwrap();
+        builder.set_pubkey(&pkey).unwrap();
+        let mut name = X509Name::builder().unwrap();
+        name.append_entry_by_nid_with_type(Nid::COMMONNAME, "", Asn1Type::UTF8STRING).unwrap();
+        builder.set_subject_name(&name.build()).unwrap();
+        let mut extensions = Stack::new().unwrap();
+        for subject_alt_name in Csr::default().with_alt_names(vec!["a.example.com", "b.example.com"]).subject_alt_names() {
+            extensions.push(subject_alt_name.build(&builder.x509v3_context(None)).unwrap()).unwrap();
+        }
+        builder.add_extensions(&extensions).unwrap();
+        builder.sign(&pkey, MessageDigest::sha256()).unwrap();
+        let pem = String::from_utf8(builder.build().to_pem().unwrap()).unwrap();
+
+        let req = CreateCertificateReq::from_pem_csr(&pem).unwrap();
+        assert_eq!(req.certificate_domains, "a.example.com,b.example.com");
+    }
+
+    #[test]
+    fn download_certificate_zip_test() {
+        let write_zip = |content: &str| {
+            let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+            zip.start_file("certificate.crt", zip::write::FileOptions::default()).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+            zip.finish().unwrap().into_inner()
+        };
+
+        let archive = DownloadCertificateZip::from_bytes(write_zip("-----BEGIN CERTIFICATE-----")).unwrap();
+        assert_eq!(archive.certificate_crt().map(|s| s.as_str()), Some("-----BEGIN CERTIFICATE-----"));
+        assert_eq!(archive.ca_bundle_crt(), None);
+
+        // Compresses to almost nothing but would expand past the limit
+        let oversized = "A".repeat(ZIP_ENTRY_LIMIT as usize + 1);
+        assert!(DownloadCertificateZip::from_bytes(write_zip(&oversized)).unwrap_err().is_decode());
+    }
+}
@@ -0,0 +1,108 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::client::result::{ErrorMsg, Resp, ResultStatus};
+use crate::error::Result;
+
+// ACME External Account Binding
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EabCredentialsEmailReq {
+    email: String,
+}
+
+impl EabCredentialsEmailReq {
+    pub fn new(email: String) -> Self {
+        Self { email }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EabCredentialsRes {
+    #[serde(flatten)]
+    pub(crate) result_status: ResultStatus,
+
+    // Actual response
+    eab_kid: Option<String>,
+    eab_hmac_key: Option<String>,
+}
+
+impl EabCredentialsRes {
+    pub fn to_credentials(&self) -> Result<EabCredentials> {
+        let kid = self.eab_kid.as_ref()
+            .ok_or_else(|| crate::error::decode("missing eab_kid", None))?;
+        let hmac_key = self.eab_hmac_key.as_ref()
+            .ok_or_else(|| crate::error::decode("missing eab_hmac_key", None))?;
+
+        EabCredentials::from_encoded(kid.clone(), hmac_key)
+    }
+}
+
+impl Resp for EabCredentialsRes {
+    fn is_ok(&self) -> bool {
+        self.result_status.is_ok()
+    }
+
+    fn err_msg(&self) -> Option<ErrorMsg> {
+        self.result_status.err_msg()
+    }
+}
+
+/// Credentials for binding an ACME account to a ZeroSSL account.
+#[derive(Clone, PartialEq, Eq)]
+pub struct EabCredentials {
+    pub kid: String,
+    /// The decoded HMAC key
+    pub hmac_key: Vec<u8>,
+}
+
+impl EabCredentials {
+    /// Decodes a base64url HMAC key, as handed out by ZeroSSL.
+    pub fn from_encoded(kid: String, hmac_key: &str) -> Result<Self> {
+        let hmac_key = base64::decode_config(hmac_key.trim().trim_end_matches('='), base64::URL_SAFE_NO_PAD)
+            .map_err(|e| crate::error::decode(e, Some("invalid eab_hmac_key".to_string())))?;
+
+        Ok(Self { kid, hmac_key })
+    }
+
+    /// The HMAC key in base64url, without padding.
+    pub fn hmac_key_encoded(&self) -> String {
+        base64::encode_config(&self.hmac_key, base64::URL_SAFE_NO_PAD)
+    }
+}
+
+// Keeps the key out of logs
+impl fmt::Debug for EabCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EabCredentials")
+            .field("kid", &self.kid)
+            .field("hmac_key", &"<redacted>")
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::eab::{EabCredentials, EabCredentialsRes};
+
+    #[test]
+    fn eab_credentials_res_test() {
+        let res: EabCredentialsRes = serde_json::from_str(
+            r#"{"success":true,"eab_kid":"f_UP6N3i2YcqMsNvdEdYEQ","eab_hmac_key":"a2V5LWJ5dGVzLWZvci10ZXN0aW5nXz8-"}"#).unwrap();
+        let creds = res.to_credentials().unwrap();
+
+        assert_eq!(creds.kid, "f_UP6N3i2YcqMsNvdEdYEQ");
+        assert_eq!(creds.hmac_key, b"key-bytes-for-testing_?>".to_vec());
+        assert_eq!(creds.hmac_key_encoded(), "a2V5LWJ5dGVzLWZvci10ZXN0aW5nXz8-");
+        assert!(!format!("{:?}", creds).contains("a2V5"));
+    }
+
+    #[test]
+    fn eab_credentials_invalid_test() {
+        assert!(EabCredentials::from_encoded("kid".to_string(), "not base64!").is_err());
+
+        let res: EabCredentialsRes = serde_json::from_str(r#"{"success":true}"#).unwrap();
+        assert!(res.to_credentials().unwrap_err().is_decode());
+    }
+}
@@ -1,50 +1,64 @@
+use futures::{Stream, TryStreamExt};
+use futures::stream;
 use reqwest::{Response, StatusCode};
 
-use crate::client::certificates::{CreateCertificateReq, CreateCertificateRes, DownloadCertificateRes, ListCertificatesReq, ListCertificatesRes, VerifyCertificateReq, VerifyCertificateRes};
-use crate::client::result::{Resp, ResultStatusAlt};
+use crate::client::certificates::{Certificate, CertificateStatus, CreateCertificateReq, CreateCertificateRes, GetCertificateRes, DownloadCertificateRes, DownloadCertificateZip, DownloadOptions, ListCertificatesReq, ListCertificatesRes, VerifyCertificateReq, VerifyCertificateRes};
+use crate::client::eab::{EabCredentials, EabCredentialsEmailReq, EabCredentialsRes};
+use crate::client::result::{ErrorMsg, Resp, ResultStatus, ResultStatusAlt};
+use crate::client::retry::{Idempotency, parse_retry_after, RetryCause, RetryDecision, RetryEvent, RetryPolicy};
+use crate::client::validation::{ValidateCsrReq, ValidateCsrRes, ValidationStatus};
+use crate::client::wait::{WaitOptions, WaitProgress};
 use crate::error as error;
 use crate::error::Result;
 
+pub mod builder;
 pub mod certificates;
+pub mod eab;
 pub mod validation;
 pub mod result;
+pub mod retry;
+pub mod wait;
 
-pub const API_URL: &str = "https://api.zerossl.com";
+pub use builder::ClientBuilder;
 
-pub const STATUS_DRAFT: &str = "draft";
-pub const STATUS_PENDING_VALIDATION: &str = "pending_validation";
-pub const STATUS_ISSUED: &str = "issued";
-pub const STATUS_CANCELLED: &str = "cancelled";
-pub const STATUS_REVOKED: &str = "revoked";
-pub const STATUS_EXPIRED: &str = "expired";
+pub const API_URL: &str = "https://api.zerossl.com";
 
-pub static ACTIVE_STATUS: [&str;1] = [STATUS_ISSUED];
-pub static PENDING_STATUS: [&str;2] = [STATUS_DRAFT, STATUS_PENDING_VALIDATION];
+// Page size used by `list_all_certificates` when the request has none (ZeroSSL's maximum)
+pub const DEFAULT_PAGE_LIMIT: u32 = 100;
 
+#[derive(Debug, Clone)]
 pub struct Client {
     api_key: String,
     api_url: String,
+    // Pooled; cloning the Client shares connections
+    http: reqwest::Client,
+    retry_policy: RetryPolicy,
 }
 
-impl Client {
-    pub fn default() -> Self {
-        Client {
-            api_key: "".to_string(),
-            api_url: API_URL.to_string(),
-        }
+impl Default for Client {
+    fn default() -> Self {
+        Client::new("".to_string())
     }
+}
 
+impl Client {
+    /// A client with the `ClientBuilder` defaults. Panics, like
+    /// `reqwest::Client::new`, when the TLS backend can't be initialized.
     pub fn new(api_key: String) -> Self {
-        Client {
-            api_key,
-            ..Client::default()
-        }
+        ClientBuilder::new(api_key).build()
+            .expect("failed to build http client")
     }
 
-    fn prepare(&self, method: reqwest::Method, uri: &str) -> reqwest::RequestBuilder {
-        let client = reqwest::Client::new();
+    pub fn builder(api_key: String) -> ClientBuilder {
+        ClientBuilder::new(api_key)
+    }
+
+    pub fn api_url(&self) -> String {
+        self.api_url.clone()
+    }
 
-        client.request(method, format!("{}{}", self.api_url, uri))
+    fn prepare(&self, method: reqwest::Method, uri: &str) -> reqwest::RequestBuilder {
+        self.http.request(method, format!("{}{}", self.api_url, uri))
             .query(&[("access_key", self.api_key.clone())])
     }
 
@@ -56,27 +70,103 @@ impl Client {
         self.prepare(reqwest::Method::POST, uri)
     }
 
+    // Sends the request, retrying according to the retry policy, until a 200 is returned
+    // that doesn't report a retryable API error.
+    async fn send(&self, idempotency: Idempotency, req: reqwest::RequestBuilder) -> Result<Response> {
+        let mut attempt: u32 = 1;
+
+        loop {
+            let attempt_req = req.try_clone()
+                .ok_or_else(|| error::request("request cannot be cloned for retry", None))?;
+
+            let (cause, retry_after, err) = match attempt_req.send().await {
+                Ok(res) if res.status() == StatusCode::OK => {
+                    let retry_after = parse_retry_after(res.headers());
+                    match read_api_error(res).await {
+                        Ok((_, Some(err_msg))) => (RetryCause::Api(err_msg.error_code()), retry_after, error::api(err_msg)),
+                        Ok((res, None)) => return Ok(res),
+                        Err(e) => (RetryCause::from_reqwest(&e), None, error::request(e, None)),
+                    }
+                }
+                Ok(res) => {
+                    let retry_after = parse_retry_after(res.headers());
+                    (RetryCause::Status(res.status()), retry_after, self.res_to_err(res).await)
+                }
+                Err(e) => (RetryCause::from_reqwest(&e), None, error::request(e, None)),
+            };
+
+            let decision = self.retry_policy.decide(attempt, idempotency, &cause, retry_after);
+            self.retry_policy.notify(&RetryEvent {
+                attempt,
+                idempotency,
+                cause,
+                retry_after,
+                decision: decision.clone(),
+            });
+
+            match decision {
+                RetryDecision::Retry(delay) => tokio::time::sleep(delay).await,
+                _ => return Err(err),
+            }
+
+            attempt += 1;
+        }
+    }
+
     // Error handling
     async fn res_to_err(&self, res: Response) -> error::Error {
-        return error::request(format!("request failed"),
-                                  Some(res.text()
-                                      .await.unwrap_or("no body returned".to_string())))
+        let status = res.status();
+        let body = res.text()
+            .await.unwrap_or("no body returned".to_string());
+
+        let err_msg = serde_json::from_str::<ResultStatus>(&body).ok()
+            .and_then(|r| r.err_msg());
+        let err = error::http(status, Some(body));
+
+        match err_msg {
+            Some(err_msg) => err.with_api_error(err_msg),
+            None => err,
+        }
     }
 
     // Actions
-    pub async fn create_certificate(&self, req: &CreateCertificateReq) -> Result<CreateCertificateRes> {
-        let res = self.post("/certificates")
-            .form(req)
-            .send().await
-            .map_err(|e| error::request(e, None))?;
+    /// Generates ACME EAB credentials tied to this client's API key.
+    pub async fn generate_eab_credentials(&self) -> Result<EabCredentials> {
+        let res = self.send(Idempotency::NonIdempotent,
+                            self.post("/acme/eab-credentials")).await?;
 
-        if res.status() != StatusCode::OK {
-            return Err(self.res_to_err(res).await);
+        self.eab_credentials_from_res(res).await
+    }
+
+    /// Generates ACME EAB credentials for the ZeroSSL account registered to
+    /// `email` (no API key needed).
+    pub async fn generate_eab_credentials_for_email(&self, email: String) -> Result<EabCredentials> {
+        let res = self.send(Idempotency::NonIdempotent,
+                            self.post("/acme/eab-credentials-email").form(&EabCredentialsEmailReq::new(email))).await?;
+
+        self.eab_credentials_from_res(res).await
+    }
+
+    async fn eab_credentials_from_res(&self, res: Response) -> Result<EabCredentials> {
+        let res = res.json::<EabCredentialsRes>()
+            .await
+            .map_err(|e| error::decode(e, None))?;
+
+        // Apparently even error's produce Status 200 (???)
+        if !res.is_ok() {
+            return Err(res.to_err());
         }
 
+        res.to_credentials()
+    }
+
+    pub async fn create_certificate(&self, req: &CreateCertificateReq) -> Result<CreateCertificateRes> {
+        let res = self.send(Idempotency::NonIdempotent,
+                            self.post("/certificates").form(req)).await?;
+
         let res = res.json::<CreateCertificateRes>()
             .await
-            .map_err(|e| error::request(e, None))?;
+            .map_err(|e| error::decode(e, None))?;
 
         // Apparently even error's produce Status 200 (???)
         if !res.is_ok() {
@@ -86,19 +176,78 @@ impl Client {
         Ok(res)
     }
 
-    pub async fn get_certificates(&self, req: &ListCertificatesReq) -> Result<ListCertificatesRes> {
-        let res = self.get("/certificates")
-            .query(req)
-            .send().await
-            .map_err(|e| error::request(e, None))?;
+    pub async fn get_certificate(&self, id: String) -> Result<Certificate> {
+        let res = self.send(Idempotency::Safe,
+                            self.get(format!("/certificates/{}", id).as_str())).await?;
+
+        let res = res.json::<GetCertificateRes>()
+            .await
+            .map_err(|e| error::decode(e, None))?;
 
-        if res.status() != StatusCode::OK {
-            return Err(self.res_to_err(res).await);
+        // Apparently even error's produce Status 200 (???)
+        if !res.is_ok() {
+            return Err(res.to_err());
         }
 
+        Ok(res.into_certificate())
+    }
+
+    /// Polls the certificate until it reaches `target` or a terminal status
+    /// (cancelled, revoked, expired) and returns it in that state. Fails with a
+    /// timeout error (see `Error::is_timeout`) once the deadline has passed.
+    pub async fn wait_for_status(&self, id: String, target: CertificateStatus, opts: &WaitOptions) -> Result<Certificate> {
+        let started = tokio::time::Instant::now();
+        let mut interval = opts.initial_interval();
+        let mut attempt: u32 = 1;
+
+        loop {
+            let cert = self.get_certificate(id.clone()).await?;
+            let elapsed = started.elapsed();
+
+            let done = cert.status.as_ref()
+                .map(|s| *s == target || s.is_terminal())
+                .unwrap_or(false);
+            let remaining = opts.deadline().saturating_sub(elapsed);
+            let next_poll = if done || remaining.is_zero() {
+                None
+            } else {
+                Some(interval.min(remaining))
+            };
+
+            opts.notify(&WaitProgress {
+                attempt,
+                elapsed,
+                status: cert.status.clone(),
+                next_poll,
+            });
+
+            if done {
+                return Ok(cert);
+            }
+
+            match next_poll {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => {
+                    let status = cert.status.as_ref()
+                        .map(|s| s.to_string())
+                        .unwrap_or("unknown".to_string());
+                    return Err(error::timeout(format!(
+                        "certificate {} still {} after {:?} (waiting for {})", id, status, elapsed, target)));
+                }
+            }
+
+            interval = opts.next_interval(interval);
+            attempt += 1;
+        }
+    }
+
+    pub async fn get_certificates(&self, req: &ListCertificatesReq) -> Result<ListCertificatesRes> {
+        let res = self.send(Idempotency::Safe,
+                            self.get("/certificates").query(req)).await?;
+
         let res = res.json::<ListCertificatesRes>()
             .await
-            .map_err(|e| error::request(e, None))?;
+            .map_err(|e| error::decode(e, None))?;
 
         // Apparently even error's produce Status 200 (???)
         if !res.is_ok() {
@@ -108,42 +257,78 @@ impl Client {
         Ok(res)
     }
 
+    /// Lazily walks every page of results, starting from `req.page` (or 1).
+    pub fn list_all_certificates(&self, req: &ListCertificatesReq) -> impl Stream<Item = Result<Certificate>> + '_ {
+        let mut req = req.clone();
+        let limit = req.limit().unwrap_or(DEFAULT_PAGE_LIMIT).max(1);
+        let page = req.page().unwrap_or(1).max(1);
+        req.with_limit(limit);
+
+        stream::try_unfold(Some((req, page)), move |state| async move {
+            let (mut req, page) = match state {
+                Some(state) => state,
+                None => return Ok(None),
+            };
+
+            req.with_page(page);
+            let res = self.get_certificates(&req).await?;
+
+            let total_count = res.total_count();
+            let results = res.into_results();
+            if results.is_empty() {
+                return Ok(None);
+            }
+
+            let seen = u64::from(page - 1) * u64::from(limit) + results.len() as u64;
+            let more = (results.len() as u32) >= limit
+                && total_count.map(|t| seen < u64::from(t)).unwrap_or(true);
+            let next = if more {
+                Some((req, page + 1))
+            } else {
+                None
+            };
+
+            Ok(Some((results, next)))
+        })
+            .map_ok(|results| stream::iter(results.into_iter().map(Ok)))
+            .try_flatten()
+    }
+
     pub async fn get_pending_certificates(&self, domain: String) -> Result<ListCertificatesRes> {
         let mut cert_search_req = ListCertificatesReq::for_search(domain);
-        cert_search_req.with_status(PENDING_STATUS.to_vec());
+        cert_search_req.with_status(CertificateStatus::pending());
 
         self.get_certificates(&cert_search_req).await
     }
 
     pub async fn purge_certificates(&self, domain: String, include_pending: bool, include_active: bool) -> Result<()> {
         if !include_pending && !include_active {
-            return Err(error::request("include_pending or include_active must be true when calling purge_certificates", None))
+            return Err(error::validation("include_pending or include_active must be true when calling purge_certificates"))
         }
 
         let mut cert_search_req = ListCertificatesReq::for_search(domain);
 
         let mut status = Vec::new();
         if include_pending {
-            status.extend(PENDING_STATUS.to_vec());
+            status.extend(CertificateStatus::pending());
         }
         if include_active {
-            status.extend(ACTIVE_STATUS);
+            status.extend(CertificateStatus::active());
         }
 
         cert_search_req.with_status(status);
 
-        let res = self.get_certificates(&cert_search_req).await?;
+        // Collect first, cancelling while paging would shift the pages
+        let certs: Vec<Certificate> = self.list_all_certificates(&cert_search_req)
+            .try_collect().await?;
 
-        for rec in res.results.iter() {
+        for rec in certs.iter() {
             if let Some(id) = rec.id.as_ref() {
                 if let Some(status) = rec.status.as_ref() {
-                    match status.as_str() {
-                        STATUS_DRAFT | STATUS_PENDING_VALIDATION => {
-                            self.cancel_certificate(id.clone()).await?;
-                        }
-                        _ => {
-                            self.revoke_certificate(id.clone()).await?;
-                        }
+                    if status.is_pending() {
+                        self.cancel_certificate(id.clone()).await?;
+                    } else if status.is_active() {
+                        self.revoke_certificate(id.clone()).await?;
                     }
                 }
             }
@@ -153,17 +338,12 @@ impl Client {
     }
 
     pub async fn cancel_certificate(&self, id: String) -> Result<ResultStatusAlt> {
-        let res = self.post(format!("/certificates/{}/cancel", id).as_str())
-            .send().await
-            .map_err(|e| error::request(e, None))?;
-
-        if res.status() != StatusCode::OK {
-            return Err(self.res_to_err(res).await);
-        }
+        let res = self.send(Idempotency::Idempotent,
+                            self.post(format!("/certificates/{}/cancel", id).as_str())).await?;
 
         let res = res.json::<ResultStatusAlt>()
             .await
-            .map_err(|e| error::request(e, None))?;
+            .map_err(|e| error::decode(e, None))?;
 
         // Apparently even error's produce Status 200 (???)
         if !res.is_ok() {
@@ -174,17 +354,12 @@ impl Client {
     }
 
     pub async fn revoke_certificate(&self, id: String) -> Result<ResultStatusAlt> {
-        let res = self.post(format!("/certificates/{}/revoke", id).as_str())
-            .send().await
-            .map_err(|e| error::request(e, None))?;
-
-        if res.status() != StatusCode::OK {
-            return Err(self.res_to_err(res).await);
-        }
+        let res = self.send(Idempotency::Idempotent,
+                            self.post(format!("/certificates/{}/revoke", id).as_str())).await?;
 
         let res = res.json::<ResultStatusAlt>()
             .await
-            .map_err(|e| error::request(e, None))?;
+            .map_err(|e| error::decode(e, None))?;
 
         // Apparently even error's produce Status 200 (???)
         if !res.is_ok() {
@@ -195,18 +370,30 @@ impl Client {
     }
 
     pub async fn verify_certificate(&self, id: String, req: &VerifyCertificateReq) -> Result<VerifyCertificateRes> {
-        let res = self.post(format!("/certificates/{}/challenges", id).as_str())
-            .form(req)
-            .send().await
-            .map_err(|e| error::request(e, None))?;
+        let res = self.send(Idempotency::NonIdempotent,
+                            self.post(format!("/certificates/{}/challenges", id).as_str()).form(req)).await?;
 
-        if res.status() != StatusCode::OK {
-            return Err(self.res_to_err(res).await);
+        let res = res.json::<VerifyCertificateRes>()
+            .await
+            .map_err(|e| error::decode(e, None))?;
+
+        // Apparently even error's produce Status 200 (???)
+        if !res.is_ok() {
+            return Err(res.to_err());
         }
 
-        let res = res.json::<VerifyCertificateRes>()
+        Ok(res)
+    }
+
+    /// Asks ZeroSSL whether it would accept the PEM encoded CSR. A rejected CSR
+    /// is not an error, see `ValidateCsrRes::is_valid`.
+    pub async fn validate_csr(&self, csr: String) -> Result<ValidateCsrRes> {
+        let res = self.send(Idempotency::Safe,
+                            self.post("/validation/csr").form(&ValidateCsrReq::new(csr))).await?;
+
+        let res = res.json::<ValidateCsrRes>()
             .await
-            .map_err(|e| error::request(e, None))?;
+            .map_err(|e| error::decode(e, None))?;
 
         // Apparently even error's produce Status 200 (???)
         if !res.is_ok() {
@@ -216,18 +403,47 @@ impl Client {
         Ok(res)
     }
 
-    pub async fn download_certificate(&self, id: String) -> Result<DownloadCertificateRes> {
-        let res = self.get(format!("/certificates/{}/download/return", id).as_str())
-            .send().await
-            .map_err(|e| error::request(e, None))?;
+    /// Sends the approval emails again for a certificate pending email validation.
+    pub async fn resend_verification_email(&self, id: String) -> Result<ResultStatusAlt> {
+        let res = self.send(Idempotency::NonIdempotent,
+                            self.post(format!("/certificates/{}/challenges/email", id).as_str())).await?;
+
+        let res = res.json::<ResultStatusAlt>()
+            .await
+            .map_err(|e| error::decode(e, None))?;
+
+        // Apparently even error's produce Status 200 (???)
+        if !res.is_ok() {
+            return Err(res.to_err());
+        }
+
+        Ok(res)
+    }
+
+    /// Per-domain results of the last domain control validation.
+    pub async fn validation_status(&self, id: String) -> Result<ValidationStatus> {
+        let res = self.send(Idempotency::Safe,
+                            self.get(format!("/certificates/{}/status", id).as_str())).await?;
+
+        let res = res.json::<ValidationStatus>()
+            .await
+            .map_err(|e| error::decode(e, None))?;
 
-        if res.status() != StatusCode::OK {
-            return Err(self.res_to_err(res).await);
+        // Apparently even error's produce Status 200 (???)
+        if !res.is_ok() {
+            return Err(res.to_err());
         }
 
+        Ok(res)
+    }
+
+    pub async fn download_certificate(&self, id: String, opts: &DownloadOptions) -> Result<DownloadCertificateRes> {
+        let res = self.send(Idempotency::Safe,
+                            self.get(format!("/certificates/{}/download/return", id).as_str()).query(opts)).await?;
+
         let res = res.json::<DownloadCertificateRes>()
             .await
-            .map_err(|e| error::request(e, None))?;
+            .map_err(|e| error::decode(e, None))?;
 
         // Apparently even error's produce Status 200 (???)
         if !res.is_ok() {
@@ -236,65 +452,513 @@ impl Client {
 
         Ok(res)
     }
+
+    pub async fn download_certificate_zip(&self, id: String, opts: &DownloadOptions) -> Result<DownloadCertificateZip> {
+        let res = self.send(Idempotency::Safe,
+                            self.get(format!("/certificates/{}/download", id).as_str()).query(opts)).await?;
+
+        let bytes = res.bytes()
+            .await
+            .map_err(|e| error::request(e, None))?;
+
+        // Errors still come back as JSON with status 200
+        if bytes.first() == Some(&b'{') {
+            let res = serde_json::from_slice::<ResultStatus>(&bytes)
+                .map_err(|e| error::decode(e, None))?;
+            return Err(res.to_err());
+        }
+
+        DownloadCertificateZip::from_bytes(bytes.to_vec())
+    }
+}
+
+// ZeroSSL answers most failures with a 200 and `success: false`. Buffers the
+// body to look for a retryable error, returning the response intact otherwise.
+async fn read_api_error(res: Response) -> std::result::Result<(Response, Option<ErrorMsg>), reqwest::Error> {
+    let status = res.status();
+    let version = res.version();
+    let headers = res.headers().clone();
+    let body = res.bytes().await?;
+
+    let err_msg = serde_json::from_slice::<ResultStatus>(&body).ok()
+        .filter(|r| !r.is_ok())
+        .and_then(|r| r.err_msg())
+        .filter(|e| e.error_code().is_retryable());
+
+    let mut res = http::Response::new(body);
+    *res.status_mut() = status;
+    *res.version_mut() = version;
+    *res.headers_mut() = headers;
+
+    Ok((Response::from(res), err_msg))
 }
 
 #[cfg(test)]
 mod tests {
     use std::env;
-
-    use crate::certs::csr::{Csr, generate_rsa_2048_priv_key};
-    use crate::client::certificates::{CreateCertificateReq, ListCertificatesReq};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use futures::{StreamExt, TryStreamExt};
+    use openssl::x509::X509;
+    use reqwest::StatusCode;
+
+    use crate::certs::csr::{Csr, generate_csr, generate_rsa_2048_priv_key};
+    use crate::client::certificates::{Certificate, CertificateStatus, CreateCertificateReq, DownloadOptions, ListCertificatesReq,
+                                      VerifyCertificateReq};
+    use crate::client::result::ApiErrorCode;
+    use crate::client::retry::{RetryCause, RetryDecision, RetryEvent, RetryPolicy};
+    use crate::client::wait::{WaitOptions, WaitProgress};
+    use crate::client::validation::ValidationType;
+    use crate::client::builder::DEFAULT_USER_AGENT;
     use crate::client::Client;
+    use crate::testing::FakeZeroSsl;
 
-    #[tokio::test]
-    async fn dummy_test() {
-        //let test_domain = "107.178.100.155".to_string();
-        //let is_ip = true;
-        let test_domain = "dev.getlit.sh".to_string();
-        let is_ip = false;
+    const TEST_API_KEY: &str = "test-api-key";
 
-        let api_key = env::var("API_KEY").unwrap();
-        let client = Client::new(api_key);
+    async fn fake_client() -> (FakeZeroSsl, Client) {
+        let fake = FakeZeroSsl::start(TEST_API_KEY.to_string()).await
+            .expect("failed to start fake server");
+        let client = Client::builder(TEST_API_KEY.to_string())
+            .with_api_url(fake.url())
+            .build()
+            .expect("failed to build client");
 
-        let pkey = generate_rsa_2048_priv_key().unwrap();
+        (fake, client)
+    }
 
-        let mut domains: Vec<String> = Vec::new();
-        domains.push(test_domain.clone());
+    fn cert_req(domain: &str) -> CreateCertificateReq {
+        let pkey = generate_rsa_2048_priv_key().unwrap();
 
-        let mut csr = Csr::new(test_domain.clone());
-        let csr = csr.with_alt_names(domains.clone(), is_ip)
+        let mut csr = Csr::new(domain.to_string());
+        let csr = csr.with_alt_names(vec![domain.to_string()])
             .with_country("AU".to_string())
-            .with_org_name("Lit".to_string())
-            .with_org_unit("Node Devs".to_string());
+            .with_org_name("Lit".to_string());
+
+        CreateCertificateReq::from_csr(&pkey, csr)
+            .expect("failed to make cert req")
+    }
+
+    #[tokio::test]
+    async fn create_verify_download_test() {
+        let (fake, client) = fake_client().await;
+        let test_domain = "dev.example.com".to_string();
 
-        let cert_req = CreateCertificateReq::from_csr(&pkey, &csr)
-            .expect("failed to make cert req");
+        let cert_res = client.create_certificate(&cert_req(&test_domain)).await
+            .expect("failed to create cert");
+        let id = cert_res.certificate().id.clone().unwrap();
+
+        assert_eq!(cert_res.certificate().status, Some(CertificateStatus::Draft));
+        assert!(cert_res.certificate().file_validation(&test_domain).is_some());
+        assert_eq!(cert_res.certificate().challenges().len(), 3);
+
+        client.verify_certificate(id.clone(), &VerifyCertificateReq::new(ValidationType::HttpCsrHash, None)).await
+            .expect("failed to verify cert");
+        assert_eq!(fake.certificate_status(&id).as_deref(), Some("issued"));
+
+        let cert = client.get_certificate(id.clone()).await
+            .expect("failed to get cert");
+        assert_eq!(cert.status, Some(CertificateStatus::Issued));
+        assert_eq!(cert.validation_type, Some(ValidationType::HttpCsrHash));
+        assert!(cert.fingerprint_sha1.is_some());
+        assert!(cert.signature_algorithm_properties.is_some());
+        assert!(cert.validation.is_some());
+
+        let mut download_res = client.download_certificate(id.clone(), &DownloadOptions::default()).await
+            .expect("failed to download cert");
+        let crt = X509::from_pem(download_res.take_certificate_crt().unwrap().as_bytes())
+            .unwrap();
+        let ca = fake.ca();
+
+        assert!(download_res.take_ca_bundle_crt().is_some());
+        assert!(crt.verify(&ca.public_key().unwrap()).unwrap());
+
+        let mut opts = DownloadOptions::default();
+        opts.with_include_cross_signed(true);
+        let mut download_res = client.download_certificate(id.clone(), &opts).await
+            .expect("failed to download cert");
+        let ca_bundle = X509::stack_from_pem(download_res.take_ca_bundle_crt().unwrap().as_bytes()).unwrap();
+        assert_eq!(ca_bundle.len(), 2);
+
+        let zip = client.download_certificate_zip(id, &opts).await
+            .expect("failed to download cert zip");
+        assert!(zip.bytes().starts_with(b"PK"));
+        assert_eq!(zip.certificate_crt().unwrap().as_bytes(), crt.to_pem().unwrap().as_slice());
+        assert_eq!(X509::stack_from_pem(zip.ca_bundle_crt().unwrap().as_bytes()).unwrap().len(), 2);
+
+        let err = client.download_certificate_zip("missing".to_string(), &opts).await.unwrap_err();
+        assert_eq!(err.api_error(), Some(ApiErrorCode::CertificateNotFound));
+    }
+
+    #[tokio::test]
+    async fn purge_certificates_test() {
+        let (fake, client) = fake_client().await;
+        let test_domain = "purge.example.com".to_string();
+
+        let pending = client.create_certificate(&cert_req(&test_domain)).await.unwrap();
+        let issued = client.create_certificate(&cert_req(&test_domain)).await.unwrap();
+        let issued_id = issued.certificate().id.clone().unwrap();
+        client.verify_certificate(issued_id.clone(), &VerifyCertificateReq::new(ValidationType::Email, None)).await
+            .unwrap();
 
         client.purge_certificates(test_domain.clone(), true, false).await
             .expect("failed to purge certs");
 
-        let cert_res = client.create_certificate(&cert_req).await
-            .expect("failed to get cert");
+        let pending_id = pending.certificate().id.clone().unwrap();
+        assert_eq!(fake.certificate_status(&pending_id).as_deref(), Some("cancelled"));
+        assert_eq!(fake.certificate_status(&issued_id).as_deref(), Some("issued"));
 
-        println!("FV: {:#?}", cert_res.certificate().file_validation(&test_domain).unwrap());
+        client.purge_certificates(test_domain, false, true).await
+            .expect("failed to purge certs");
+        assert_eq!(fake.certificate_status(&issued_id).as_deref(), Some("revoked"));
+    }
 
-        println!("OUT: {:#?}", cert_res);
+    #[tokio::test]
+    async fn invalid_api_key_test() {
+        let (_fake, mut client) = fake_client().await;
+        client.api_key = "wrong".to_string();
 
-        assert!(false);
+        let err = client.create_certificate(&cert_req("example.com")).await.unwrap_err();
+        assert!(err.is_api());
+        assert_eq!(err.api_error(), Some(ApiErrorCode::InvalidAccessKey));
     }
 
-    /*
-    async fn dummy_test() {
-        // TODO: REMOVE
+    #[tokio::test]
+    async fn error_kinds_test() {
+        let (fake, client) = fake_client().await;
+
+        let err = client.download_certificate("missing".to_string(), &DownloadOptions::default()).await.unwrap_err();
+        assert_eq!(err.api_error(), Some(ApiErrorCode::CertificateNotFound));
+
+        let err = client.get_certificate("missing".to_string()).await.unwrap_err();
+        assert_eq!(err.api_error(), Some(ApiErrorCode::CertificateNotFound));
+
+        let id = client.create_certificate(&cert_req("example.com")).await.unwrap()
+            .certificate().id.clone().unwrap();
+        let err = client.download_certificate(id, &DownloadOptions::default()).await.unwrap_err();
+        assert_eq!(err.api_error(), Some(ApiErrorCode::CertificateNotIssued));
+
+        fake.fail_next(400, None);
+        let err = client.create_certificate(&cert_req("example.com")).await.unwrap_err();
+        assert!(err.is_http());
+        assert_eq!(err.status(), Some(StatusCode::BAD_REQUEST));
+        assert!(!err.is_retryable());
+
+        let err = client.purge_certificates("example.com".to_string(), false, false).await.unwrap_err();
+        assert!(err.is_validation());
+    }
+
+    #[tokio::test]
+    async fn client_builder_test() {
+        let fake = FakeZeroSsl::start(TEST_API_KEY.to_string()).await.unwrap();
+
+        let client = Client::builder(TEST_API_KEY.to_string())
+            .with_api_url(format!("{}/", fake.url()))
+            .with_connect_timeout(Duration::from_secs(5))
+            .with_timeout(Duration::from_secs(30))
+            .with_user_agent("zerossl-test".to_string())
+            .build()
+            .unwrap();
+        assert_eq!(client.api_url(), fake.url());
+
+        let shared = Client::builder(TEST_API_KEY.to_string())
+            .with_api_url(fake.url())
+            .with_http_client(reqwest::Client::new())
+            .build()
+            .unwrap();
+
+        // Both the built and the injected http client reach the server
+        let id = client.create_certificate(&cert_req("example.com")).await.unwrap()
+            .certificate().id.clone().unwrap();
+        assert_eq!(fake.last_user_agent().as_deref(), Some("zerossl-test"));
+        shared.cancel_certificate(id.clone()).await.unwrap();
+        assert_eq!(fake.certificate_status(&id).as_deref(), Some("cancelled"));
+
+        // Client::new uses the builder defaults
+        let mut plain = Client::new(TEST_API_KEY.to_string());
+        plain.api_url = fake.url();
+        plain.get_certificates(&ListCertificatesReq::default()).await.unwrap();
+        assert_eq!(fake.last_user_agent().as_deref(), Some(DEFAULT_USER_AGENT));
+
+        let slow = Client::builder(TEST_API_KEY.to_string())
+            .with_api_url(fake.url())
+            .with_timeout(Duration::from_millis(100))
+            .with_retry_policy(RetryPolicy::none())
+            .build()
+            .unwrap();
+        fake.set_response_delay(Duration::from_secs(1));
+        let err = slow.get_certificates(&ListCertificatesReq::default()).await.unwrap_err();
+        assert!(err.is_request());
+        assert!(err.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn retry_test() {
+        let fake = FakeZeroSsl::start(TEST_API_KEY.to_string()).await.unwrap();
+        let events: Arc<Mutex<Vec<RetryEvent>>> = Arc::new(Mutex::new(Vec::new()));
+
+        let hook_events = events.clone();
+        let mut policy = RetryPolicy::default();
+        policy.with_base_delay(Duration::from_millis(1))
+            .with_hook(Arc::new(move |e: &RetryEvent| hook_events.lock().unwrap().push(e.clone())));
+
+        let client = Client::builder(TEST_API_KEY.to_string())
+            .with_api_url(fake.url())
+            .with_retry_policy(policy)
+            .build()
+            .unwrap();
+
+        // Rate limited requests are retried, even when creating
+        fake.fail_next(429, Some(0));
+        let id = client.create_certificate(&cert_req("example.com")).await.unwrap()
+            .certificate().id.clone().unwrap();
+        assert_eq!(fake.certificate_count(), 1);
+
+        // 5xx is retried for safe operations, until exhausted
+        fake.fail_next(503, None);
+        client.get_certificates(&ListCertificatesReq::default()).await.unwrap();
+
+        fake.fail_next(503, None);
+        fake.fail_next(503, None);
+        fake.fail_next(503, None);
+        let err = client.download_certificate(id, &DownloadOptions::default()).await.unwrap_err();
+        assert_eq!(err.status(), Some(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(err.is_retryable());
+
+        // ..but not when creating
+        fake.fail_next(500, None);
+        assert!(client.create_certificate(&cert_req("example.com")).await.is_err());
+        assert_eq!(fake.certificate_count(), 1);
+
+        // Errors ZeroSSL reports in a 200 body are retried when the error type says so
+        fake.fail_next_api(0, "rate_limit_reached");
+        client.create_certificate(&cert_req("example.com")).await.unwrap();
+        assert_eq!(fake.certificate_count(), 2);
+
+        fake.fail_next_api(0, "rate_limit_reached");
+        fake.fail_next_api(0, "rate_limit_reached");
+        fake.fail_next_api(0, "rate_limit_reached");
+        let err = client.get_certificates(&ListCertificatesReq::default()).await.unwrap_err();
+        assert_eq!(err.api_error(), Some(ApiErrorCode::RateLimitReached));
+        assert!(err.is_retryable());
+
+        fake.fail_next_api(2832, "certificate_not_found");
+        let err = client.get_certificate("missing".to_string()).await.unwrap_err();
+        assert!(!err.is_retryable());
+
+        // ..but a failed create is sent once, it may have gone through anyway
+        let sent = fake.request_count();
+        fake.fail_next_api(0, "failed_creating_certificate");
+        let err = client.create_certificate(&cert_req("example.com")).await.unwrap_err();
+        assert_eq!(err.api_error(), Some(ApiErrorCode::FailedCreatingCertificate));
+        assert_eq!(fake.request_count(), sent + 1);
+
+        let events = events.lock().unwrap();
+        let retried = events.iter()
+            .filter(|e| e.decision.delay().is_some())
+            .count();
+        assert_eq!(retried, 7);
+        assert_eq!(events[0].decision, RetryDecision::Retry(Duration::ZERO));
+        assert_eq!(events[4].decision, RetryDecision::Exhausted);
+        assert_eq!(events[5].decision, RetryDecision::NotRetryable);
+        assert_eq!(events[6].cause, RetryCause::Api(ApiErrorCode::RateLimitReached));
+        assert_eq!(events[9].decision, RetryDecision::Exhausted);
+        assert_eq!(events[10].decision, RetryDecision::NotRetryable);
+        assert_eq!(events.len(), 11);
+        assert_eq!(fake.request_count(), 15);
+    }
+
+    #[tokio::test]
+    async fn wait_for_status_test() {
+        let (fake, client) = fake_client().await;
+        fake.set_pending_polls(2);
+
+        let progress: Arc<Mutex<Vec<WaitProgress>>> = Arc::new(Mutex::new(Vec::new()));
+        let hook_progress = progress.clone();
+        let mut opts = WaitOptions::default();
+        opts.with_initial_interval(Duration::from_millis(1))
+            .with_deadline(Duration::from_secs(10))
+            .with_on_progress(Arc::new(move |p: &WaitProgress| hook_progress.lock().unwrap().push(p.clone())));
+
+        let id = client.create_certificate(&cert_req("example.com")).await.unwrap()
+            .certificate().id.clone().unwrap();
+        client.verify_certificate(id.clone(), &VerifyCertificateReq::new(ValidationType::HttpCsrHash, None)).await
+            .unwrap();
+
+        let cert = client.wait_for_status(id, CertificateStatus::Issued, &opts).await
+            .expect("failed to wait for cert");
+        assert_eq!(cert.status, Some(CertificateStatus::Issued));
+
+        let progress = progress.lock().unwrap();
+        assert_eq!(progress.len(), 3);
+        assert_eq!(progress[0].status, Some(CertificateStatus::PendingValidation));
+        assert!(progress[2].next_poll.is_none());
+    }
+
+    #[tokio::test]
+    async fn wait_for_status_terminal_and_timeout_test() {
+        let (fake, client) = fake_client().await;
+        let mut opts = WaitOptions::default();
+        opts.with_initial_interval(Duration::from_millis(5))
+            .with_deadline(Duration::from_millis(50));
+
+        let id = client.create_certificate(&cert_req("example.com")).await.unwrap()
+            .certificate().id.clone().unwrap();
+
+        let err = client.wait_for_status(id.clone(), CertificateStatus::Issued, &opts).await.unwrap_err();
+        assert!(err.is_timeout());
+
+        fake.set_certificate_status(&id, "cancelled");
+        let cert = client.wait_for_status(id, CertificateStatus::Issued, &opts).await.unwrap();
+        assert_eq!(cert.status, Some(CertificateStatus::Cancelled));
+    }
+
+    #[tokio::test]
+    async fn list_all_certificates_test() {
+        let (fake, client) = fake_client().await;
+
+        for _ in 0..7 {
+            client.create_certificate(&cert_req("page.example.com")).await.unwrap();
+        }
+        client.create_certificate(&cert_req("other.example.com")).await.unwrap();
+
+        let mut req = ListCertificatesReq::for_search("page.example.com".to_string());
+        req.with_limit(3);
+
+        let first = client.get_certificates(&req).await.unwrap();
+        assert_eq!(first.total_count(), Some(7));
+        assert_eq!(first.result_count(), Some(3));
+        assert_eq!(first.page(), Some(1));
+
+        let requests = fake.request_count();
+        let certs: Vec<Certificate> = client.list_all_certificates(&req)
+            .try_collect().await.unwrap();
+        assert_eq!(certs.len(), 7);
+        assert_eq!(fake.request_count() - requests, 3);
+
+        // Pages are fetched lazily
+        let requests = fake.request_count();
+        let first_two: Vec<Certificate> = client.list_all_certificates(&req)
+            .take(2)
+            .try_collect().await.unwrap();
+        assert_eq!(first_two.len(), 2);
+        assert_eq!(fake.request_count() - requests, 1);
+
+        client.purge_certificates("page.example.com".to_string(), true, false).await.unwrap();
+        let pending = client.get_pending_certificates("page.example.com".to_string()).await.unwrap();
+        assert_eq!(pending.total_count(), Some(0));
+    }
+
+    #[tokio::test]
+    async fn validation_status_test() {
+        let (fake, client) = fake_client().await;
+        fake.fail_validation("www.status.example.com");
+
+        let pkey = generate_rsa_2048_priv_key().unwrap();
+        let mut csr = Csr::new("status.example.com".to_string());
+        let csr = csr.with_alt_names(vec!["www.status.example.com".to_string()]);
+        let req = CreateCertificateReq::from_csr(&pkey, csr).unwrap();
+
+        let id = client.create_certificate(&req).await.unwrap()
+            .certificate().id.clone().unwrap();
+
+        let err = client.verify_certificate(id.clone(), &VerifyCertificateReq::new(ValidationType::CnameCsrHash, None))
+            .await.unwrap_err();
+        assert_eq!(err.api_error(), Some(ApiErrorCode::DomainControlValidationFailed));
+
+        let status = client.validation_status(id).await
+            .expect("failed to get validation status");
+        assert!(!status.is_completed());
+        assert_eq!(status.failed_domains(), vec!["www.status.example.com".to_string()]);
+        assert_eq!(status.domain("status.example.com").unwrap().is_passed(), Some(true));
+        assert_eq!(status.domain("status.example.com").unwrap().method, Some(ValidationType::CnameCsrHash));
+    }
+
+    #[tokio::test]
+    async fn email_validation_test() {
+        let (fake, client) = fake_client().await;
+        fake.set_pending_polls(1);
+
+        let pkey = generate_rsa_2048_priv_key().unwrap();
+        let mut csr = Csr::new("mail.example.com".to_string());
+        let csr = csr.with_alt_names(vec!["www.mail.example.com".to_string()]);
+        let req = CreateCertificateReq::from_csr(&pkey, csr).unwrap();
+
+        let cert = client.create_certificate(&req).await.unwrap().certificate().clone();
+        let id = cert.id.clone().unwrap();
+        assert!(cert.email_approvers("www.mail.example.com").contains(&"admin@mail.example.com".to_string()));
+
+        // Not pending email validation yet
+        assert!(client.resend_verification_email(id.clone()).await.is_err());
+
+        let err = client.verify_certificate(id.clone(),
+                                            &VerifyCertificateReq::for_email(vec!["admin@mail.example.com".to_string()]))
+            .await.unwrap_err();
+        assert_eq!(err.api_error(), Some(ApiErrorCode::InvalidValidationEmail));
+
+        let verify_req = VerifyCertificateReq::for_email_approvers(&cert, &["hostmaster"]).unwrap();
+        assert_eq!(verify_req.validation_emails(), vec![
+            "hostmaster@mail.example.com".to_string(),
+            "hostmaster@www.mail.example.com".to_string(),
+        ]);
+        client.verify_certificate(id.clone(), &verify_req).await.unwrap();
+
+        client.resend_verification_email(id.clone()).await
+            .expect("failed to resend verification email");
+        assert_eq!(fake.email_resend_count(&id), 1);
+    }
+
+    #[tokio::test]
+    async fn validate_csr_test() {
+        let (_fake, client) = fake_client().await;
+
+        let pkey = generate_rsa_2048_priv_key().unwrap();
+        let mut csr = Csr::new("example.com".to_string());
+        let csr = csr.with_alt_names(vec!["example.com".to_string()]);
+        let pem = String::from_utf8(generate_csr(&pkey, csr).unwrap().to_pem().unwrap()).unwrap();
+
+        let res = client.validate_csr(pem).await.expect("failed to validate csr");
+        assert!(res.is_valid());
+        assert!(res.error().is_none());
+
+        let res = client.validate_csr("not a csr".to_string()).await.expect("failed to validate csr");
+        assert!(!res.is_valid());
+        assert_eq!(res.error().map(|e| e.error_code()), Some(ApiErrorCode::InvalidCertificateCsr));
+    }
+
+    #[tokio::test]
+    async fn generate_eab_credentials_test() {
+        let (fake, mut client) = fake_client().await;
+
+        let creds = client.generate_eab_credentials().await
+            .expect("failed to generate eab credentials");
+        assert_eq!(creds.hmac_key.len(), 32);
+
+        client.api_key = "".to_string();
+        let creds_email = client.generate_eab_credentials_for_email("ops@example.com".to_string()).await
+            .expect("failed to generate eab credentials");
+        assert_ne!(creds.kid, creds_email.kid);
+        assert_eq!(fake.eab_credentials(&creds_email.kid), Some(creds_email.hmac_key));
+
+        let err = client.generate_eab_credentials_for_email("".to_string()).await.unwrap_err();
+        assert!(err.is_api());
+        assert!(client.generate_eab_credentials().await.unwrap_err().is_api());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires API_KEY and a live domain"]
+    async fn live_test() {
         let test_domain = "dev.getlit.sh".to_string();
+
         let api_key = env::var("API_KEY").unwrap();
         let client = Client::new(api_key);
 
-        client.purge_certificates(test_domain, true, false).await
+        client.purge_certificates(test_domain.clone(), true, false).await
             .expect("failed to purge certs");
 
-        assert!(false);
-    }
+        let cert_res = client.create_certificate(&cert_req(&test_domain)).await
+            .expect("failed to get cert");
 
-     */
-}
\ No newline at end of file
+        assert!(cert_res.certificate().file_validation(&test_domain).is_some());
+    }
+}
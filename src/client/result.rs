@@ -17,7 +17,10 @@ pub trait Resp {
     }
 
     fn to_err(&self) -> crate::error::Error {
-        crate::error::request(format!("request failed"), self.err_msg_string())
+        match self.err_msg() {
+            Some(err_msg) => crate::error::api(err_msg),
+            None => crate::error::api_failed(),
+        }
     }
 }
 
@@ -74,8 +77,24 @@ pub struct ErrorMsg {
 }
 
 impl ErrorMsg {
+    pub fn new(code: Option<i32>, typ: Option<String>, details: Option<Value>) -> Self {
+        Self { code, typ, details }
+    }
+
+    /// The error type (or failing that the numeric code) as an `ApiErrorCode`.
+    pub fn error_code(&self) -> ApiErrorCode {
+        if let Some(typ) = self.typ.as_ref() {
+            return ApiErrorCode::from(typ.as_str());
+        }
+        if let Some(code) = self.code {
+            return ApiErrorCode::from_code(code);
+        }
+
+        ApiErrorCode::Unknown("".to_string())
+    }
+
     pub fn code(&self) -> Option<i32> {
-        self.code.clone()
+        self.code
     }
 
     pub fn typ(&self) -> Option<String> {
@@ -105,4 +124,238 @@ impl Display for ErrorMsg {
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// Error types documented by ZeroSSL, as found in `error.type`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ApiErrorCode {
+    // General
+    InternalError,
+    InvalidAccessKey,
+    MissingAccessKey,
+    InactiveUser,
+    InvalidApiFunction,
+    PermissionDenied,
+    RateLimitReached,
+    // Create
+    InvalidCertificateType,
+    MissingCertificateType,
+    InvalidCertificateValidity,
+    InvalidCertificateDomain,
+    MissingCertificateDomains,
+    WildcardDomainsNotAllowed,
+    IpAddressDomainsNotAllowed,
+    DuplicateCertificateDomains,
+    InvalidCertificateCsr,
+    MissingCertificateCsr,
+    CertificateLimitReached,
+    FailedCreatingCertificate,
+    // Lookup
+    CertificateNotFound,
+    InvalidCertificateId,
+    // Validation
+    InvalidValidationMethod,
+    MissingValidationMethod,
+    InvalidValidationEmail,
+    CertificateNotReadyToValidate,
+    DomainControlValidationFailed,
+    FailedValidatingCertificate,
+    // Cancel / revoke / download
+    CertificateCannotBeCancelled,
+    FailedCancellingCertificate,
+    CertificateCannotBeRevoked,
+    FailedRevokingCertificate,
+    CertificateNotIssued,
+    FailedDownloadingCertificate,
+    /// A type (or code) this crate does not know about yet.
+    Unknown(String),
+}
+
+impl ApiErrorCode {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ApiErrorCode::InternalError => "internal_error",
+            ApiErrorCode::InvalidAccessKey => "invalid_access_key",
+            ApiErrorCode::MissingAccessKey => "missing_access_key",
+            ApiErrorCode::InactiveUser => "inactive_user",
+            ApiErrorCode::InvalidApiFunction => "invalid_api_function",
+            ApiErrorCode::PermissionDenied => "permission_denied",
+            ApiErrorCode::RateLimitReached => "rate_limit_reached",
+            ApiErrorCode::InvalidCertificateType => "invalid_certificate_type",
+            ApiErrorCode::MissingCertificateType => "missing_certificate_type",
+            ApiErrorCode::InvalidCertificateValidity => "invalid_certificate_validity",
+            ApiErrorCode::InvalidCertificateDomain => "invalid_certificate_domain",
+            ApiErrorCode::MissingCertificateDomains => "missing_certificate_domains",
+            ApiErrorCode::WildcardDomainsNotAllowed => "wildcard_domains_not_allowed",
+            ApiErrorCode::IpAddressDomainsNotAllowed => "ip_address_domains_not_allowed",
+            ApiErrorCode::DuplicateCertificateDomains => "duplicate_certificate_domains",
+            ApiErrorCode::InvalidCertificateCsr => "invalid_certificate_csr",
+            ApiErrorCode::MissingCertificateCsr => "missing_certificate_csr",
+            ApiErrorCode::CertificateLimitReached => "certificate_limit_reached",
+            ApiErrorCode::FailedCreatingCertificate => "failed_creating_certificate",
+            ApiErrorCode::CertificateNotFound => "certificate_not_found",
+            ApiErrorCode::InvalidCertificateId => "invalid_certificate_id",
+            ApiErrorCode::InvalidValidationMethod => "invalid_validation_method",
+            ApiErrorCode::MissingValidationMethod => "missing_validation_method",
+            ApiErrorCode::InvalidValidationEmail => "invalid_validation_email",
+            ApiErrorCode::CertificateNotReadyToValidate => "certificate_not_ready_to_validate",
+            ApiErrorCode::DomainControlValidationFailed => "domain_control_validation_failed",
+            ApiErrorCode::FailedValidatingCertificate => "failed_validating_certificate",
+            ApiErrorCode::CertificateCannotBeCancelled => "certificate_cannot_be_cancelled",
+            ApiErrorCode::FailedCancellingCertificate => "failed_cancelling_certificate",
+            ApiErrorCode::CertificateCannotBeRevoked => "certificate_cannot_be_revoked",
+            ApiErrorCode::FailedRevokingCertificate => "failed_revoking_certificate",
+            ApiErrorCode::CertificateNotIssued => ERR_TYPE_CERTIFICATE_NOT_ISSUED,
+            ApiErrorCode::FailedDownloadingCertificate => "failed_downloading_certificate",
+            ApiErrorCode::Unknown(typ) => typ.as_str(),
+        }
+    }
+
+    /// Maps the numeric codes documented by ZeroSSL. Types are preferred,
+    /// codes are only used when no type was returned.
+    pub fn from_code(code: i32) -> Self {
+        match code {
+            0 => ApiErrorCode::InternalError,
+            101 => ApiErrorCode::InvalidAccessKey,
+            102 => ApiErrorCode::InactiveUser,
+            103 => ApiErrorCode::InvalidApiFunction,
+            429 => ApiErrorCode::RateLimitReached,
+            2800 => ApiErrorCode::InvalidCertificateType,
+            2801 => ApiErrorCode::MissingCertificateType,
+            2802 => ApiErrorCode::InvalidCertificateValidity,
+            2803 => ApiErrorCode::InvalidCertificateDomain,
+            2804 => ApiErrorCode::WildcardDomainsNotAllowed,
+            2805 => ApiErrorCode::IpAddressDomainsNotAllowed,
+            2806 => ApiErrorCode::DuplicateCertificateDomains,
+            2807 => ApiErrorCode::MissingCertificateDomains,
+            2808 => ApiErrorCode::InvalidCertificateCsr,
+            2809 => ApiErrorCode::MissingCertificateCsr,
+            2822 => ApiErrorCode::MissingValidationMethod,
+            2823 => ApiErrorCode::InvalidValidationMethod,
+            2824 => ApiErrorCode::InvalidValidationEmail,
+            2831 => ApiErrorCode::CertificateNotReadyToValidate,
+            2832 => ApiErrorCode::CertificateNotFound,
+            2833 => ApiErrorCode::CertificateNotIssued,
+            2834 => ApiErrorCode::CertificateCannotBeCancelled,
+            other => ApiErrorCode::Unknown(other.to_string()),
+        }
+    }
+
+    /// Failures caused by ZeroSSL itself rather than the request, which may
+    /// succeed when sent again.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            // Server side errors, nothing about the request was wrong
+            ApiErrorCode::InternalError => true,
+            // Goes away once the rate limit window has passed
+            ApiErrorCode::RateLimitReached => true,
+            // ZeroSSL failed to carry out a request it accepted as valid. Failed
+            // validation isn't one of them, it means the challenge isn't in place yet.
+            ApiErrorCode::FailedCreatingCertificate | ApiErrorCode::FailedCancellingCertificate
+            | ApiErrorCode::FailedRevokingCertificate | ApiErrorCode::FailedDownloadingCertificate => true,
+            _ => false,
+        }
+    }
+
+    /// Caused by the API key or account rather than the request.
+    pub fn is_auth(&self) -> bool {
+        matches!(self, ApiErrorCode::InvalidAccessKey | ApiErrorCode::MissingAccessKey
+            | ApiErrorCode::InactiveUser | ApiErrorCode::PermissionDenied)
+    }
+}
+
+impl From<&str> for ApiErrorCode {
+    fn from(typ: &str) -> Self {
+        match typ {
+            "internal_error" => ApiErrorCode::InternalError,
+            "invalid_access_key" => ApiErrorCode::InvalidAccessKey,
+            "missing_access_key" => ApiErrorCode::MissingAccessKey,
+            "inactive_user" => ApiErrorCode::InactiveUser,
+            "invalid_api_function" => ApiErrorCode::InvalidApiFunction,
+            "permission_denied" => ApiErrorCode::PermissionDenied,
+            "rate_limit_reached" => ApiErrorCode::RateLimitReached,
+            "invalid_certificate_type" => ApiErrorCode::InvalidCertificateType,
+            "missing_certificate_type" => ApiErrorCode::MissingCertificateType,
+            "invalid_certificate_validity" => ApiErrorCode::InvalidCertificateValidity,
+            "invalid_certificate_domain" => ApiErrorCode::InvalidCertificateDomain,
+            "missing_certificate_domains" => ApiErrorCode::MissingCertificateDomains,
+            "wildcard_domains_not_allowed" => ApiErrorCode::WildcardDomainsNotAllowed,
+            "ip_address_domains_not_allowed" => ApiErrorCode::IpAddressDomainsNotAllowed,
+            "duplicate_certificate_domains" => ApiErrorCode::DuplicateCertificateDomains,
+            "invalid_certificate_csr" => ApiErrorCode::InvalidCertificateCsr,
+            "missing_certificate_csr" => ApiErrorCode::MissingCertificateCsr,
+            "certificate_limit_reached" => ApiErrorCode::CertificateLimitReached,
+            "failed_creating_certificate" => ApiErrorCode::FailedCreatingCertificate,
+            "certificate_not_found" => ApiErrorCode::CertificateNotFound,
+            "invalid_certificate_id" => ApiErrorCode::InvalidCertificateId,
+            "invalid_validation_method" => ApiErrorCode::InvalidValidationMethod,
+            "missing_validation_method" => ApiErrorCode::MissingValidationMethod,
+            "invalid_validation_email" => ApiErrorCode::InvalidValidationEmail,
+            "certificate_not_ready_to_validate" => ApiErrorCode::CertificateNotReadyToValidate,
+            "domain_control_validation_failed" => ApiErrorCode::DomainControlValidationFailed,
+            "failed_validating_certificate" => ApiErrorCode::FailedValidatingCertificate,
+            "certificate_cannot_be_cancelled" => ApiErrorCode::CertificateCannotBeCancelled,
+            "failed_cancelling_certificate" => ApiErrorCode::FailedCancellingCertificate,
+            "certificate_cannot_be_revoked" => ApiErrorCode::CertificateCannotBeRevoked,
+            "failed_revoking_certificate" => ApiErrorCode::FailedRevokingCertificate,
+            "certificate_not_issued" => ApiErrorCode::CertificateNotIssued,
+            "failed_downloading_certificate" => ApiErrorCode::FailedDownloadingCertificate,
+            other => ApiErrorCode::Unknown(other.to_string()),
+        }
+    }
+}
+
+impl Display for ApiErrorCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::certificates::CreateCertificateRes;
+    use crate::client::result::{ApiErrorCode, Resp};
+
+    #[test]
+    fn error_code_test() {
+        let res: CreateCertificateRes = serde_json::from_str(
+            r#"{"success":false,"error":{"code":0,"type":"domain_control_validation_failed"}}"#).unwrap();
+        let err = res.to_err();
+
+        assert!(err.is_api());
+        assert_eq!(err.api_error(), Some(ApiErrorCode::DomainControlValidationFailed));
+        assert!(!err.is_retryable());
+        assert_eq!(err.status(), None);
+
+        let res: CreateCertificateRes = serde_json::from_str(
+            r#"{"success":false,"error":{"code":101}}"#).unwrap();
+        assert_eq!(res.to_err().api_error(), Some(ApiErrorCode::InvalidAccessKey));
+
+        let res: CreateCertificateRes = serde_json::from_str(
+            r#"{"success":false,"error":{"code":2832}}"#).unwrap();
+        assert_eq!(res.to_err().api_error(), Some(ApiErrorCode::CertificateNotFound));
+
+        let res: CreateCertificateRes = serde_json::from_str(
+            r#"{"success":false,"error":{"code":429}}"#).unwrap();
+        assert_eq!(res.to_err().api_error(), Some(ApiErrorCode::RateLimitReached));
+        assert!(res.to_err().is_retryable());
+
+        assert_eq!(ApiErrorCode::from_code(2808), ApiErrorCode::InvalidCertificateCsr);
+        assert_eq!(ApiErrorCode::from_code(2824), ApiErrorCode::InvalidValidationEmail);
+        assert_eq!(ApiErrorCode::from_code(1234), ApiErrorCode::Unknown("1234".to_string()));
+
+        let res: CreateCertificateRes = serde_json::from_str(
+            r#"{"success":false,"error":{"code":2999,"type":"something_new"}}"#).unwrap();
+        let err = res.to_err();
+        assert_eq!(err.api_error(), Some(ApiErrorCode::Unknown("something_new".to_string())));
+        assert_eq!(err.api_error_msg().and_then(|e| e.code()), Some(2999));
+    }
+
+    #[test]
+    fn retryable_code_test() {
+        assert!(ApiErrorCode::InternalError.is_retryable());
+        assert!(!ApiErrorCode::InvalidCertificateCsr.is_retryable());
+        assert!(!ApiErrorCode::FailedValidatingCertificate.is_retryable());
+        assert!(ApiErrorCode::InactiveUser.is_auth());
+    }
+}
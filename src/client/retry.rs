@@ -0,0 +1,323 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::StatusCode;
+
+use crate::client::result::ApiErrorCode;
+
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+pub const DEFAULT_BASE_DELAY: Duration = Duration::from_millis(500);
+pub const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(30);
+
+pub type RetryHook = Arc<dyn Fn(&RetryEvent) + Send + Sync>;
+
+/// How safe it is to send a request again after an ambiguous failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Idempotency {
+    /// Read only (e.g. listing or downloading certificates).
+    Safe,
+    /// Repeating has the same effect as sending once (e.g. cancel, revoke).
+    Idempotent,
+    /// Repeating may have additional effects (e.g. creating a certificate).
+    /// Only retried when the server cannot have processed the request.
+    NonIdempotent,
+}
+
+impl Idempotency {
+    fn is_repeatable(&self) -> bool {
+        !matches!(self, Idempotency::NonIdempotent)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RetryCause {
+    /// The connection could not be established, so nothing was sent.
+    Connect,
+    Timeout,
+    Status(StatusCode),
+    /// A 200 whose body reports an error (`success: false`).
+    Api(ApiErrorCode),
+    Other,
+}
+
+impl RetryCause {
+    pub fn from_reqwest(e: &reqwest::Error) -> Self {
+        if e.is_connect() {
+            RetryCause::Connect
+        } else if e.is_timeout() {
+            RetryCause::Timeout
+        } else if let Some(status) = e.status() {
+            RetryCause::Status(status)
+        } else {
+            RetryCause::Other
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RetryDecision {
+    Retry(Duration),
+    /// The failure (or the operation) is not safe to retry.
+    NotRetryable,
+    /// The maximum number of attempts has been reached.
+    Exhausted,
+}
+
+impl RetryDecision {
+    pub fn delay(&self) -> Option<Duration> {
+        match self {
+            RetryDecision::Retry(delay) => Some(*delay),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RetryEvent {
+    pub attempt: u32,
+    pub idempotency: Idempotency,
+    pub cause: RetryCause,
+    pub retry_after: Option<Duration>,
+    pub decision: RetryDecision,
+}
+
+#[derive(Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    jitter: bool,
+    respect_retry_after: bool,
+    hook: Option<RetryHook>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            base_delay: DEFAULT_BASE_DELAY,
+            max_delay: DEFAULT_MAX_DELAY,
+            jitter: true,
+            respect_retry_after: true,
+            hook: None,
+        }
+    }
+}
+
+impl fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_attempts", &self.max_attempts)
+            .field("base_delay", &self.base_delay)
+            .field("max_delay", &self.max_delay)
+            .field("jitter", &self.jitter)
+            .field("respect_retry_after", &self.respect_retry_after)
+            .field("hook", &self.hook.is_some())
+            .finish()
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that sends every request exactly once.
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Total attempts including the first one.
+    pub fn with_max_attempts(&mut self, max_attempts: u32) -> &mut Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    pub fn with_base_delay(&mut self, base_delay: Duration) -> &mut Self {
+        self.base_delay = base_delay;
+        self
+    }
+
+    pub fn with_max_delay(&mut self, max_delay: Duration) -> &mut Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    pub fn with_jitter(&mut self, jitter: bool) -> &mut Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Waits as long as the server's `Retry-After` asks, capped at the max delay.
+    pub fn with_respect_retry_after(&mut self, respect_retry_after: bool) -> &mut Self {
+        self.respect_retry_after = respect_retry_after;
+        self
+    }
+
+    /// Called for every failed attempt with the decision that was taken.
+    pub fn with_hook(&mut self, hook: RetryHook) -> &mut Self {
+        self.hook = Some(hook);
+        self
+    }
+
+    // Accessors
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    pub fn base_delay(&self) -> Duration {
+        self.base_delay
+    }
+
+    pub fn max_delay(&self) -> Duration {
+        self.max_delay
+    }
+
+    // Util
+    pub fn is_retryable(&self, idempotency: Idempotency, cause: &RetryCause) -> bool {
+        match cause {
+            RetryCause::Connect => true,
+            RetryCause::Status(StatusCode::TOO_MANY_REQUESTS) => true,
+            RetryCause::Timeout => idempotency.is_repeatable(),
+            RetryCause::Status(status) => idempotency.is_repeatable()
+                && matches!(*status, StatusCode::INTERNAL_SERVER_ERROR | StatusCode::BAD_GATEWAY
+                    | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT),
+            // Turned away before being processed
+            RetryCause::Api(ApiErrorCode::RateLimitReached) => true,
+            RetryCause::Api(code) => idempotency.is_repeatable() && code.is_retryable(),
+            RetryCause::Other => false,
+        }
+    }
+
+    /// Decides what to do after `attempt` (starting at 1) failed with `cause`.
+    pub fn decide(&self, attempt: u32, idempotency: Idempotency, cause: &RetryCause,
+                  retry_after: Option<Duration>) -> RetryDecision {
+        if !self.is_retryable(idempotency, cause) {
+            return RetryDecision::NotRetryable;
+        }
+        if attempt >= self.max_attempts {
+            return RetryDecision::Exhausted;
+        }
+
+        if self.respect_retry_after {
+            if let Some(retry_after) = retry_after {
+                return RetryDecision::Retry(retry_after.min(self.max_delay));
+            }
+        }
+
+        RetryDecision::Retry(self.backoff(attempt))
+    }
+
+    /// Exponential backoff for the delay after `attempt`, with "equal jitter"
+    /// (a random value between half and all of the computed delay).
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exp = attempt.saturating_sub(1).min(31);
+        let delay = self.base_delay
+            .checked_mul(1_u32 << exp)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+
+        if !self.jitter {
+            return delay;
+        }
+
+        let half = delay / 2;
+        half + half.mul_f64(random_unit())
+    }
+
+    pub(crate) fn notify(&self, event: &RetryEvent) {
+        if let Some(hook) = self.hook.as_ref() {
+            hook(event);
+        }
+    }
+}
+
+/// Parses a `Retry-After` header given either in seconds or as an HTTP date.
+pub fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let at = httpdate::parse_http_date(value).ok()?;
+    Some(at.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO))
+}
+
+fn random_unit() -> f64 {
+    let mut buf = [0u8; 4];
+    if openssl::rand::rand_bytes(&mut buf).is_err() {
+        return 0.5;
+    }
+
+    f64::from(u32::from_le_bytes(buf)) / f64::from(u32::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+    use reqwest::StatusCode;
+
+    use crate::client::result::ApiErrorCode;
+    use crate::client::retry::{Idempotency, parse_retry_after, RetryCause, RetryDecision, RetryPolicy};
+
+    #[test]
+    fn decide_test() {
+        let policy = RetryPolicy::default();
+        let unavailable = RetryCause::Status(StatusCode::SERVICE_UNAVAILABLE);
+        let rate_limited = RetryCause::Status(StatusCode::TOO_MANY_REQUESTS);
+
+        assert!(matches!(policy.decide(1, Idempotency::Safe, &unavailable, None), RetryDecision::Retry(_)));
+        assert_eq!(policy.decide(1, Idempotency::NonIdempotent, &unavailable, None), RetryDecision::NotRetryable);
+        assert!(matches!(policy.decide(1, Idempotency::NonIdempotent, &rate_limited, None), RetryDecision::Retry(_)));
+        assert!(matches!(policy.decide(1, Idempotency::NonIdempotent, &RetryCause::Connect, None), RetryDecision::Retry(_)));
+        assert_eq!(policy.decide(1, Idempotency::Safe, &RetryCause::Status(StatusCode::BAD_REQUEST), None),
+                   RetryDecision::NotRetryable);
+        assert_eq!(policy.decide(3, Idempotency::Safe, &unavailable, None), RetryDecision::Exhausted);
+        assert_eq!(policy.decide(1, Idempotency::Safe, &rate_limited, Some(Duration::from_secs(7))),
+                   RetryDecision::Retry(Duration::from_secs(7)));
+        assert!(matches!(policy.decide(1, Idempotency::NonIdempotent, &RetryCause::Api(ApiErrorCode::RateLimitReached), None),
+                         RetryDecision::Retry(_)));
+        assert_eq!(policy.decide(1, Idempotency::NonIdempotent, &RetryCause::Api(ApiErrorCode::InternalError), None),
+                   RetryDecision::NotRetryable);
+        assert!(matches!(policy.decide(1, Idempotency::Safe, &RetryCause::Api(ApiErrorCode::InternalError), None),
+                         RetryDecision::Retry(_)));
+        assert_eq!(policy.decide(1, Idempotency::Safe, &rate_limited, Some(Duration::from_secs(3600))),
+                   RetryDecision::Retry(policy.max_delay()));
+    }
+
+    #[test]
+    fn backoff_test() {
+        let mut policy = RetryPolicy::default();
+        policy.with_base_delay(Duration::from_millis(100))
+            .with_max_delay(Duration::from_millis(350))
+            .with_jitter(false);
+
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(350));
+        assert_eq!(policy.backoff(100), Duration::from_millis(350));
+
+        policy.with_jitter(true);
+        for attempt in 1..5 {
+            let delay = policy.backoff(attempt);
+            assert!(delay <= Duration::from_millis(350));
+            assert!(delay >= Duration::from_millis(50));
+        }
+    }
+
+    #[test]
+    fn parse_retry_after_test() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(parse_retry_after(&headers), None);
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+        assert_eq!(parse_retry_after(&headers), Some(Duration::from_secs(120)));
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"));
+        assert_eq!(parse_retry_after(&headers), Some(Duration::ZERO));
+    }
+}
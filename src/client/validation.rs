@@ -1,8 +1,12 @@
 use std::collections::HashMap;
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use reqwest::Url;
+use serde_json::Value;
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::client::result::{ErrorMsg, Resp, ResultStatus};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum ValidationType {
     Email,
@@ -11,25 +15,87 @@ pub enum ValidationType {
     HttpsCsrHash
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ValidationOptions {
     pub email_validation: Option<HashMap<String, Vec<String>>>,
     pub other_methods: Option<HashMap<String, OtherValidation>>,
 }
 
+// Local parts ZeroSSL may offer for email validation, most commonly monitored first
+pub static DEFAULT_APPROVER_PREFERENCE: [&str; 5] = ["admin", "administrator", "hostmaster", "postmaster", "webmaster"];
+
 impl ValidationOptions {
-    pub fn file_validation(&self, domain: &String) -> Option<(String, Vec<String>)> {
-        if let Some(other_methods) = self.other_methods.as_ref() {
-            if let Some(other_validation) = other_methods.get(domain) {
-                return other_validation.file_validation();
+    /// The addresses ZeroSSL accepts as approvers for `domain`.
+    pub fn email_approvers(&self, domain: &str) -> Vec<String> {
+        if let Some(email_validation) = self.email_validation.as_ref() {
+            if let Some(emails) = email_validation.get(domain) {
+                return emails.clone();
             }
         }
 
-        None
+        Vec::new()
+    }
+
+    /// Picks an approver for `domain`, trying the local parts in `preference`
+    /// in order (closest domain first), then falling back to the first offered.
+    /// An empty `preference` uses `DEFAULT_APPROVER_PREFERENCE`.
+    pub fn pick_email_approver(&self, domain: &str, preference: &[&str]) -> Option<String> {
+        let approvers = self.email_approvers(domain);
+        let preference = if preference.is_empty() {
+            &DEFAULT_APPROVER_PREFERENCE[..]
+        } else {
+            preference
+        };
+
+        for local_part in preference {
+            let found = approvers.iter()
+                .filter(|e| e.split('@').next()
+                    .map(|l| l.eq_ignore_ascii_case(local_part))
+                    .unwrap_or(false))
+                .max_by_key(|e| e.len());
+            if let Some(found) = found {
+                return Some(found.clone());
+            }
+        }
+
+        approvers.first().cloned()
+    }
+
+    pub fn file_validation(&self, domain: &str) -> Option<FileChallenge> {
+        self.other_methods.as_ref()
+            .and_then(|other_methods| other_methods.get(domain))
+            .and_then(|other_validation| other_validation.file_validation(domain))
+    }
+
+    pub fn cname_validation(&self, domain: &str) -> Option<CnameChallenge> {
+        self.other_methods.as_ref()
+            .and_then(|other_methods| other_methods.get(domain))
+            .and_then(|other_validation| other_validation.cname_validation(domain))
+    }
+
+    /// Every way `domain` can be validated, in the order file, CNAME, email.
+    pub fn challenges(&self, domain: &str) -> Vec<Challenge> {
+        let mut challenges = Vec::new();
+
+        if let Some(file) = self.file_validation(domain) {
+            challenges.push(Challenge::File(file));
+        }
+        if let Some(cname) = self.cname_validation(domain) {
+            challenges.push(Challenge::Cname(cname));
+        }
+        let approvers = self.email_approvers(domain);
+        if !approvers.is_empty() {
+            challenges.push(Challenge::Email(EmailChallenge {
+                domain: domain.to_string(),
+                approvers,
+            }));
+        }
+
+        challenges
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OtherValidation {
     pub file_validation_url_http: Option<String>,
     pub file_validation_url_https: Option<String>,
@@ -39,20 +105,352 @@ pub struct OtherValidation {
 }
 
 impl OtherValidation {
-    pub fn file_validation(&self) -> Option<(String, Vec<String>)> {
-        if let Some(file_validation_url_https) = self.file_validation_url_https.as_ref() {
-            if let Some(file_validation_content) = self.file_validation_content.as_ref() {
-                let parts: Vec<&str> = file_validation_url_https.split("/").collect();
-                if parts.len() > 0 {
-                    let id_part = parts.last().unwrap();
-                    let parts: Vec<&str> = id_part.split(".").collect();
-                    if parts.len() > 0 {
-                        return Some((parts.first().unwrap().to_string(), file_validation_content.clone()))
-                    }
-                }
+    /// The validation file for `domain`. The path is taken from the HTTP URL,
+    /// falling back to the HTTPS one.
+    pub fn file_validation(&self, domain: &str) -> Option<FileChallenge> {
+        let content = self.file_validation_content.clone()?;
+        let url = self.file_validation_url_http.as_ref()
+            .or(self.file_validation_url_https.as_ref())?;
+
+        let url = Url::parse(url).ok()?;
+        let path = url.path().to_string();
+        let file_name = url.path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .filter(|name| !name.is_empty())?
+            .to_string();
+
+        Some(FileChallenge {
+            domain: domain.to_string(),
+            http_url: self.file_validation_url_http.clone(),
+            https_url: self.file_validation_url_https.clone(),
+            path,
+            file_name,
+            content,
+        })
+    }
+
+    pub fn cname_validation(&self, domain: &str) -> Option<CnameChallenge> {
+        match (self.cname_validation_p1.as_ref(), self.cname_validation_p2.as_ref()) {
+            (Some(name), Some(target)) => Some(CnameChallenge {
+                domain: domain.to_string(),
+                name: name.clone(),
+                target: target.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+// Challenges
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Challenge {
+    /// `HTTP_CSR_HASH` / `HTTPS_CSR_HASH`
+    File(FileChallenge),
+    /// `CNAME_CSR_HASH`
+    Cname(CnameChallenge),
+    /// `EMAIL`
+    Email(EmailChallenge),
+}
+
+impl Challenge {
+    pub fn domain(&self) -> &str {
+        match self {
+            Challenge::File(c) => &c.domain,
+            Challenge::Cname(c) => &c.domain,
+            Challenge::Email(c) => &c.domain,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileChallenge {
+    pub domain: String,
+    pub http_url: Option<String>,
+    pub https_url: Option<String>,
+    /// e.g. `/.well-known/pki-validation/<hash>.txt`
+    pub path: String,
+    pub file_name: String,
+    /// One entry per line
+    pub content: Vec<String>,
+}
+
+impl FileChallenge {
+    /// The file content as served.
+    pub fn body(&self) -> String {
+        self.content.join("\n")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CnameChallenge {
+    pub domain: String,
+    /// The record to create, e.g. `_<hash>.example.com`
+    pub name: String,
+    /// What it must point to
+    pub target: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmailChallenge {
+    pub domain: String,
+    pub approvers: Vec<String>,
+}
+
+// Validation Status
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValidationStatus {
+    #[serde(flatten)]
+    pub(crate) result_status: ResultStatus,
+
+    // Actual response
+    #[serde(default, deserialize_with = "de_opt_bool")]
+    pub validation_completed: Option<bool>,
+    #[serde(default)]
+    pub details: HashMap<String, DomainValidationStatus>,
+}
+
+impl ValidationStatus {
+    pub fn is_completed(&self) -> bool {
+        self.validation_completed.unwrap_or(false)
+    }
+
+    pub fn domain(&self, domain: &str) -> Option<&DomainValidationStatus> {
+        self.details.get(domain)
+    }
+
+    /// Domains whose check has run and did not pass.
+    pub fn failed_domains(&self) -> Vec<String> {
+        let mut failed: Vec<String> = self.details.iter()
+            .filter(|(_, status)| status.is_passed() == Some(false))
+            .map(|(domain, _)| domain.clone())
+            .collect();
+        failed.sort();
+
+        failed
+    }
+}
+
+impl Resp for ValidationStatus {
+    fn is_ok(&self) -> bool {
+        self.result_status.is_ok()
+    }
+
+    fn err_msg(&self) -> Option<ErrorMsg> {
+        self.result_status.err_msg()
+    }
+}
+
+/// The DCV result for a single domain. Which fields are set depends on the method.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DomainValidationStatus {
+    pub method: Option<ValidationType>,
+    pub status: Option<String>,
+    // CNAME_CSR_HASH
+    #[serde(default, deserialize_with = "de_opt_bool")]
+    pub cname_found: Option<bool>,
+    #[serde(default, deserialize_with = "de_opt_bool")]
+    pub record_correct: Option<bool>,
+    pub target_host: Option<String>,
+    pub target_record: Option<String>,
+    pub actual_record: Option<String>,
+    // HTTP_CSR_HASH / HTTPS_CSR_HASH
+    #[serde(default, deserialize_with = "de_opt_bool")]
+    pub file_found: Option<bool>,
+    #[serde(default, deserialize_with = "de_opt_bool")]
+    pub content_correct: Option<bool>,
+    pub actual_content: Option<String>,
+    // Anything not covered above
+    #[serde(flatten)]
+    pub other: HashMap<String, Value>,
+}
+
+impl DomainValidationStatus {
+    /// `None` when the result does not say either way (e.g. email validation).
+    pub fn is_passed(&self) -> Option<bool> {
+        if let Some(record_correct) = self.record_correct {
+            return Some(record_correct && self.cname_found.unwrap_or(true));
+        }
+        if let Some(content_correct) = self.content_correct {
+            return Some(content_correct && self.file_found.unwrap_or(true));
+        }
+        if let Some(file_found) = self.file_found {
+            if !file_found {
+                return Some(false);
+            }
+        }
+        if let Some(cname_found) = self.cname_found {
+            if !cname_found {
+                return Some(false);
             }
         }
 
         None
     }
-}
\ No newline at end of file
+}
+
+// Validate CSR
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ValidateCsrReq {
+    csr: String,
+}
+
+impl ValidateCsrReq {
+    pub fn new(csr: String) -> Self {
+        Self { csr }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValidateCsrRes {
+    #[serde(flatten)]
+    pub(crate) result_status: ResultStatus,
+
+    // Actual response
+    #[serde(default, deserialize_with = "de_opt_bool")]
+    valid: Option<bool>,
+}
+
+impl ValidateCsrRes {
+    pub fn is_valid(&self) -> bool {
+        self.valid.unwrap_or(false)
+    }
+
+    /// Why the CSR was rejected.
+    pub fn error(&self) -> Option<ErrorMsg> {
+        self.result_status.err_msg()
+    }
+}
+
+impl Resp for ValidateCsrRes {
+    // An invalid CSR is a successful response, only a missing `valid` is a failure
+    fn is_ok(&self) -> bool {
+        self.valid.is_some() || (self.result_status.is_ok() && self.result_status.err_msg().is_none())
+    }
+
+    fn err_msg(&self) -> Option<ErrorMsg> {
+        self.result_status.err_msg()
+    }
+}
+
+// ZeroSSL mixes 0/1, "0"/"1" and booleans
+fn de_opt_bool<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<bool>, D::Error> {
+    match Option::<Value>::deserialize(deserializer)? {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::Bool(b)) => Ok(Some(b)),
+        Some(Value::Number(n)) => Ok(Some(n.as_i64() != Some(0))),
+        Some(Value::String(s)) => match s.as_str() {
+            "1" | "true" => Ok(Some(true)),
+            "0" | "false" | "" => Ok(Some(false)),
+            other => Err(serde::de::Error::custom(format!("expected a boolean, got: {}", other))),
+        },
+        Some(other) => Err(serde::de::Error::custom(format!("expected a boolean, got: {}", other))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::client::validation::{Challenge, OtherValidation, ValidationOptions, ValidationStatus, ValidationType};
+
+    #[test]
+    fn pick_email_approver_test() {
+        let mut email_validation = HashMap::new();
+        email_validation.insert("www.example.com".to_string(), vec![
+            "admin@example.com".to_string(),
+            "admin@www.example.com".to_string(),
+            "webmaster@example.com".to_string(),
+        ]);
+        let opts = ValidationOptions {
+            email_validation: Some(email_validation),
+            other_methods: None,
+        };
+
+        assert_eq!(opts.email_approvers("www.example.com").len(), 3);
+        assert!(opts.email_approvers("other.com").is_empty());
+        assert_eq!(opts.pick_email_approver("www.example.com", &["admin"]).as_deref(), Some("admin@www.example.com"));
+        assert_eq!(opts.pick_email_approver("www.example.com", &["hostmaster", "webmaster"]).as_deref(),
+                   Some("webmaster@example.com"));
+        assert_eq!(opts.pick_email_approver("www.example.com", &["hostmaster"]).as_deref(), Some("admin@example.com"));
+        assert_eq!(opts.pick_email_approver("www.example.com", &[]).as_deref(), Some("admin@www.example.com"));
+        assert_eq!(opts.pick_email_approver("other.com", &["admin"]), None);
+    }
+
+    #[test]
+    fn file_validation_test() {
+        let other = OtherValidation {
+            file_validation_url_http: Some("http://example.com/.well-known/pki-validation/A1B2.txt".to_string()),
+            file_validation_url_https: Some("https://example.com/.well-known/pki-validation/A1B2.txt".to_string()),
+            file_validation_content: Some(vec!["c1".to_string(), "comodoca.com".to_string(), "u1".to_string()]),
+            cname_validation_p1: Some("_A1B2.example.com".to_string()),
+            cname_validation_p2: Some("c1.u1.comodoca.com".to_string()),
+        };
+
+        let file = other.file_validation("example.com").unwrap();
+        assert_eq!(file.path, "/.well-known/pki-validation/A1B2.txt");
+        assert_eq!(file.file_name, "A1B2.txt");
+        assert_eq!(file.body(), "c1\ncomodoca.com\nu1");
+
+        // HTTP only, with a dotted path
+        let other = OtherValidation {
+            file_validation_url_https: None,
+            file_validation_url_http: Some("http://sub.example.com/.well-known/pki-validation/A1.B2.txt?x=1".to_string()),
+            ..other
+        };
+        let file = other.file_validation("sub.example.com").unwrap();
+        assert_eq!(file.file_name, "A1.B2.txt");
+        assert_eq!(file.https_url, None);
+
+        let mut email_validation = HashMap::new();
+        email_validation.insert("sub.example.com".to_string(), vec!["admin@example.com".to_string()]);
+        let mut other_methods = HashMap::new();
+        other_methods.insert("sub.example.com".to_string(), other);
+        let opts = ValidationOptions {
+            email_validation: Some(email_validation),
+            other_methods: Some(other_methods),
+        };
+
+        let challenges = opts.challenges("sub.example.com");
+        assert_eq!(challenges.len(), 3);
+        assert!(matches!(&challenges[0], Challenge::File(_)));
+        assert!(matches!(&challenges[1], Challenge::Cname(c) if c.name == "_A1B2.example.com"));
+        assert!(matches!(&challenges[2], Challenge::Email(c) if c.approvers.len() == 1));
+        assert!(challenges.iter().all(|c| c.domain() == "sub.example.com"));
+        assert!(opts.challenges("other.com").is_empty());
+    }
+
+    #[test]
+    fn validation_status_test() {
+        let status: ValidationStatus = serde_json::from_str(r#"{
+            "validation_completed": 0,
+            "details": {
+                "example.com": {
+                    "method": "CNAME_CSR_HASH",
+                    "cname_found": 1,
+                    "record_correct": 1,
+                    "target_host": "_A1B2.example.com",
+                    "target_record": "A1.B2.comodoca.com",
+                    "actual_record": "A1.B2.comodoca.com"
+                },
+                "www.example.com": {
+                    "method": "CNAME_CSR_HASH",
+                    "cname_found": 0,
+                    "record_correct": 0,
+                    "target_host": "_A1B2.www.example.com",
+                    "target_record": "A1.B2.comodoca.com",
+                    "actual_record": ""
+                }
+            }
+        }"#).unwrap();
+
+        assert!(!status.is_completed());
+        assert_eq!(status.failed_domains(), vec!["www.example.com".to_string()]);
+
+        let domain = status.domain("example.com").unwrap();
+        assert_eq!(domain.method, Some(ValidationType::CnameCsrHash));
+        assert_eq!(domain.is_passed(), Some(true));
+    }
+}
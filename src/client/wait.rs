@@ -0,0 +1,101 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::client::certificates::CertificateStatus;
+
+pub const DEFAULT_INITIAL_INTERVAL: Duration = Duration::from_secs(2);
+pub const DEFAULT_MAX_INTERVAL: Duration = Duration::from_secs(30);
+pub const DEFAULT_DEADLINE: Duration = Duration::from_secs(600);
+
+pub type WaitProgressHook = Arc<dyn Fn(&WaitProgress) + Send + Sync>;
+
+/// Reported after every poll in `Client::wait_for_status`.
+#[derive(Debug, Clone)]
+pub struct WaitProgress {
+    pub attempt: u32,
+    pub elapsed: Duration,
+    pub status: Option<CertificateStatus>,
+    /// How long until the next poll, `None` when polling has finished.
+    pub next_poll: Option<Duration>,
+}
+
+#[derive(Clone)]
+pub struct WaitOptions {
+    initial_interval: Duration,
+    max_interval: Duration,
+    deadline: Duration,
+    on_progress: Option<WaitProgressHook>,
+}
+
+impl Default for WaitOptions {
+    fn default() -> Self {
+        Self {
+            initial_interval: DEFAULT_INITIAL_INTERVAL,
+            max_interval: DEFAULT_MAX_INTERVAL,
+            deadline: DEFAULT_DEADLINE,
+            on_progress: None,
+        }
+    }
+}
+
+impl fmt::Debug for WaitOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WaitOptions")
+            .field("initial_interval", &self.initial_interval)
+            .field("max_interval", &self.max_interval)
+            .field("deadline", &self.deadline)
+            .field("on_progress", &self.on_progress.is_some())
+            .finish()
+    }
+}
+
+impl WaitOptions {
+    /// Delay before the second poll, doubled after each poll up to `max_interval`.
+    pub fn with_initial_interval(&mut self, initial_interval: Duration) -> &mut Self {
+        self.initial_interval = initial_interval;
+        self
+    }
+
+    pub fn with_max_interval(&mut self, max_interval: Duration) -> &mut Self {
+        self.max_interval = max_interval;
+        self
+    }
+
+    /// Overall time allowed, measured from the first poll.
+    pub fn with_deadline(&mut self, deadline: Duration) -> &mut Self {
+        self.deadline = deadline;
+        self
+    }
+
+    pub fn with_on_progress(&mut self, on_progress: WaitProgressHook) -> &mut Self {
+        self.on_progress = Some(on_progress);
+        self
+    }
+
+    // Accessors
+    pub fn initial_interval(&self) -> Duration {
+        self.initial_interval
+    }
+
+    pub fn max_interval(&self) -> Duration {
+        self.max_interval
+    }
+
+    pub fn deadline(&self) -> Duration {
+        self.deadline
+    }
+
+    // Util
+    pub(crate) fn next_interval(&self, interval: Duration) -> Duration {
+        interval.checked_mul(2)
+            .unwrap_or(self.max_interval)
+            .min(self.max_interval)
+    }
+
+    pub(crate) fn notify(&self, progress: &WaitProgress) {
+        if let Some(on_progress) = self.on_progress.as_ref() {
+            on_progress(progress);
+        }
+    }
+}
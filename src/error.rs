@@ -3,6 +3,11 @@ use std::error::Error as StdError;
 use std::fmt;
 use std::io;
 
+use reqwest::StatusCode;
+
+use crate::acme::types::Problem;
+use crate::client::result::{ApiErrorCode, ErrorMsg};
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 pub struct Error {
@@ -15,6 +20,9 @@ struct Inner {
     kind: Kind,
     msg: Option<String>,
     source: Option<BoxError>,
+    status: Option<StatusCode>,
+    api_error: Option<ErrorMsg>,
+    problem: Option<Problem>,
 }
 
 #[allow(dead_code)]
@@ -28,6 +36,9 @@ impl Error {
                 kind,
                 msg,
                 source: source.map(Into::into),
+                status: None,
+                api_error: None,
+                problem: None,
             }),
         }
     }
@@ -38,14 +49,120 @@ impl Error {
             inner: Box::new(Inner {
                 kind,
                 msg,
-                source: None
+                source: None,
+                status: None,
+                api_error: None,
+                problem: None,
             }),
         }
     }
 
+    pub(crate) fn with_status(mut self, status: StatusCode) -> Error {
+        self.inner.status = Some(status);
+        self
+    }
+
+    pub(crate) fn with_api_error(mut self, api_error: ErrorMsg) -> Error {
+        self.inner.api_error = Some(api_error);
+        self
+    }
+
+    pub(crate) fn with_problem(mut self, problem: Problem) -> Error {
+        self.inner.problem = Some(problem);
+        self
+    }
+
     #[allow(unused)]
     pub(crate) fn into_io(self) -> io::Error {
-        io::Error::new(io::ErrorKind::Other, self)
+        io::Error::other(self)
+    }
+
+    /// The ZeroSSL error code, when the API reported one.
+    pub fn api_error(&self) -> Option<ApiErrorCode> {
+        self.inner.api_error.as_ref().map(|e| e.error_code())
+    }
+
+    /// The raw error returned by the API, including its details.
+    pub fn api_error_msg(&self) -> Option<&ErrorMsg> {
+        self.inner.api_error.as_ref()
+    }
+
+    /// The problem document returned by an ACME server.
+    pub fn acme_problem(&self) -> Option<&Problem> {
+        self.inner.problem.as_ref()
+    }
+
+    /// The HTTP status code, when the API answered with something other than 200.
+    pub fn status(&self) -> Option<StatusCode> {
+        if let Some(status) = self.inner.status {
+            return Some(status);
+        }
+
+        self.reqwest_source().and_then(|e| e.status())
+    }
+
+    /// Whether sending the same request again may succeed.
+    pub fn is_retryable(&self) -> bool {
+        if let Some(status) = self.status() {
+            if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+                return true;
+            }
+        }
+
+        if let Some(code) = self.api_error() {
+            if code.is_retryable() {
+                return true;
+            }
+        }
+
+        if let Kind::Request = self.inner.kind {
+            if let Some(e) = self.reqwest_source() {
+                return e.is_connect() || e.is_timeout();
+            }
+        }
+
+        false
+    }
+
+    pub fn is_request(&self) -> bool {
+        matches!(self.inner.kind, Kind::Request)
+    }
+
+    pub fn is_api(&self) -> bool {
+        matches!(self.inner.kind, Kind::Api)
+    }
+
+    pub fn is_acme(&self) -> bool {
+        matches!(self.inner.kind, Kind::Acme)
+    }
+
+    pub fn is_http(&self) -> bool {
+        matches!(self.inner.kind, Kind::Http)
+    }
+
+    pub fn is_decode(&self) -> bool {
+        matches!(self.inner.kind, Kind::Decode)
+    }
+
+    pub fn is_validation(&self) -> bool {
+        matches!(self.inner.kind, Kind::Validation)
+    }
+
+    pub fn is_dns(&self) -> bool {
+        matches!(self.inner.kind, Kind::Dns)
+    }
+
+    pub fn is_timeout(&self) -> bool {
+        matches!(self.inner.kind, Kind::Timeout)
+    }
+
+    pub fn is_io(&self) -> bool {
+        matches!(self.inner.kind, Kind::Io)
+    }
+
+    fn reqwest_source(&self) -> Option<&reqwest::Error> {
+        self.inner.source.as_ref()
+            .and_then(|e| e.downcast_ref::<reqwest::Error>())
     }
 }
 
@@ -55,6 +172,18 @@ impl fmt::Debug for Error {
 
         builder.field("kind", &self.inner.kind);
 
+        if let Some(ref status) = self.inner.status {
+            builder.field("status", status);
+        }
+
+        if let Some(ref api_error) = self.inner.api_error {
+            builder.field("api_error", api_error);
+        }
+
+        if let Some(ref problem) = self.inner.problem {
+            builder.field("problem", problem);
+        }
+
         if let Some(ref msg) = self.inner.msg {
             builder.field("msg", msg);
         }
@@ -71,10 +200,21 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self.inner.kind {
             Kind::Request => f.write_str("request error")?,
+            Kind::Api => f.write_str("api error")?,
+            Kind::Acme => f.write_str("acme error")?,
+            Kind::Http => f.write_str("http error")?,
+            Kind::Decode => f.write_str("decode error")?,
+            Kind::Validation => f.write_str("validation error")?,
+            Kind::Timeout => f.write_str("timeout error")?,
+            Kind::Dns => f.write_str("dns error")?,
             Kind::OpenSSL => f.write_str("openssl error")?,
             Kind::Io => f.write_str("io error")?,
         };
 
+        if let Some(status) = &self.inner.status {
+            write!(f, ": {}", status)?;
+        }
+
         if let Some(msg) = &self.inner.msg {
             write!(f, ": {}", msg)?;
         }
@@ -95,7 +235,22 @@ impl StdError for Error {
 
 #[derive(Debug)]
 pub(crate) enum Kind {
+    /// Sending the request failed (connect, timeout, ...)
     Request,
+    /// ZeroSSL answered with `success: false` and an error
+    Api,
+    /// An ACME server answered with a problem document
+    Acme,
+    /// ZeroSSL answered with a non 200 status
+    Http,
+    /// The response body could not be parsed
+    Decode,
+    /// Invalid input, rejected before anything was sent
+    Validation,
+    /// Gave up waiting for a certificate to reach a status
+    Timeout,
+    /// A DNS server refused or failed a query or update
+    Dns,
     OpenSSL,
     Io,
 }
@@ -106,6 +261,41 @@ pub(crate) fn request<E: Into<BoxError>>(e: E, msg: Option<String>) -> Error {
     Error::new(Kind::Request, msg, Some(e))
 }
 
+pub(crate) fn api(api_error: ErrorMsg) -> Error {
+    Error::new_msg(Kind::Api, Some(format!("{}", api_error)))
+        .with_api_error(api_error)
+}
+
+pub(crate) fn api_failed() -> Error {
+    Error::new_msg(Kind::Api, Some("request failed".to_string()))
+}
+
+pub(crate) fn acme(problem: Problem) -> Error {
+    Error::new_msg(Kind::Acme, Some(format!("{}", problem)))
+        .with_problem(problem)
+}
+
+pub(crate) fn http(status: StatusCode, body: Option<String>) -> Error {
+    Error::new_msg(Kind::Http, body)
+        .with_status(status)
+}
+
+pub(crate) fn decode<E: Into<BoxError>>(e: E, msg: Option<String>) -> Error {
+    Error::new(Kind::Decode, msg, Some(e))
+}
+
+pub(crate) fn validation<S: Into<String>>(msg: S) -> Error {
+    Error::new_msg(Kind::Validation, Some(msg.into()))
+}
+
+pub(crate) fn timeout<S: Into<String>>(msg: S) -> Error {
+    Error::new_msg(Kind::Timeout, Some(msg.into()))
+}
+
+pub(crate) fn dns<S: Into<String>>(msg: S) -> Error {
+    Error::new_msg(Kind::Dns, Some(msg.into()))
+}
+
 pub(crate) fn openssl<E: Into<BoxError>>(e: E, msg: Option<String>) -> Error {
     Error::new(Kind::OpenSSL, msg, Some(e))
 }
@@ -1,9 +1,17 @@
 pub mod error;
 pub mod client;
 pub mod certs;
+pub mod acme;
+pub mod validation;
+mod util;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
 pub use error::{Result, Error};
-pub use certs::csr::{generate_csr, generate_ca, generate_ca_signed_cert, generate_rsa_2048_priv_key};
-pub use client::Client;
-pub use client::certificates::{CreateCertificateReq, CreateCertificateRes, ListCertificatesReq, ListCertificatesRes, VerifyCertificateRes, VerifyCertificateReq, DownloadCertificateRes};
-pub use client::result::{ResultStatus, ResultStatusAlt, ErrorMsg, Resp};
\ No newline at end of file
+pub use certs::csr::{generate_csr, generate_ca, generate_ca_signed_cert, generate_private_key, generate_rsa_2048_priv_key, lint_csr, CsrFinding, KeySpec, SubjectAltName};
+pub use client::{Client, ClientBuilder};
+pub use acme::AcmeClient;
+pub use client::certificates::{Certificate, CertificateStatus, CreateCertificateReq, CreateCertificateRes, GetCertificateRes, ListCertificatesReq, ListCertificatesRes, VerifyCertificateRes, VerifyCertificateReq, DownloadCertificateRes, DownloadCertificateZip, DownloadOptions};
+pub use client::validation::{Challenge, FileChallenge, CnameChallenge, EmailChallenge, ValidateCsrRes, ValidationStatus, DomainValidationStatus, ValidationType};
+pub use client::eab::EabCredentials;
+pub use client::result::{ApiErrorCode, ResultStatus, ResultStatusAlt, ErrorMsg, Resp};
\ No newline at end of file
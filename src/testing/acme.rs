@@ -0,0 +1,674 @@
+//! A Pebble-style stand-in for an ACME (RFC 8555) server.
+//!
+//! Requires External Account Binding like ZeroSSL. Instead of reaching out to
+//! the domains, challenges are checked against what the test registered with
+//! [`FakeAcme::serve_http01`] and [`FakeAcme::serve_dns01`].
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use hyper::service::{make_service_fn, service_fn};
+use openssl::hash::{hash, MessageDigest};
+use openssl::pkey::{PKey, Private};
+use openssl::x509::{X509, X509Req};
+use serde_json::{json, Value};
+use tokio::sync::oneshot;
+
+use crate::acme::jws;
+use crate::certs::csr::{Csr, generate_ca, generate_rsa_2048_priv_key, req_subject_alt_names};
+use crate::client::eab::EabCredentials;
+use crate::error;
+use crate::error::Result;
+use crate::testing::{random_hex, sign_csr};
+use crate::util::hmac_sha256;
+
+const VALIDITY_DAYS: u32 = 90;
+
+pub struct FakeAcme {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl FakeAcme {
+    /// Binds an ephemeral port on 127.0.0.1 and serves ACME until dropped.
+    pub async fn start() -> Result<Self> {
+        let state = Arc::new(Mutex::new(State::new()?));
+
+        let svc_state = state.clone();
+        let make_svc = make_service_fn(move |_conn| {
+            let state = svc_state.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| handle(state.clone(), req)))
+            }
+        });
+
+        let server = Server::try_bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .map_err(|e| error::io(e, Some("failed to bind fake ACME server".to_string())))?
+            .serve(make_svc);
+        let addr = server.local_addr();
+        state.lock().unwrap().base_url = format!("http://{}", addr);
+
+        let (tx, rx) = oneshot::channel::<()>();
+        tokio::spawn(server.with_graceful_shutdown(async {
+            rx.await.ok();
+        }));
+
+        Ok(Self {
+            addr,
+            state,
+            shutdown: Some(tx),
+        })
+    }
+
+    pub fn directory_url(&self) -> String {
+        format!("http://{}/directory", self.addr)
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The CA that signs every certificate issued by this server.
+    pub fn ca(&self) -> X509 {
+        self.state.lock().unwrap().ca.clone()
+    }
+
+    /// Credentials accepted for binding new accounts.
+    pub fn generate_eab_credentials(&self) -> EabCredentials {
+        let kid = random_hex(8);
+        let mut hmac_key = vec![0u8; 32];
+        openssl::rand::rand_bytes(&mut hmac_key).unwrap();
+
+        self.state.lock().unwrap().eab_credentials.insert(kid.clone(), hmac_key.clone());
+        EabCredentials { kid, hmac_key }
+    }
+
+    /// Pretends `content` is served at `/.well-known/acme-challenge/<token>` on `domain`.
+    pub fn serve_http01(&self, domain: &str, token: &str, content: &str) {
+        self.state.lock().unwrap().http01.insert((domain.to_string(), token.to_string()), content.to_string());
+    }
+
+    /// Pretends `_acme-challenge.<domain>` has a TXT record with `value`.
+    pub fn serve_dns01(&self, domain: &str, value: &str) {
+        self.state.lock().unwrap().dns01.entry(domain.trim_start_matches("*.").to_string())
+            .or_default()
+            .push(value.to_string());
+    }
+
+    /// Answers the next signed request with `badNonce`.
+    pub fn reject_next_nonce(&self) {
+        self.state.lock().unwrap().reject_next_nonce = true;
+    }
+
+    pub fn account_count(&self) -> usize {
+        self.state.lock().unwrap().accounts.len()
+    }
+}
+
+impl Drop for FakeAcme {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+// State
+
+struct FakeAccount {
+    id: String,
+    thumbprint: String,
+    jwk: Value,
+    contact: Value,
+}
+
+struct FakeChallenge {
+    id: String,
+    typ: &'static str,
+    token: String,
+    status: &'static str,
+    error: Option<Value>,
+}
+
+struct FakeAuthorization {
+    id: String,
+    domain: String,
+    wildcard: bool,
+    status: &'static str,
+    challenges: Vec<FakeChallenge>,
+}
+
+struct FakeOrder {
+    id: String,
+    account: String,
+    status: &'static str,
+    identifiers: Vec<String>,
+    authorizations: Vec<String>,
+    chain: Option<String>,
+}
+
+// An RFC 7807 problem, turned into a response at the end of `handle`
+struct AcmeProblem {
+    status: StatusCode,
+    typ: &'static str,
+    detail: String,
+}
+
+impl AcmeProblem {
+    fn into_response(self) -> Response<Body> {
+        let body = json!({
+            "type": format!("urn:ietf:params:acme:error:{}", self.typ),
+            "detail": self.detail,
+            "status": self.status.as_u16(),
+        });
+
+        Response::builder()
+            .status(self.status)
+            .header("content-type", "application/problem+json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+}
+
+type Handled = std::result::Result<Response<Body>, AcmeProblem>;
+
+struct State {
+    base_url: String,
+    ca_key: PKey<Private>,
+    ca: X509,
+    nonces: HashSet<String>,
+    reject_next_nonce: bool,
+    eab_credentials: HashMap<String, Vec<u8>>,
+    accounts: Vec<FakeAccount>,
+    orders: Vec<FakeOrder>,
+    authorizations: Vec<FakeAuthorization>,
+    http01: HashMap<(String, String), String>,
+    dns01: HashMap<String, Vec<String>>,
+}
+
+impl State {
+    fn new() -> Result<Self> {
+        let ca_key = generate_rsa_2048_priv_key()
+            .map_err(|e| error::openssl(e, None))?;
+
+        let mut csr = Csr::new("Fake ACME CA".to_string());
+        let csr = csr.with_org_name("ZeroSSL Fake".to_string());
+        let ca = generate_ca(&ca_key, csr, Some(3650))
+            .map_err(|e| error::openssl(e, None))?;
+
+        Ok(Self {
+            base_url: "".to_string(),
+            ca_key,
+            ca,
+            nonces: HashSet::new(),
+            reject_next_nonce: false,
+            eab_credentials: HashMap::new(),
+            accounts: Vec::new(),
+            orders: Vec::new(),
+            authorizations: Vec::new(),
+            http01: HashMap::new(),
+            dns01: HashMap::new(),
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    fn new_nonce(&mut self) -> String {
+        let nonce = random_hex(16);
+        self.nonces.insert(nonce.clone());
+        nonce
+    }
+
+    fn directory(&self) -> Value {
+        json!({
+            "newNonce": self.url("/new-nonce"),
+            "newAccount": self.url("/new-account"),
+            "newOrder": self.url("/new-order"),
+            "revokeCert": self.url("/revoke-cert"),
+            "keyChange": self.url("/key-change"),
+            "meta": {
+                "termsOfService": self.url("/terms"),
+                "externalAccountRequired": true,
+            },
+        })
+    }
+
+    // Checks the JWS and returns the signing account (if identified by kid),
+    // the JWK and the payload (null for POST-as-GET).
+    fn verify_jws(&mut self, url: &str, body: &[u8]) -> std::result::Result<(Option<String>, Value, Value), AcmeProblem> {
+        let malformed = |detail: &str| problem(StatusCode::BAD_REQUEST, "malformed", detail);
+
+        let jws: Value = serde_json::from_slice(body)
+            .map_err(|_| malformed("request is not a JWS"))?;
+        let (protected_b64, payload_b64, signature) = match (jws["protected"].as_str(), jws["payload"].as_str(),
+                                                             jws["signature"].as_str().and_then(jws::b64_decode)) {
+            (Some(protected), Some(payload), Some(signature)) => (protected, payload, signature),
+            _ => return Err(malformed("incomplete JWS")),
+        };
+        let protected: Value = jws::b64_decode(protected_b64)
+            .and_then(|p| serde_json::from_slice(&p).ok())
+            .ok_or_else(|| malformed("invalid protected header"))?;
+
+        let nonce = protected["nonce"].as_str().unwrap_or_default();
+        if std::mem::take(&mut self.reject_next_nonce) || !self.nonces.remove(nonce) {
+            return Err(problem(StatusCode::BAD_REQUEST, "badNonce", "unknown nonce"));
+        }
+        if protected["url"].as_str() != Some(url) {
+            return Err(problem(StatusCode::UNAUTHORIZED, "unauthorized", "url does not match request"));
+        }
+
+        let (account, jwk) = match (protected.get("kid").and_then(|k| k.as_str()), protected.get("jwk")) {
+            (Some(kid), None) => {
+                let account = self.accounts.iter()
+                    .find(|a| self.url(&format!("/account/{}", a.id)) == kid)
+                    .ok_or_else(|| problem(StatusCode::BAD_REQUEST, "accountDoesNotExist", "unknown kid"))?;
+                (Some(account.id.clone()), account.jwk.clone())
+            }
+            (None, Some(jwk)) => (None, jwk.clone()),
+            _ => return Err(malformed("exactly one of kid and jwk is required")),
+        };
+
+        let alg = protected["alg"].as_str().unwrap_or_default();
+        let valid = jws::public_key_from_jwk(&jwk)
+            .map(|key| jws::verify(&key, alg, format!("{}.{}", protected_b64, payload_b64).as_bytes(), &signature))
+            .unwrap_or(false);
+        if !valid {
+            return Err(problem(StatusCode::BAD_REQUEST, "malformed", "invalid signature"));
+        }
+
+        let payload = if payload_b64.is_empty() {
+            Value::Null
+        } else {
+            jws::b64_decode(payload_b64)
+                .and_then(|p| serde_json::from_slice(&p).ok())
+                .ok_or_else(|| malformed("invalid payload"))?
+        };
+
+        Ok((account, jwk, payload))
+    }
+
+    fn new_account(&mut self, jwk: Value, payload: &Value) -> Handled {
+        let thumbprint = jws::jwk_thumbprint(&jwk)
+            .map_err(|_| problem(StatusCode::BAD_REQUEST, "badPublicKey", "invalid jwk"))?;
+
+        if let Some(account) = self.accounts.iter().find(|a| a.thumbprint == thumbprint) {
+            return Ok(self.account_response(StatusCode::OK, account));
+        }
+
+        let eab = &payload["externalAccountBinding"];
+        if eab.is_null() {
+            return Err(problem(StatusCode::UNAUTHORIZED, "externalAccountRequired", "external account binding required"));
+        }
+        if !self.verify_eab(eab, &jwk) {
+            return Err(problem(StatusCode::UNAUTHORIZED, "unauthorized", "invalid external account binding"));
+        }
+
+        let account = FakeAccount {
+            id: random_hex(8),
+            thumbprint,
+            jwk,
+            contact: payload["contact"].clone(),
+        };
+        let res = self.account_response(StatusCode::CREATED, &account);
+        self.accounts.push(account);
+
+        Ok(res)
+    }
+
+    fn verify_eab(&self, eab: &Value, jwk: &Value) -> bool {
+        let (protected_b64, payload_b64, signature) = match (eab["protected"].as_str(), eab["payload"].as_str(),
+                                                             eab["signature"].as_str().and_then(jws::b64_decode)) {
+            (Some(protected), Some(payload), Some(signature)) => (protected, payload, signature),
+            _ => return false,
+        };
+        let protected: Value = match jws::b64_decode(protected_b64).and_then(|p| serde_json::from_slice(&p).ok()) {
+            Some(protected) => protected,
+            None => return false,
+        };
+        let hmac_key = match protected["kid"].as_str().and_then(|kid| self.eab_credentials.get(kid)) {
+            Some(hmac_key) => hmac_key,
+            None => return false,
+        };
+
+        let expected = hmac_sha256(hmac_key, format!("{}.{}", protected_b64, payload_b64).as_bytes())
+            .unwrap_or_default();
+        let payload: Option<Value> = jws::b64_decode(payload_b64)
+            .and_then(|p| serde_json::from_slice(&p).ok());
+
+        protected["alg"] == "HS256"
+            && protected["url"].as_str() == Some(self.url("/new-account").as_str())
+            && openssl::memcmp::eq(&expected, &signature)
+            && payload.as_ref() == Some(jwk)
+    }
+
+    fn account_response(&self, status: StatusCode, account: &FakeAccount) -> Response<Body> {
+        let mut res = respond(status, json!({
+            "status": "valid",
+            "contact": account.contact,
+            "orders": self.url(&format!("/account/{}/orders", account.id)),
+        }));
+        res.headers_mut().insert("location", self.url(&format!("/account/{}", account.id)).parse().unwrap());
+        res
+    }
+
+    fn new_order(&mut self, account: String, payload: &Value) -> Handled {
+        let identifiers: Vec<String> = payload["identifiers"].as_array()
+            .map(|ids| ids.iter()
+                .filter(|i| i["type"] == "dns")
+                .filter_map(|i| i["value"].as_str().map(|v| v.to_string()))
+                .collect())
+            .unwrap_or_default();
+        if identifiers.is_empty() {
+            return Err(problem(StatusCode::BAD_REQUEST, "rejectedIdentifier", "no dns identifiers"));
+        }
+
+        let mut authorizations = Vec::new();
+        for identifier in identifiers.iter() {
+            let wildcard = identifier.starts_with("*.");
+            let mut challenges = vec![FakeChallenge {
+                id: random_hex(8),
+                typ: "dns-01",
+                token: jws::b64(&random_hex(16).into_bytes()),
+                status: "pending",
+                error: None,
+            }];
+            // As in RFC 8555, wildcards can only be validated via DNS
+            if !wildcard {
+                challenges.push(FakeChallenge {
+                    id: random_hex(8),
+                    typ: "http-01",
+                    token: jws::b64(&random_hex(16).into_bytes()),
+                    status: "pending",
+                    error: None,
+                });
+            }
+
+            let authorization = FakeAuthorization {
+                id: random_hex(8),
+                domain: identifier.trim_start_matches("*.").to_string(),
+                wildcard,
+                status: "pending",
+                challenges,
+            };
+            authorizations.push(authorization.id.clone());
+            self.authorizations.push(authorization);
+        }
+
+        let order = FakeOrder {
+            id: random_hex(8),
+            account,
+            status: "pending",
+            identifiers,
+            authorizations,
+            chain: None,
+        };
+        let mut res = respond(StatusCode::CREATED, self.order_json(&order));
+        res.headers_mut().insert("location", self.url(&format!("/order/{}", order.id)).parse().unwrap());
+        self.orders.push(order);
+
+        Ok(res)
+    }
+
+    fn order_json(&self, order: &FakeOrder) -> Value {
+        let identifiers: Vec<Value> = order.identifiers.iter()
+            .map(|i| json!({ "type": "dns", "value": i }))
+            .collect();
+        let authorizations: Vec<String> = order.authorizations.iter()
+            .map(|a| self.url(&format!("/authz/{}", a)))
+            .collect();
+
+        let mut res = json!({
+            "status": order.status,
+            "identifiers": identifiers,
+            "authorizations": authorizations,
+            "finalize": self.url(&format!("/finalize/{}", order.id)),
+        });
+        if order.status == "valid" {
+            res["certificate"] = json!(self.url(&format!("/cert/{}", order.id)));
+        }
+        if order.status == "invalid" {
+            res["error"] = json!({
+                "type": "urn:ietf:params:acme:error:unauthorized",
+                "detail": "an authorization failed",
+            });
+        }
+
+        res
+    }
+
+    fn authorization_json(&self, authorization: &FakeAuthorization) -> Value {
+        let challenges: Vec<Value> = authorization.challenges.iter()
+            .map(|c| self.challenge_json(c))
+            .collect();
+
+        json!({
+            "identifier": { "type": "dns", "value": authorization.domain },
+            "status": authorization.status,
+            "challenges": challenges,
+            "wildcard": authorization.wildcard,
+        })
+    }
+
+    fn challenge_json(&self, challenge: &FakeChallenge) -> Value {
+        let mut res = json!({
+            "type": challenge.typ,
+            "url": self.url(&format!("/chall/{}", challenge.id)),
+            "status": challenge.status,
+            "token": challenge.token,
+        });
+        if let Some(error) = challenge.error.as_ref() {
+            res["error"] = error.clone();
+        }
+
+        res
+    }
+
+    fn find_order(&mut self, account: &str, id: &str) -> std::result::Result<&mut FakeOrder, AcmeProblem> {
+        self.orders.iter_mut()
+            .find(|o| o.id == id && o.account == account)
+            .ok_or_else(|| problem(StatusCode::NOT_FOUND, "malformed", "order not found"))
+    }
+
+    fn get_order(&mut self, account: &str, id: &str) -> Handled {
+        let order = self.find_order(account, id)?;
+        // Issuance finishes between finalize and the next poll
+        if order.status == "processing" {
+            order.status = "valid";
+        }
+
+        let order = self.orders.iter().find(|o| o.id == id).unwrap();
+        Ok(respond(StatusCode::OK, self.order_json(order)))
+    }
+
+    fn get_authorization(&self, id: &str) -> Handled {
+        self.authorizations.iter()
+            .find(|a| a.id == id)
+            .map(|a| respond(StatusCode::OK, self.authorization_json(a)))
+            .ok_or_else(|| problem(StatusCode::NOT_FOUND, "malformed", "authorization not found"))
+    }
+
+    fn respond_challenge(&mut self, account: &str, id: &str) -> Handled {
+        let thumbprint = self.accounts.iter()
+            .find(|a| a.id == account)
+            .map(|a| a.thumbprint.clone())
+            .unwrap_or_default();
+
+        let (authz_idx, chall_idx) = self.authorizations.iter().enumerate()
+            .find_map(|(i, a)| a.challenges.iter().position(|c| c.id == id).map(|j| (i, j)))
+            .ok_or_else(|| problem(StatusCode::NOT_FOUND, "malformed", "challenge not found"))?;
+
+        let authorization = &self.authorizations[authz_idx];
+        let challenge = &authorization.challenges[chall_idx];
+        if authorization.status == "pending" && challenge.status == "pending" {
+            let key_authorization = format!("{}.{}", challenge.token, thumbprint);
+            let passed = match challenge.typ {
+                "http-01" => self.http01.get(&(authorization.domain.clone(), challenge.token.clone()))
+                    == Some(&key_authorization),
+                _ => {
+                    let digest = hash(MessageDigest::sha256(), key_authorization.as_bytes())
+                        .map(|d| jws::b64(&d))
+                        .unwrap_or_default();
+                    self.dns01.get(&authorization.domain).is_some_and(|values| values.contains(&digest))
+                }
+            };
+
+            let authorization = &mut self.authorizations[authz_idx];
+            let challenge = &mut authorization.challenges[chall_idx];
+            if passed {
+                challenge.status = "valid";
+                authorization.status = "valid";
+            } else {
+                challenge.status = "invalid";
+                challenge.error = Some(json!({
+                    "type": "urn:ietf:params:acme:error:unauthorized",
+                    "detail": format!("{} challenge for {} failed", challenge.typ, authorization.domain),
+                    "status": 403,
+                }));
+                authorization.status = "invalid";
+            }
+            self.update_orders();
+        }
+
+        let challenge = &self.authorizations[authz_idx].challenges[chall_idx];
+        Ok(respond(StatusCode::OK, self.challenge_json(challenge)))
+    }
+
+    fn update_orders(&mut self) {
+        let statuses: HashMap<String, &'static str> = self.authorizations.iter()
+            .map(|a| (a.id.clone(), a.status))
+            .collect();
+
+        for order in self.orders.iter_mut().filter(|o| o.status == "pending") {
+            let authorizations: Vec<&str> = order.authorizations.iter()
+                .map(|a| statuses.get(a).copied().unwrap_or("invalid"))
+                .collect();
+
+            if authorizations.contains(&"invalid") {
+                order.status = "invalid";
+            } else if authorizations.iter().all(|s| *s == "valid") {
+                order.status = "ready";
+            }
+        }
+    }
+
+    fn finalize(&mut self, account: &str, id: &str, payload: &Value) -> Handled {
+        let ca = self.ca.clone();
+        let ca_key = self.ca_key.clone();
+        let order = self.find_order(account, id)?;
+
+        if order.status != "ready" {
+            return Err(problem(StatusCode::FORBIDDEN, "orderNotReady", "order is not ready"));
+        }
+
+        let csr = payload["csr"].as_str()
+            .and_then(jws::b64_decode)
+            .and_then(|der| X509Req::from_der(&der).ok())
+            .ok_or_else(|| problem(StatusCode::BAD_REQUEST, "badCSR", "invalid csr"))?;
+
+        let names: HashSet<String> = req_subject_alt_names(&csr).ok().flatten()
+            .map(|names| names.iter()
+                .filter_map(|n| n.dnsname())
+                .map(|n| n.to_string())
+                .collect())
+            .unwrap_or_default();
+        if names != order.identifiers.iter().cloned().collect() {
+            return Err(problem(StatusCode::BAD_REQUEST, "badCSR", "csr names do not match the order"));
+        }
+
+        let chain = sign_csr(&csr, &ca, &ca_key, VALIDITY_DAYS)
+            .and_then(|cert| {
+                let mut chain = cert.to_pem()?;
+                chain.extend(ca.to_pem()?);
+                Ok(chain)
+            })
+            .map_err(|_| problem(StatusCode::INTERNAL_SERVER_ERROR, "serverInternal", "failed to sign"))?;
+
+        order.chain = Some(String::from_utf8_lossy(&chain).to_string());
+        order.status = "processing";
+
+        let order = self.orders.iter().find(|o| o.id == id).unwrap();
+        Ok(respond(StatusCode::OK, self.order_json(order)))
+    }
+
+    fn certificate(&mut self, account: &str, id: &str) -> Handled {
+        let order = self.find_order(account, id)?;
+        let chain = match (order.status, order.chain.as_ref()) {
+            ("valid", Some(chain)) => chain.clone(),
+            _ => return Err(problem(StatusCode::NOT_FOUND, "malformed", "certificate not issued")),
+        };
+
+        Ok(Response::builder()
+            .header("content-type", "application/pem-certificate-chain")
+            .body(Body::from(chain))
+            .unwrap())
+    }
+}
+
+// Routing
+
+async fn handle(state: Arc<Mutex<State>>, req: Request<Body>) -> std::result::Result<Response<Body>, Infallible> {
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let body = hyper::body::to_bytes(req.into_body()).await
+        .unwrap_or_default();
+
+    let mut state = state.lock().unwrap();
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    let mut res = match (&method, segments.as_slice()) {
+        (&Method::GET, ["directory"]) => respond(StatusCode::OK, state.directory()),
+        (&Method::HEAD, ["new-nonce"]) | (&Method::GET, ["new-nonce"]) => Response::builder()
+            .header("cache-control", "no-store")
+            .body(Body::empty())
+            .unwrap(),
+        (&Method::POST, _) => {
+            let url = state.url(&path);
+            match state.verify_jws(&url, &body) {
+                Ok((account, jwk, payload)) => {
+                    let handled = match (account, segments.as_slice()) {
+                        (None, ["new-account"]) => state.new_account(jwk, &payload),
+                        (Some(_), ["new-account"]) => Err(problem(StatusCode::BAD_REQUEST, "malformed", "new-account requires jwk")),
+                        (None, _) => Err(problem(StatusCode::BAD_REQUEST, "malformed", "kid required")),
+                        (Some(account), ["new-order"]) => state.new_order(account, &payload),
+                        (Some(account), ["order", id]) => state.get_order(&account, id),
+                        (Some(_), ["authz", id]) => state.get_authorization(id),
+                        (Some(account), ["chall", id]) => state.respond_challenge(&account, id),
+                        (Some(account), ["finalize", id]) => state.finalize(&account, id, &payload),
+                        (Some(account), ["cert", id]) => state.certificate(&account, id),
+                        _ => Err(problem(StatusCode::NOT_FOUND, "malformed", "not found")),
+                    };
+                    handled.unwrap_or_else(|problem| problem.into_response())
+                }
+                Err(problem) => problem.into_response(),
+            }
+        }
+        _ => problem(StatusCode::NOT_FOUND, "malformed", "not found").into_response(),
+    };
+
+    // Every response carries a fresh nonce
+    let nonce = state.new_nonce();
+    res.headers_mut().insert("replay-nonce", nonce.parse().unwrap());
+
+    Ok(res)
+}
+
+fn respond(status: StatusCode, body: Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+fn problem(status: StatusCode, typ: &'static str, detail: &str) -> AcmeProblem {
+    AcmeProblem {
+        status,
+        typ,
+        detail: detail.to_string(),
+    }
+}
@@ -0,0 +1,919 @@
+//! An in-memory fake of the ZeroSSL REST API for offline integration testing.
+//!
+//! Enabled with the `testing` feature. Certificates are issued by a throwaway CA
+//! created with [`generate_ca`](crate::certs::csr::generate_ca).
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::Infallible;
+use std::io::{Cursor, Write};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use hyper::service::{make_service_fn, service_fn};
+use openssl::asn1::Asn1Time;
+use openssl::bn::{BigNum, MsbOption};
+use openssl::error::ErrorStack;
+use openssl::hash::{hash, MessageDigest};
+use openssl::pkey::{Id, PKey, Private};
+use openssl::x509::{X509, X509Req};
+use openssl::x509::extension::{AuthorityKeyIdentifier, BasicConstraints, ExtendedKeyUsage, SubjectKeyIdentifier};
+use serde_json::{json, Value};
+use tokio::sync::oneshot;
+
+use crate::certs::csr::{Csr, generate_ca, generate_rsa_2048_priv_key, lint_csr};
+use crate::error;
+use crate::error::Result;
+
+pub mod acme;
+
+const DEFAULT_VALIDITY_DAYS: u32 = 90;
+const DEFAULT_LIMIT: usize = 100;
+
+/// Resolves a CNAME record name to its target.
+pub type CnameLookup = Arc<dyn Fn(&str) -> Option<String> + Send + Sync>;
+
+pub struct FakeZeroSsl {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl FakeZeroSsl {
+    /// Binds an ephemeral port on 127.0.0.1 and serves the fake API until dropped.
+    pub async fn start(api_key: String) -> Result<Self> {
+        let state = Arc::new(Mutex::new(State::new(api_key)?));
+
+        let svc_state = state.clone();
+        let make_svc = make_service_fn(move |_conn| {
+            let state = svc_state.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| handle(state.clone(), req)))
+            }
+        });
+
+        let server = Server::try_bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .map_err(|e| error::io(e, Some("failed to bind fake ZeroSSL server".to_string())))?
+            .serve(make_svc);
+        let addr = server.local_addr();
+
+        let (tx, rx) = oneshot::channel::<()>();
+        tokio::spawn(server.with_graceful_shutdown(async {
+            rx.await.ok();
+        }));
+
+        Ok(Self {
+            addr,
+            state,
+            shutdown: Some(tx),
+        })
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn api_key(&self) -> String {
+        self.state.lock().unwrap().api_key.clone()
+    }
+
+    /// The CA that signs every certificate issued by this server.
+    pub fn ca(&self) -> X509 {
+        self.state.lock().unwrap().ca.clone()
+    }
+
+    pub fn certificate_status(&self, id: &str) -> Option<String> {
+        self.state.lock().unwrap().find(id).map(|c| c.status.clone())
+    }
+
+    pub fn certificate_count(&self) -> usize {
+        self.state.lock().unwrap().certificates.len()
+    }
+
+    /// Keeps verified certificates in `pending_validation` for this many
+    /// `GET /certificates/{id}` requests before issuing them.
+    pub fn set_pending_polls(&self, polls: usize) {
+        self.state.lock().unwrap().pending_polls = polls;
+    }
+
+    pub fn set_certificate_status(&self, id: &str, status: &str) {
+        if let Some(cert) = self.state.lock().unwrap().find_mut(id) {
+            cert.status = status.to_string();
+        }
+    }
+
+    pub fn email_resend_count(&self, id: &str) -> usize {
+        self.state.lock().unwrap().find(id).map(|c| c.email_resends).unwrap_or(0)
+    }
+
+    /// The HMAC key of EAB credentials handed out by this server.
+    pub fn eab_credentials(&self, kid: &str) -> Option<Vec<u8>> {
+        self.state.lock().unwrap().eab_credentials.get(kid).cloned()
+    }
+
+    /// Checks `HTTP_CSR_HASH` validation files by fetching them from `addr`
+    /// instead of accepting every request.
+    pub fn set_file_validation_addr(&self, addr: SocketAddr) {
+        self.state.lock().unwrap().file_validation_addr = Some(addr);
+    }
+
+    /// Checks `CNAME_CSR_HASH` records with `lookup` instead of accepting
+    /// every request.
+    pub fn set_cname_lookup(&self, lookup: CnameLookup) {
+        self.state.lock().unwrap().cname_lookup = Some(lookup);
+    }
+
+    /// Makes domain control validation fail for `domain` until further notice.
+    pub fn fail_validation(&self, domain: &str) {
+        self.state.lock().unwrap().failed_validations.insert(domain.to_string());
+    }
+
+    /// Answers the next request with `status` (and an optional `Retry-After`
+    /// in seconds) instead of handling it. Calls queue up.
+    pub fn fail_next(&self, status: u16, retry_after: Option<u64>) {
+        let status = StatusCode::from_u16(status).expect("invalid status code");
+        self.state.lock().unwrap().failures.push_back((status, retry_after, json!({ "success": false })));
+    }
+
+    /// Answers the next request with a 200 reporting the API error `typ`
+    /// (`success: false`), the way ZeroSSL reports rate limits. Calls queue up.
+    pub fn fail_next_api(&self, code: i32, typ: &str) {
+        self.state.lock().unwrap().failures.push_back((StatusCode::OK, None, api_error(code, typ)));
+    }
+
+    /// Number of HTTP requests received, including failed ones.
+    pub fn request_count(&self) -> usize {
+        self.state.lock().unwrap().request_count
+    }
+
+    /// The `User-Agent` header of the last request received.
+    pub fn last_user_agent(&self) -> Option<String> {
+        self.state.lock().unwrap().last_user_agent.clone()
+    }
+
+    /// Waits `delay` before answering each request, e.g. to trigger client timeouts.
+    pub fn set_response_delay(&self, delay: Duration) {
+        self.state.lock().unwrap().response_delay = delay;
+    }
+}
+
+impl Drop for FakeZeroSsl {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+// State
+
+struct FakeCertificate {
+    id: String,
+    common_name: String,
+    additional_domains: Vec<String>,
+    status: String,
+    created: String,
+    expires: String,
+    validation_type: Option<String>,
+    csr: X509Req,
+    csr_md5: String,
+    csr_sha256: String,
+    unique_value: String,
+    certificate: Option<X509>,
+    pending_polls: usize,
+    failed_domains: Vec<String>,
+    email_resends: usize,
+}
+
+impl FakeCertificate {
+    fn domains(&self) -> Vec<String> {
+        let mut domains = vec![self.common_name.clone()];
+        domains.extend(self.additional_domains.iter().cloned());
+        domains
+    }
+
+    fn cname_name(&self, domain: &str) -> String {
+        format!("_{}.{}", self.csr_md5, domain.trim_start_matches("*."))
+    }
+
+    fn cname_target(&self) -> String {
+        format!("{}.{}.{}.comodoca.com", &self.csr_sha256[..32], &self.csr_sha256[32..], self.unique_value)
+    }
+
+    fn matches_search(&self, search: &str) -> bool {
+        self.domains().iter().any(|d| d.contains(search))
+    }
+
+    fn validation_details(&self) -> Value {
+        let method = self.validation_type.clone()
+            .unwrap_or("CNAME_CSR_HASH".to_string());
+        let mut details = serde_json::Map::new();
+
+        for domain in self.domains() {
+            let passed = if self.failed_domains.contains(&domain) { 0 } else { 1 };
+
+            let detail = match method.as_str() {
+                "HTTP_CSR_HASH" | "HTTPS_CSR_HASH" => json!({
+                    "method": method,
+                    "file_found": passed,
+                    "content_correct": passed,
+                }),
+                "EMAIL" => json!({
+                    "method": method,
+                    "status": if passed == 1 { "approved" } else { "pending" },
+                }),
+                _ => {
+                    let target_record = self.cname_target();
+                    json!({
+                        "method": method,
+                        "cname_found": passed,
+                        "record_correct": passed,
+                        "target_host": self.cname_name(&domain),
+                        "actual_record": if passed == 1 { target_record.clone() } else { "".to_string() },
+                        "target_record": target_record,
+                    })
+                }
+            };
+            details.insert(domain, detail);
+        }
+
+        Value::Object(details)
+    }
+
+    fn signature_algorithm_properties(&self) -> Value {
+        let (key_type, key_size) = match self.csr.public_key() {
+            Ok(key) => match key.id() {
+                Id::RSA => ("RSA", key.bits()),
+                Id::EC => ("EC", key.bits()),
+                _ => ("unknown", key.bits()),
+            },
+            Err(_) => ("unknown", 0),
+        };
+
+        json!({
+            "signature_algorithm": "sha256WithRSAEncryption",
+            "key_type": key_type,
+            "key_size": key_size,
+        })
+    }
+
+    fn to_json(&self) -> Value {
+        let mut email_validation = serde_json::Map::new();
+        let mut other_methods = serde_json::Map::new();
+
+        for domain in self.domains() {
+            email_validation.insert(domain.clone(), json!(approver_emails(&domain)));
+            other_methods.insert(domain.clone(), json!({
+                "file_validation_url_http": format!("http://{}/.well-known/pki-validation/{}.txt", domain, self.csr_md5),
+                "file_validation_url_https": format!("https://{}/.well-known/pki-validation/{}.txt", domain, self.csr_md5),
+                "file_validation_content": [self.csr_sha256.clone(), "comodoca.com".to_string(), self.unique_value.clone()],
+                "cname_validation_p1": self.cname_name(&domain),
+                "cname_validation_p2": self.cname_target(),
+            }));
+        }
+
+        let fingerprint_sha1 = self.certificate.as_ref()
+            .and_then(|c| c.digest(MessageDigest::sha1()).ok())
+            .map(|d| to_hex(&d));
+
+        json!({
+            "id": self.id,
+            "type": "1",
+            "common_name": self.common_name,
+            "additional_domains": self.additional_domains.join(","),
+            "created": self.created,
+            "expires": self.expires,
+            "status": self.status,
+            "validation_type": self.validation_type,
+            "validation_emails": null,
+            "replacement_for": "",
+            "fingerprint_sha1": fingerprint_sha1,
+            "brand_validation": null,
+            "signature_algorithm_properties": self.signature_algorithm_properties(),
+            "validation": {
+                "email_validation": email_validation,
+                "other_methods": other_methods,
+            },
+        })
+    }
+}
+
+struct State {
+    api_key: String,
+    ca_key: PKey<Private>,
+    ca: X509,
+    cross_ca: X509,
+    certificates: Vec<FakeCertificate>,
+    failures: VecDeque<(StatusCode, Option<u64>, Value)>,
+    request_count: usize,
+    last_user_agent: Option<String>,
+    response_delay: Duration,
+    pending_polls: usize,
+    failed_validations: HashSet<String>,
+    eab_credentials: HashMap<String, Vec<u8>>,
+    file_validation_addr: Option<SocketAddr>,
+    cname_lookup: Option<CnameLookup>,
+}
+
+impl State {
+    fn new(api_key: String) -> Result<Self> {
+        let ca_key = generate_rsa_2048_priv_key()
+            .map_err(|e| error::openssl(e, None))?;
+
+        let mut csr = Csr::new("ZeroSSL Fake CA".to_string());
+        let csr = csr.with_country("AT".to_string())
+            .with_org_name("ZeroSSL Fake".to_string());
+        let ca = generate_ca(&ca_key, csr, Some(3650))
+            .map_err(|e| error::openssl(e, None))?;
+
+        // Stands in for the legacy root, only sent with include_cross_signed
+        let mut csr = Csr::new("ZeroSSL Fake Legacy CA".to_string());
+        let csr = csr.with_country("AT".to_string())
+            .with_org_name("ZeroSSL Fake".to_string());
+        let cross_ca = generate_ca(&ca_key, csr, Some(3650))
+            .map_err(|e| error::openssl(e, None))?;
+
+        Ok(Self {
+            api_key,
+            ca_key,
+            ca,
+            cross_ca,
+            certificates: Vec::new(),
+            failures: VecDeque::new(),
+            request_count: 0,
+            last_user_agent: None,
+            response_delay: Duration::ZERO,
+            pending_polls: 0,
+            failed_validations: HashSet::new(),
+            eab_credentials: HashMap::new(),
+            file_validation_addr: None,
+            cname_lookup: None,
+        })
+    }
+
+    fn find(&self, id: &str) -> Option<&FakeCertificate> {
+        self.certificates.iter().find(|c| c.id == id)
+    }
+
+    fn find_mut(&mut self, id: &str) -> Option<&mut FakeCertificate> {
+        self.certificates.iter_mut().find(|c| c.id == id)
+    }
+
+    fn list(&self, query: &HashMap<String, String>) -> Value {
+        let statuses: Option<Vec<&str>> = query.get("certificate_status")
+            .map(|s| s.split(',').collect());
+        let search = query.get("search");
+        let limit = query.get("limit")
+            .and_then(|l| l.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_LIMIT)
+            .max(1);
+        let page = query.get("page")
+            .and_then(|p| p.parse::<usize>().ok())
+            .unwrap_or(1)
+            .max(1);
+
+        let matching: Vec<&FakeCertificate> = self.certificates.iter()
+            .filter(|c| statuses.as_ref().map(|s| s.contains(&c.status.as_str())).unwrap_or(true))
+            .filter(|c| search.map(|s| c.matches_search(s)).unwrap_or(true))
+            .collect();
+
+        let results: Vec<Value> = matching.iter()
+            .skip((page - 1) * limit)
+            .take(limit)
+            .map(|c| c.to_json())
+            .collect();
+
+        json!({
+            "total_count": matching.len(),
+            "result_count": results.len(),
+            "page": page.to_string(),
+            "limit": limit,
+            "results": results,
+        })
+    }
+
+    fn get(&mut self, id: &str) -> Value {
+        let cert = match self.find_mut(id) {
+            Some(cert) => cert,
+            None => return api_error(2832, "certificate_not_found"),
+        };
+
+        if cert.status == "pending_validation" && cert.certificate.is_some() {
+            if cert.pending_polls == 0 {
+                cert.status = "issued".to_string();
+            } else {
+                cert.pending_polls -= 1;
+            }
+        }
+
+        cert.to_json()
+    }
+
+    fn generate_eab_credentials(&mut self) -> Value {
+        let mut kid = vec![0u8; 16];
+        let mut hmac_key = vec![0u8; 32];
+        if openssl::rand::rand_bytes(&mut kid).is_err() || openssl::rand::rand_bytes(&mut hmac_key).is_err() {
+            return api_error(0, "internal_error");
+        }
+
+        let kid = base64::encode_config(&kid, base64::URL_SAFE_NO_PAD);
+        let res = json!({
+            "success": true,
+            "eab_kid": kid,
+            "eab_hmac_key": base64::encode_config(&hmac_key, base64::URL_SAFE_NO_PAD),
+        });
+        self.eab_credentials.insert(kid, hmac_key);
+
+        res
+    }
+
+    fn validation_status(&self, id: &str) -> Value {
+        let cert = match self.find(id) {
+            Some(cert) => cert,
+            None => return api_error(2832, "certificate_not_found"),
+        };
+
+        let completed = cert.certificate.is_some() && cert.failed_domains.is_empty();
+        json!({
+            "validation_completed": if completed { 1 } else { 0 },
+            "details": cert.validation_details(),
+        })
+    }
+
+    fn create(&mut self, form: &HashMap<String, String>) -> Value {
+        let domains: Vec<String> = match form.get("certificate_domains") {
+            Some(domains) if !domains.trim().is_empty() => domains.split(',')
+                .map(|d| d.trim().to_string())
+                .collect(),
+            _ => return api_error(2807, "missing_certificate_domains"),
+        };
+
+        let csr = match form.get("certificate_csr") {
+            Some(pem) => match X509Req::from_pem(pem.as_bytes()) {
+                Ok(csr) => csr,
+                Err(_) => return api_error(2808, "invalid_certificate_csr"),
+            },
+            None => return api_error(2809, "missing_certificate_csr"),
+        };
+
+        let csr_der = match csr.to_der() {
+            Ok(der) => der,
+            Err(_) => return api_error(2808, "invalid_certificate_csr"),
+        };
+
+        let validity_days = form.get("certificate_validity_days")
+            .and_then(|d| d.parse::<u32>().ok())
+            .unwrap_or(DEFAULT_VALIDITY_DAYS);
+
+        let now = now_secs();
+        let cert = FakeCertificate {
+            id: random_hex(16),
+            common_name: domains[0].clone(),
+            additional_domains: domains[1..].to_vec(),
+            status: "draft".to_string(),
+            created: format_timestamp(now),
+            expires: format_timestamp(now + u64::from(validity_days) * 86400),
+            validation_type: None,
+            csr_md5: digest_hex(MessageDigest::md5(), &csr_der),
+            csr_sha256: digest_hex(MessageDigest::sha256(), &csr_der),
+            unique_value: random_hex(5).to_lowercase(),
+            csr,
+            certificate: None,
+            pending_polls: 0,
+            failed_domains: Vec::new(),
+            email_resends: 0,
+        };
+
+        let res = cert.to_json();
+        self.certificates.push(cert);
+
+        res
+    }
+
+    // Where to fetch the validation file of `id` from, and what it should contain
+    fn validation_file(&self, id: &str) -> Option<(String, String)> {
+        let addr = self.file_validation_addr?;
+        let cert = self.find(id)?;
+
+        Some((format!("http://{}/.well-known/pki-validation/{}.txt", addr, cert.csr_md5),
+              [cert.csr_sha256.as_str(), "comodoca.com", cert.unique_value.as_str()].join("\n")))
+    }
+
+    // `file_found` is the result of fetching the validation file, if it was checked.
+    fn verify(&mut self, id: &str, form: &HashMap<String, String>, file_found: Option<bool>) -> Value {
+        let validation_method = match form.get("validation_method") {
+            Some(method) if ["EMAIL", "CNAME_CSR_HASH", "HTTP_CSR_HASH", "HTTPS_CSR_HASH"]
+                .contains(&method.as_str()) => method.clone(),
+            Some(_) => return api_error(2823, "invalid_validation_method"),
+            None => return api_error(2822, "missing_validation_method"),
+        };
+
+        let ca = self.ca.clone();
+        let ca_key = self.ca_key.clone();
+        let pending_polls = self.pending_polls;
+        let failed_validations = self.failed_validations.clone();
+        let cname_lookup = self.cname_lookup.clone();
+        let cert = match self.find_mut(id) {
+            Some(cert) => cert,
+            None => return api_error(2832, "certificate_not_found"),
+        };
+
+        if cert.status != "draft" && cert.status != "pending_validation" {
+            return api_error(2831, "certificate_not_ready_to_validate");
+        }
+
+        if validation_method == "EMAIL" {
+            if let Some(validation_email) = form.get("validation_email") {
+                let emails: Vec<&str> = validation_email.split(',').map(|e| e.trim()).collect();
+                let domains = cert.domains();
+                let valid = emails.len() == domains.len() && domains.iter().zip(emails.iter())
+                    .all(|(domain, email)| approver_emails(domain).iter().any(|a| a == email));
+                if !valid {
+                    return api_error(2824, "invalid_validation_email");
+                }
+            }
+        }
+
+        cert.validation_type = Some(validation_method);
+        cert.failed_domains = cert.domains().into_iter()
+            .filter(|d| failed_validations.contains(d) || file_found == Some(false))
+            .collect();
+        if let (Some(lookup), Some("CNAME_CSR_HASH")) = (cname_lookup.as_ref(), cert.validation_type.as_deref()) {
+            let target = cert.cname_target().to_lowercase();
+            for domain in cert.domains() {
+                let found = lookup(&cert.cname_name(&domain).to_lowercase())
+                    .map(|t| t.trim_end_matches('.').to_lowercase() == target)
+                    .unwrap_or(false);
+                if !found && !cert.failed_domains.contains(&domain) {
+                    cert.failed_domains.push(domain);
+                }
+            }
+        }
+        if !cert.failed_domains.is_empty() {
+            let mut err = api_error(0, "domain_control_validation_failed");
+            err["error"]["details"] = cert.validation_details();
+            return err;
+        }
+
+        match sign_csr(&cert.csr, &ca, &ca_key, DEFAULT_VALIDITY_DAYS) {
+            Ok(signed) => cert.certificate = Some(signed),
+            Err(_) => return api_error(0, "internal_error"),
+        }
+        if pending_polls == 0 {
+            cert.status = "issued".to_string();
+        } else {
+            cert.status = "pending_validation".to_string();
+            cert.pending_polls = pending_polls;
+        }
+
+        cert.to_json()
+    }
+
+    fn resend_email(&mut self, id: &str) -> Value {
+        let cert = match self.find_mut(id) {
+            Some(cert) => cert,
+            None => return api_error(2832, "certificate_not_found"),
+        };
+
+        if cert.status != "pending_validation" || cert.validation_type.as_deref() != Some("EMAIL") {
+            return api_error(2831, "certificate_not_ready_to_validate");
+        }
+        cert.email_resends += 1;
+
+        json!({ "success": 1 })
+    }
+
+    fn cancel(&mut self, id: &str) -> Value {
+        let cert = match self.find_mut(id) {
+            Some(cert) => cert,
+            None => return api_error(2832, "certificate_not_found"),
+        };
+
+        if cert.status != "draft" && cert.status != "pending_validation" {
+            return api_error(2834, "certificate_cannot_be_cancelled");
+        }
+        cert.status = "cancelled".to_string();
+
+        json!({ "success": 1 })
+    }
+
+    fn revoke(&mut self, id: &str) -> Value {
+        let cert = match self.find_mut(id) {
+            Some(cert) => cert,
+            None => return api_error(2832, "certificate_not_found"),
+        };
+
+        if cert.status != "issued" {
+            return api_error(2833, "certificate_not_issued");
+        }
+        cert.status = "revoked".to_string();
+
+        json!({ "success": 1 })
+    }
+
+    fn download_files(&self, id: &str, include_cross_signed: bool) -> std::result::Result<Vec<(String, String)>, Value> {
+        let cert = self.find(id)
+            .ok_or_else(|| api_error(2832, "certificate_not_found"))?;
+
+        let certificate = match (cert.status.as_str(), cert.certificate.as_ref()) {
+            ("issued", Some(certificate)) => certificate,
+            _ => return Err(api_error(2833, "certificate_not_issued")),
+        };
+
+        let mut ca_bundle = self.ca.to_pem()
+            .map_err(|_| api_error(0, "internal_error"))?;
+        if include_cross_signed {
+            ca_bundle.extend(self.cross_ca.to_pem()
+                .map_err(|_| api_error(0, "internal_error"))?);
+        }
+        let crt = certificate.to_pem()
+            .map_err(|_| api_error(0, "internal_error"))?;
+
+        Ok(vec![
+            ("certificate.crt".to_string(), String::from_utf8_lossy(&crt).to_string()),
+            ("ca_bundle.crt".to_string(), String::from_utf8_lossy(&ca_bundle).to_string()),
+        ])
+    }
+
+    fn download(&self, id: &str, include_cross_signed: bool) -> Value {
+        match self.download_files(id, include_cross_signed) {
+            Ok(files) => {
+                let mut res = serde_json::Map::new();
+                for (name, content) in files {
+                    res.insert(name, Value::String(content));
+                }
+                Value::Object(res)
+            }
+            Err(err) => err,
+        }
+    }
+
+    fn download_zip(&self, id: &str, include_cross_signed: bool) -> Response<Body> {
+        let files = match self.download_files(id, include_cross_signed) {
+            Ok(files) => files,
+            Err(err) => return respond(StatusCode::OK, err),
+        };
+
+        match write_zip(&files) {
+            Ok(zip) => Response::builder()
+                .header("content-type", "application/zip")
+                .body(Body::from(zip))
+                .unwrap(),
+            Err(_) => respond(StatusCode::OK, api_error(0, "internal_error")),
+        }
+    }
+}
+
+// Routing
+
+async fn handle(state: Arc<Mutex<State>>, req: Request<Body>) -> std::result::Result<Response<Body>, Infallible> {
+    let user_agent = req.headers().get("user-agent")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
+    let response_delay = {
+        let mut state = state.lock().unwrap();
+        state.last_user_agent = user_agent;
+        state.response_delay
+    };
+    if !response_delay.is_zero() {
+        tokio::time::sleep(response_delay).await;
+    }
+
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let query: HashMap<String, String> = serde_urlencoded::from_str(req.uri().query().unwrap_or(""))
+        .unwrap_or_default();
+    let body = hyper::body::to_bytes(req.into_body()).await
+        .unwrap_or_default();
+    let form: HashMap<String, String> = serde_urlencoded::from_bytes(&body)
+        .unwrap_or_default();
+
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    // Fetched before locking the state, the file may be served by this process
+    let mut file_found = None;
+    if let (&Method::POST, ["certificates", id, "challenges"]) = (&method, segments.as_slice()) {
+        if form.get("validation_method").map(|m| m.as_str()) == Some("HTTP_CSR_HASH") {
+            let validation_file = state.lock().unwrap().validation_file(id);
+            if let Some((url, content)) = validation_file {
+                let served = match reqwest::get(url).await {
+                    Ok(res) if res.status().is_success() => res.text().await.ok(),
+                    _ => None,
+                };
+                file_found = Some(served.map(|s| s.trim_end() == content).unwrap_or(false));
+            }
+        }
+    }
+
+    let mut state = state.lock().unwrap();
+    state.request_count += 1;
+
+    if let Some((status, retry_after, body)) = state.failures.pop_front() {
+        let mut res = respond(status, body);
+        if let Some(retry_after) = retry_after {
+            res.headers_mut().insert("retry-after", retry_after.into());
+        }
+        return Ok(res);
+    }
+
+    // The only function that works without an API key
+    if method == Method::POST && segments == ["acme", "eab-credentials-email"] {
+        let res = match form.get("email") {
+            Some(email) if email.contains('@') => state.generate_eab_credentials(),
+            _ => api_error(2900, "invalid_email"),
+        };
+        return Ok(respond(StatusCode::OK, res));
+    }
+
+    if query.get("access_key") != Some(&state.api_key) {
+        return Ok(respond(StatusCode::OK, api_error(101, "invalid_access_key")));
+    }
+
+    let include_cross_signed = query.get("include_cross_signed").map(|v| v == "1").unwrap_or(false);
+    let res = match (&method, segments.as_slice()) {
+        (&Method::GET, ["certificates"]) => state.list(&query),
+        (&Method::POST, ["certificates"]) => state.create(&form),
+        (&Method::POST, ["validation", "csr"]) => validate_csr(&form),
+        (&Method::POST, ["acme", "eab-credentials"]) => state.generate_eab_credentials(),
+        (&Method::GET, ["certificates", id]) => state.get(id),
+        (&Method::GET, ["certificates", id, "status"]) => state.validation_status(id),
+        (&Method::POST, ["certificates", id, "challenges"]) => state.verify(id, &form, file_found),
+        (&Method::POST, ["certificates", id, "challenges", "email"]) => state.resend_email(id),
+        (&Method::POST, ["certificates", id, "cancel"]) => state.cancel(id),
+        (&Method::POST, ["certificates", id, "revoke"]) => state.revoke(id),
+        (&Method::GET, ["certificates", id, "download", "return"]) => state.download(id, include_cross_signed),
+        (&Method::GET, ["certificates", id, "download"]) => return Ok(state.download_zip(id, include_cross_signed)),
+        _ => return Ok(respond(StatusCode::NOT_FOUND, api_error(103, "invalid_api_function"))),
+    };
+
+    Ok(respond(StatusCode::OK, res))
+}
+
+fn validate_csr(form: &HashMap<String, String>) -> Value {
+    let valid = form.get("csr")
+        .and_then(|pem| X509Req::from_pem(pem.as_bytes()).ok())
+        .and_then(|csr| lint_csr(&csr).ok())
+        .map(|findings| findings.is_empty())
+        .unwrap_or(false);
+
+    if valid {
+        json!({ "valid": true, "error": null })
+    } else {
+        json!({ "valid": false, "error": { "code": 2808, "type": "invalid_certificate_csr" } })
+    }
+}
+
+fn respond(status: StatusCode, body: Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+// ZeroSSL reports most errors with HTTP 200 and this body.
+fn api_error(code: i32, typ: &str) -> Value {
+    json!({
+        "success": false,
+        "error": {
+            "code": code,
+            "type": typ,
+        }
+    })
+}
+
+// Util
+
+fn sign_csr(csr: &X509Req, ca: &X509, ca_key: &PKey<Private>, days: u32) -> std::result::Result<X509, ErrorStack> {
+    let pubkey = csr.public_key()?;
+
+    let mut builder = X509::builder()?;
+    builder.set_version(2)?;
+    let serial_number = {
+        let mut serial = BigNum::new()?;
+        serial.rand(159, MsbOption::MAYBE_ZERO, false)?;
+        serial.to_asn1_integer()?
+    };
+    builder.set_serial_number(&serial_number)?;
+    builder.set_subject_name(csr.subject_name())?;
+    builder.set_issuer_name(ca.subject_name())?;
+    builder.set_pubkey(&pubkey)?;
+    let not_before = Asn1Time::days_from_now(0)?;
+    builder.set_not_before(&not_before)?;
+    let not_after = Asn1Time::days_from_now(days)?;
+    builder.set_not_after(&not_after)?;
+
+    builder.append_extension(BasicConstraints::new().build()?)?;
+    builder.append_extension(ExtendedKeyUsage::new().server_auth().build()?)?;
+
+    // Carries over key usage and subject alt names
+    if let Ok(extensions) = csr.extensions() {
+        for extension in extensions {
+            builder.append_extension(extension)?;
+        }
+    }
+
+    let subject_key_identifier =
+        SubjectKeyIdentifier::new().build(&builder.x509v3_context(Some(ca), None))?;
+    builder.append_extension(subject_key_identifier)?;
+
+    let auth_key_identifier = AuthorityKeyIdentifier::new()
+        .keyid(false)
+        .issuer(false)
+        .build(&builder.x509v3_context(Some(ca), None))?;
+    builder.append_extension(auth_key_identifier)?;
+
+    builder.sign(ca_key, MessageDigest::sha256())?;
+
+    Ok(builder.build())
+}
+
+fn write_zip(files: &[(String, String)]) -> zip::result::ZipResult<Vec<u8>> {
+    let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+
+    for (name, content) in files {
+        zip.start_file(name.as_str(), zip::write::FileOptions::default())?;
+        zip.write_all(content.as_bytes())?;
+    }
+
+    Ok(zip.finish()?.into_inner())
+}
+
+fn approver_emails(domain: &str) -> Vec<String> {
+    let labels: Vec<&str> = domain.trim_start_matches("*.").split('.').collect();
+    let mut emails = Vec::new();
+
+    for i in 0..labels.len().saturating_sub(1) {
+        let base = labels[i..].join(".");
+        for user in ["admin", "administrator", "hostmaster", "postmaster", "webmaster"] {
+            emails.push(format!("{}@{}", user, base));
+        }
+    }
+
+    emails
+}
+
+fn digest_hex(digest: MessageDigest, data: &[u8]) -> String {
+    hash(digest, data)
+        .map(|d| to_hex(&d))
+        .unwrap_or_default()
+}
+
+fn random_hex(len: usize) -> String {
+    let mut buf = vec![0u8; len];
+    openssl::rand::rand_bytes(&mut buf).unwrap();
+    to_hex(&buf)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// Formats as ZeroSSL does, e.g. "2020-04-02 11:37:07" (UTC).
+fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    // Civil date from days since epoch (H. Hinnant)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", y, m, d, rem / 3600, (rem % 3600) / 60, rem % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{approver_emails, format_timestamp};
+
+    #[test]
+    fn format_timestamp_test() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00");
+        assert_eq!(format_timestamp(1585827427), "2020-04-02 11:37:07");
+    }
+
+    #[test]
+    fn approver_emails_test() {
+        let emails = approver_emails("www.example.com");
+
+        assert!(emails.contains(&"admin@www.example.com".to_string()));
+        assert!(emails.contains(&"webmaster@example.com".to_string()));
+        assert!(!emails.iter().any(|e| e.ends_with("@com")));
+    }
+}
@@ -0,0 +1,498 @@
+//! `CNAME_CSR_HASH` validation through a pluggable DNS provider.
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::client::Client;
+use crate::client::certificates::{Certificate, VerifyCertificateReq, VerifyCertificateRes};
+use crate::client::validation::{CnameChallenge, ValidationType};
+use crate::error as error;
+use crate::error::Result;
+use crate::validation::wire;
+use crate::validation::wire::normalize;
+
+pub const DEFAULT_PROPAGATION_DELAY: Duration = Duration::from_secs(30);
+
+// Between two rounds of lookups in `wait_for_propagation`
+const PROPAGATION_POLL_INTERVAL: Duration = Duration::from_secs(5);
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+/// Creates and removes the CNAME records ZeroSSL checks. Implementations can
+/// use `async fn`.
+pub trait DnsProvider {
+    /// Creates (or replaces) the record `name` pointing to `target`.
+    fn create_cname(&self, name: &str, target: &str) -> impl Future<Output = Result<()>> + Send;
+
+    /// Removes the record `name`. Removing a missing record is not an error.
+    fn delete_cname(&self, name: &str) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// Keeps records in memory, for tests. Cloning shares the records.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryDnsProvider {
+    records: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl MemoryDnsProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.records.lock().unwrap().get(&normalize(name)).cloned()
+    }
+
+    pub fn records(&self) -> HashMap<String, String> {
+        self.records.lock().unwrap().clone()
+    }
+}
+
+impl DnsProvider for MemoryDnsProvider {
+    async fn create_cname(&self, name: &str, target: &str) -> Result<()> {
+        self.records.lock().unwrap().insert(normalize(name), normalize(target));
+        Ok(())
+    }
+
+    async fn delete_cname(&self, name: &str) -> Result<()> {
+        self.records.lock().unwrap().remove(&normalize(name));
+        Ok(())
+    }
+}
+
+/// Runs `CNAME_CSR_HASH` validation: creates the records of a certificate,
+/// waits for them to propagate, asks ZeroSSL to validate and cleans up.
+#[derive(Debug)]
+pub struct DnsValidator<P> {
+    provider: P,
+    propagation_delay: Duration,
+    // Resolvers and timeout of `wait_for_propagation`, when used instead of the delay
+    propagation_check: Option<(Vec<SocketAddr>, Duration)>,
+}
+
+impl<P: DnsProvider> DnsValidator<P> {
+    pub fn new(provider: P) -> Self {
+        Self {
+            provider,
+            propagation_delay: DEFAULT_PROPAGATION_DELAY,
+            propagation_check: None,
+        }
+    }
+
+    /// How long to wait after creating the records before asking ZeroSSL to
+    /// look them up.
+    pub fn with_propagation_delay(&mut self, propagation_delay: Duration) -> &mut Self {
+        self.propagation_delay = propagation_delay;
+        self
+    }
+
+    /// Instead of waiting for the propagation delay, polls `resolvers` (the
+    /// authoritative nameservers if empty) until the records show up. See
+    /// `wait_for_propagation`.
+    pub fn with_propagation_check(&mut self, resolvers: Vec<SocketAddr>, timeout: Duration) -> &mut Self {
+        self.propagation_check = Some((resolvers, timeout));
+        self
+    }
+
+    // Accessors
+    pub fn provider(&self) -> &P {
+        &self.provider
+    }
+
+    pub fn propagation_delay(&self) -> Duration {
+        self.propagation_delay
+    }
+
+    // Actions
+    pub async fn validate(&self, client: &Client, cert: &Certificate) -> Result<VerifyCertificateRes> {
+        let id = cert.id.clone()
+            .ok_or_else(|| error::validation("certificate has no id"))?;
+        let records = cname_records(cert)?;
+
+        let mut created: Vec<&CnameChallenge> = Vec::new();
+        let mut res = Ok(());
+        for record in records.iter() {
+            res = self.provider.create_cname(&record.name, &record.target).await;
+            if res.is_err() {
+                break;
+            }
+            created.push(record);
+        }
+
+        let res = match res {
+            Ok(_) => {
+                let propagated = match &self.propagation_check {
+                    Some((resolvers, timeout)) => wait_for_propagation(&records, resolvers, *timeout).await,
+                    None => {
+                        tokio::time::sleep(self.propagation_delay).await;
+                        Ok(())
+                    }
+                };
+                match propagated {
+                    Ok(_) => client.verify_certificate(id, &VerifyCertificateReq::new(ValidationType::CnameCsrHash, None)).await,
+                    Err(e) => Err(e),
+                }
+            }
+            Err(e) => Err(e),
+        };
+
+        let mut cleaned = Ok(());
+        for record in created {
+            if let Err(e) = self.provider.delete_cname(&record.name).await {
+                cleaned = Err(e);
+            }
+        }
+
+        let res = res?;
+        cleaned?;
+
+        Ok(res)
+    }
+}
+
+/// The CNAME records ZeroSSL expects for the domains of `cert`.
+pub fn cname_records(cert: &Certificate) -> Result<Vec<CnameChallenge>> {
+    let validation = cert.validation.as_ref()
+        .ok_or_else(|| error::validation("certificate has no validation options"))?;
+
+    let mut records: Vec<CnameChallenge> = Vec::new();
+    for domain in cert.domains() {
+        let record = validation.cname_validation(&domain)
+            .ok_or_else(|| error::validation(format!("no CNAME validation offered for {}", domain)))?;
+
+        // Wildcards share the record of their base domain
+        if !records.iter().any(|r| r.name.eq_ignore_ascii_case(&record.name)) {
+            records.push(record);
+        }
+    }
+
+    Ok(records)
+}
+
+/// Waits until every record resolves to its target on each of `resolvers`
+/// or, if there are none, on each authoritative nameserver of its zone.
+/// Gives up after `timeout` with a timeout error naming the stale records.
+pub async fn wait_for_propagation(records: &[CnameChallenge], resolvers: &[SocketAddr], timeout: Duration) -> Result<()> {
+    let started = tokio::time::Instant::now();
+    let servers = record_servers(records, resolvers).await?;
+
+    loop {
+        let stale = stale(&servers).await;
+        if stale.is_empty() {
+            return Ok(());
+        }
+
+        let remaining = timeout.saturating_sub(started.elapsed());
+        if remaining.is_zero() {
+            let names: Vec<&str> = stale.iter().map(|r| r.name.as_str()).collect();
+            return Err(error::timeout(format!("DNS records not propagated after {:?}: {}", timeout, names.join(", "))));
+        }
+
+        tokio::time::sleep(PROPAGATION_POLL_INTERVAL.min(remaining)).await;
+    }
+}
+
+/// The records that don't resolve to their target yet on all servers, as
+/// checked by `wait_for_propagation`.
+pub async fn stale_records(records: &[CnameChallenge], resolvers: &[SocketAddr]) -> Result<Vec<CnameChallenge>> {
+    let servers = record_servers(records, resolvers).await?;
+
+    Ok(stale(&servers).await)
+}
+
+async fn record_servers(records: &[CnameChallenge], resolvers: &[SocketAddr]) -> Result<Vec<(CnameChallenge, Vec<SocketAddr>)>> {
+    let mut servers = Vec::new();
+    for record in records {
+        let record_resolvers = if resolvers.is_empty() {
+            authoritative_nameservers(&system_resolvers()?, &record.name).await?
+        } else {
+            resolvers.to_vec()
+        };
+        servers.push((record.clone(), record_resolvers));
+    }
+
+    Ok(servers)
+}
+
+async fn stale(servers: &[(CnameChallenge, Vec<SocketAddr>)]) -> Vec<CnameChallenge> {
+    let mut stale = Vec::new();
+    for (record, resolvers) in servers {
+        let target = normalize(&record.target);
+        let mut answered = false;
+        let mut outdated = false;
+        for resolver in resolvers {
+            match lookup_cname(*resolver, &record.name).await {
+                Ok(found) => {
+                    answered = true;
+                    if found.as_deref() != Some(target.as_str()) {
+                        outdated = true;
+                        break;
+                    }
+                }
+                // Unreachable from here (e.g. IPv6 without a route), the others have to answer
+                Err(e) if e.is_io() => continue,
+                // Servers that time out or fail may still serve old data
+                Err(_) => {
+                    outdated = true;
+                    break;
+                }
+            }
+        }
+        if outdated || !answered {
+            stale.push(record.clone());
+        }
+    }
+
+    stale
+}
+
+async fn lookup_cname(server: SocketAddr, name: &str) -> Result<Option<String>> {
+    let name = normalize(name);
+    let res = query(server, &name, wire::TYPE_CNAME).await?;
+
+    Ok(res.answers().iter()
+        .find(|r| r.rtype == wire::TYPE_CNAME && r.class == wire::CLASS_IN && r.name == name)
+        .and_then(|r| r.target.clone()))
+}
+
+// Finds the closest enclosing zone with NS records and resolves its nameservers
+async fn authoritative_nameservers(resolvers: &[SocketAddr], name: &str) -> Result<Vec<SocketAddr>> {
+    let hosts = zone_nameservers(resolvers, name).await?;
+
+    let mut servers: Vec<SocketAddr> = Vec::new();
+    for host in hosts.iter() {
+        if let Ok(addrs) = tokio::net::lookup_host((host.as_str(), 53)).await {
+            servers.extend(addrs.filter(|a| !servers.contains(a)).collect::<Vec<_>>());
+        }
+    }
+    if servers.is_empty() {
+        return Err(error::dns(format!("failed to resolve the nameservers of {}: {}", name, hosts.join(", "))));
+    }
+
+    // Plenty of hosts have no IPv6 route, so IPv6 is only used when there's nothing else
+    if servers.iter().any(|a| a.is_ipv4()) {
+        servers.retain(|a| a.is_ipv4());
+    }
+
+    Ok(servers)
+}
+
+async fn zone_nameservers(resolvers: &[SocketAddr], name: &str) -> Result<Vec<String>> {
+    let mut zone = normalize(name);
+    loop {
+        let mut res = Err(error::dns("no resolver configured"));
+        for resolver in resolvers {
+            res = query(*resolver, &zone, wire::TYPE_NS).await;
+            if res.is_ok() {
+                break;
+            }
+        }
+
+        let hosts: Vec<String> = res?.answers().iter()
+            .filter(|r| r.rtype == wire::TYPE_NS && r.name == zone)
+            .filter_map(|r| r.target.clone())
+            .collect();
+        if !hosts.is_empty() {
+            return Ok(hosts);
+        }
+
+        zone = match zone.split_once('.') {
+            Some((_, parent)) if !parent.is_empty() => parent.to_string(),
+            _ => return Err(error::dns(format!("no nameservers found for {}", name))),
+        };
+    }
+}
+
+async fn query(server: SocketAddr, name: &str, rtype: u16) -> Result<wire::Message> {
+    let id = wire::random_id();
+    let msg = wire::build_query(id, name, rtype)?;
+    let res = wire::exchange(server, &msg, false, QUERY_TIMEOUT).await?;
+
+    let res = wire::parse_message(&res)
+        .ok_or_else(|| error::dns(format!("malformed answer from {}", server)))?;
+    if res.id != id || res.flags & wire::FLAG_QR == 0 {
+        return Err(error::dns(format!("answer from {} does not match the query", server)));
+    }
+    match res.rcode() {
+        0 | wire::RCODE_NXDOMAIN => Ok(res),
+        rcode => Err(error::dns(format!("{} answered {} for {}", server, wire::rcode_name(rcode), name))),
+    }
+}
+
+fn system_resolvers() -> Result<Vec<SocketAddr>> {
+    let conf = std::fs::read_to_string(RESOLV_CONF)
+        .map_err(|e| error::io(e, Some(format!("failed to read {}", RESOLV_CONF))))?;
+
+    let resolvers: Vec<SocketAddr> = conf.lines()
+        .filter_map(|l| l.trim().strip_prefix("nameserver"))
+        .filter_map(|ip| ip.trim().parse::<IpAddr>().ok())
+        .map(|ip| SocketAddr::new(ip, 53))
+        .collect();
+    if resolvers.is_empty() {
+        return Err(error::dns(format!("no nameserver in {}", RESOLV_CONF)));
+    }
+
+    Ok(resolvers)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tokio::net::UdpSocket;
+
+    use crate::certs::csr::{Csr, generate_rsa_2048_priv_key};
+    use crate::client::Client;
+    use crate::client::certificates::{Certificate, CertificateStatus, CreateCertificateReq, VerifyCertificateReq};
+    use crate::client::validation::ValidationType;
+    use crate::testing::FakeZeroSsl;
+    use crate::validation::dns::{authoritative_nameservers, cname_records, DnsProvider, DnsValidator,
+                                 MemoryDnsProvider, stale_records, wait_for_propagation, zone_nameservers};
+    use crate::validation::wire::{CLASS_IN, encode_name, FLAG_QR, read_name, TYPE_CNAME, TYPE_NS};
+
+    const TEST_API_KEY: &str = "test-api-key";
+
+    // Answers CNAME queries from `provider`, and NS queries for example.com and example.net
+    async fn fake_resolver(provider: MemoryDnsProvider) -> SocketAddr {
+        let socket = UdpSocket::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 512];
+            loop {
+                let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+                let (name, end) = read_name(&buf[..len], 12).unwrap();
+                let qtype = u16::from_be_bytes([buf[end], buf[end + 1]]);
+
+                let answer = match qtype {
+                    TYPE_CNAME => provider.get(&name),
+                    TYPE_NS if name == "example.com" => Some("ns1.example.net".to_string()),
+                    TYPE_NS if name == "example.net" => Some("127.0.0.1".to_string()),
+                    _ => None,
+                };
+
+                let mut res = buf[..end + 4].to_vec();
+                res[2..4].copy_from_slice(&FLAG_QR.to_be_bytes());
+                if let Some(answer) = answer {
+                    res[7] = 1;
+                    let mut rdata = Vec::new();
+                    encode_name(&answer, &mut rdata).unwrap();
+                    res.extend_from_slice(&[0xc0, 12]);
+                    res.extend_from_slice(&qtype.to_be_bytes());
+                    res.extend_from_slice(&CLASS_IN.to_be_bytes());
+                    res.extend_from_slice(&60u32.to_be_bytes());
+                    res.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+                    res.extend_from_slice(&rdata);
+                } else if qtype == TYPE_CNAME {
+                    res[3] |= 3; // NXDOMAIN
+                }
+
+                socket.send_to(&res, peer).await.unwrap();
+            }
+        });
+
+        addr
+    }
+
+    async fn fake_cert() -> (FakeZeroSsl, Client, Certificate) {
+        let fake = FakeZeroSsl::start(TEST_API_KEY.to_string()).await.unwrap();
+        let client = Client::builder(TEST_API_KEY.to_string())
+            .with_api_url(fake.url())
+            .build()
+            .unwrap();
+
+        let pkey = generate_rsa_2048_priv_key().unwrap();
+        let mut csr = Csr::new("example.com".to_string());
+        let csr = csr.with_alt_names(vec!["www.example.com".to_string()]);
+        let req = CreateCertificateReq::from_csr(&pkey, csr).unwrap();
+        let cert = client.create_certificate(&req).await.unwrap().certificate().clone();
+
+        (fake, client, cert)
+    }
+
+    #[tokio::test]
+    async fn memory_provider_test() {
+        let provider = MemoryDnsProvider::new();
+        provider.create_cname("_ABC.Example.com.", "target.comodoca.com").await.unwrap();
+
+        assert_eq!(provider.get("_abc.example.com").as_deref(), Some("target.comodoca.com"));
+        provider.delete_cname("_abc.example.com").await.unwrap();
+        provider.delete_cname("_abc.example.com").await.unwrap();
+        assert!(provider.records().is_empty());
+    }
+
+    #[tokio::test]
+    async fn validate_test() {
+        let (fake, client, cert) = fake_cert().await;
+        let provider = MemoryDnsProvider::new();
+        let lookup = provider.clone();
+        fake.set_cname_lookup(Arc::new(move |name| lookup.get(name)));
+
+        let records = cname_records(&cert).unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(records[0].name.ends_with(".example.com"));
+
+        // Nothing created: ZeroSSL can't find the records
+        let req = VerifyCertificateReq::new(ValidationType::CnameCsrHash, None);
+        let err = client.verify_certificate(cert.id.clone().unwrap(), &req).await.unwrap_err();
+        assert!(err.is_api());
+
+        let resolver = fake_resolver(provider.clone()).await;
+        let mut validator = DnsValidator::new(provider.clone());
+        validator.with_propagation_check(vec![resolver], Duration::from_secs(5));
+        validator.validate(&client, &cert).await.expect("validation failed");
+
+        assert!(provider.records().is_empty());
+        let issued = client.get_certificate(cert.id.clone().unwrap()).await.unwrap();
+        assert_eq!(issued.status, Some(CertificateStatus::Issued));
+    }
+
+    #[tokio::test]
+    async fn propagation_test() {
+        let (_fake, _client, cert) = fake_cert().await;
+        let records = cname_records(&cert).unwrap();
+        let provider = MemoryDnsProvider::new();
+        let resolver = fake_resolver(provider.clone()).await;
+
+        assert_eq!(stale_records(&records, &[resolver]).await.unwrap(), records);
+
+        provider.create_cname(&records[0].name, &records[0].target).await.unwrap();
+        provider.create_cname(&records[1].name, "somewhere.else.com").await.unwrap();
+        assert_eq!(stale_records(&records, &[resolver]).await.unwrap(), vec![records[1].clone()]);
+
+        let err = wait_for_propagation(&records, &[resolver], Duration::from_millis(100)).await.unwrap_err();
+        assert!(err.is_timeout());
+        assert!(err.to_string().contains(&records[1].name));
+        assert!(!err.to_string().contains(&records[0].name));
+
+        provider.create_cname(&records[1].name, &records[1].target).await.unwrap();
+        wait_for_propagation(&records, &[resolver], Duration::from_secs(5)).await.unwrap();
+
+        // A server that doesn't answer may still serve old data, even when the others are up to date
+        let socket = UdpSocket::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+        let silent = socket.local_addr().unwrap();
+        assert_eq!(stale_records(&records[..1], &[resolver, silent]).await.unwrap(), records[..1].to_vec());
+    }
+
+    #[tokio::test]
+    async fn zone_nameservers_test() {
+        let resolver = fake_resolver(MemoryDnsProvider::new()).await;
+
+        let hosts = zone_nameservers(&[resolver], "_abc.www.Example.com.").await.unwrap();
+        assert_eq!(hosts, vec!["ns1.example.net".to_string()]);
+        assert!(zone_nameservers(&[resolver], "_abc.example.org").await.unwrap_err().is_dns());
+    }
+
+    #[tokio::test]
+    async fn authoritative_nameservers_test() {
+        let resolver = fake_resolver(MemoryDnsProvider::new()).await;
+
+        let servers = authoritative_nameservers(&[resolver], "_abc.example.net").await.unwrap();
+        assert_eq!(servers, vec![SocketAddr::from(([127, 0, 0, 1], 53))]);
+        assert!(authoritative_nameservers(&[resolver], "_abc.example.org").await.unwrap_err().is_dns());
+    }
+}
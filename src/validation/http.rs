@@ -0,0 +1,318 @@
+//! Serves `HTTP_CSR_HASH` validation files from a built-in web server.
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use hyper::service::{make_service_fn, service_fn};
+use tokio::sync::oneshot;
+
+use crate::client::Client;
+use crate::client::certificates::{Certificate, CertificateStatus, VerifyCertificateReq};
+use crate::client::validation::ValidationType;
+use crate::client::wait::WaitOptions;
+use crate::error as error;
+use crate::error::Result;
+use crate::validation::domain_files;
+
+pub const PKI_VALIDATION_PATH: &str = "/.well-known/pki-validation/";
+
+/// Validation files by file name (e.g. `<hash>.txt`), shared between servers
+/// and the code registering certificates. Cloning shares the store.
+///
+/// Files added for certificates are counted, so a file registered twice stays
+/// served until both registrations are removed.
+#[derive(Debug, Clone, Default)]
+pub struct ChallengeStore {
+    files: Arc<RwLock<HashMap<String, StoredFile>>>,
+}
+
+#[derive(Debug)]
+struct StoredFile {
+    content: String,
+    refs: usize,
+}
+
+impl ChallengeStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&self, file_name: String, content: String) {
+        self.files.write().unwrap().insert(file_name, StoredFile { content, refs: 1 });
+    }
+
+    pub fn remove(&self, file_name: &str) -> Option<String> {
+        self.files.write().unwrap().remove(file_name).map(|file| file.content)
+    }
+
+    pub fn get(&self, file_name: &str) -> Option<String> {
+        self.files.read().unwrap().get(file_name).map(|file| file.content.clone())
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.read().unwrap().is_empty()
+    }
+
+    /// Registers the validation file of every domain of `cert` and returns
+    /// the file names added. Fails without adding anything if another file
+    /// is already served under one of the names (e.g. for a certificate
+    /// requested with the same CSR).
+    pub fn add_certificate(&self, cert: &Certificate) -> Result<Vec<String>> {
+        let files = domain_files(cert)?;
+
+        let mut store = self.files.write().unwrap();
+        let mut added: Vec<(String, String)> = Vec::new();
+        for file in files {
+            if added.iter().any(|(file_name, _)| *file_name == file.file_name) {
+                continue;
+            }
+            let content = file.body();
+            if store.get(&file.file_name).map(|stored| stored.content != content).unwrap_or(false) {
+                return Err(error::validation(format!("another validation file is already served as {}", file.file_name)));
+            }
+            added.push((file.file_name, content));
+        }
+
+        let mut file_names = Vec::new();
+        for (file_name, content) in added {
+            store.entry(file_name.clone())
+                .or_insert(StoredFile { content, refs: 0 })
+                .refs += 1;
+            file_names.push(file_name);
+        }
+
+        Ok(file_names)
+    }
+
+    /// Undoes `add_certificate`. Files served for another certificate under
+    /// the same name are left alone.
+    pub fn remove_certificate(&self, cert: &Certificate) {
+        if let Ok(files) = domain_files(cert) {
+            let mut file_names: Vec<String> = Vec::new();
+            for file in files {
+                if !file_names.contains(&file.file_name) && self.get(&file.file_name) == Some(file.body()) {
+                    file_names.push(file.file_name);
+                }
+            }
+            for file_name in file_names {
+                self.release(&file_name);
+            }
+        }
+    }
+
+    // Drops one registration of `file_name`, and the file with the last one
+    fn release(&self, file_name: &str) {
+        let mut store = self.files.write().unwrap();
+        if let Some(file) = store.get_mut(file_name) {
+            file.refs -= 1;
+            if file.refs == 0 {
+                store.remove(file_name);
+            }
+        }
+    }
+}
+
+pub struct ChallengeServer {
+    addr: SocketAddr,
+    store: ChallengeStore,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl ChallengeServer {
+    /// Binds `addr` (usually port 80) and serves files from a new store until
+    /// dropped.
+    pub async fn bind(addr: SocketAddr) -> Result<Self> {
+        Self::bind_with_store(addr, ChallengeStore::new()).await
+    }
+
+    /// Like `bind`, serving from an existing (possibly shared) store.
+    pub async fn bind_with_store(addr: SocketAddr, store: ChallengeStore) -> Result<Self> {
+        let svc_store = store.clone();
+        let make_svc = make_service_fn(move |_conn| {
+            let store = svc_store.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| handle(store.clone(), req)))
+            }
+        });
+
+        let server = Server::try_bind(&addr)
+            .map_err(|e| error::io(e, Some(format!("failed to bind challenge server to {}", addr))))?
+            .serve(make_svc);
+        let addr = server.local_addr();
+
+        let (tx, rx) = oneshot::channel::<()>();
+        tokio::spawn(server.with_graceful_shutdown(async {
+            rx.await.ok();
+        }));
+
+        Ok(Self {
+            addr,
+            store,
+            shutdown: Some(tx),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn store(&self) -> &ChallengeStore {
+        &self.store
+    }
+
+    /// Serves the validation files of `cert`, asks ZeroSSL to validate via
+    /// `HTTP_CSR_HASH` and waits until the certificate is issued. The files
+    /// are removed again whatever the outcome.
+    pub async fn validate(&self, client: &Client, cert: &Certificate, opts: &WaitOptions) -> Result<Certificate> {
+        let id = cert.id.clone()
+            .ok_or_else(|| error::validation("certificate has no id"))?;
+        let file_names = self.store.add_certificate(cert)?;
+
+        let res = async {
+            client.verify_certificate(id.clone(), &VerifyCertificateReq::new(ValidationType::HttpCsrHash, None)).await?;
+            client.wait_for_status(id.clone(), CertificateStatus::Issued, opts).await
+        }.await;
+
+        for file_name in file_names {
+            self.store.release(&file_name);
+        }
+
+        res
+    }
+}
+
+impl Drop for ChallengeServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+async fn handle(store: ChallengeStore, req: Request<Body>) -> std::result::Result<Response<Body>, Infallible> {
+    let content = match *req.method() {
+        Method::GET | Method::HEAD => req.uri().path()
+            .strip_prefix(PKI_VALIDATION_PATH)
+            .and_then(|file_name| store.get(file_name)),
+        _ => None,
+    };
+
+    let res = match content {
+        Some(content) => Response::builder()
+            .header("content-type", "text/plain")
+            .body(Body::from(content)),
+        None => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty()),
+    };
+
+    Ok(res.unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    use crate::certs::csr::{Csr, generate_rsa_2048_priv_key};
+    use crate::client::Client;
+    use crate::client::certificates::{CertificateStatus, CreateCertificateReq, VerifyCertificateReq};
+    use crate::client::validation::ValidationType;
+    use crate::client::wait::WaitOptions;
+    use crate::testing::FakeZeroSsl;
+    use crate::validation::http::{ChallengeServer, ChallengeStore, PKI_VALIDATION_PATH};
+
+    const TEST_API_KEY: &str = "test-api-key";
+
+    #[tokio::test]
+    async fn challenge_server_test() {
+        let store = ChallengeStore::new();
+        let server = ChallengeServer::bind_with_store(SocketAddr::from(([127, 0, 0, 1], 0)), store.clone()).await
+            .expect("failed to bind");
+        store.insert("ABC.txt".to_string(), "line1\nline2".to_string());
+
+        let url = format!("http://{}{}", server.local_addr(), PKI_VALIDATION_PATH);
+        let res = reqwest::get(format!("{}ABC.txt", url)).await.unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.text().await.unwrap(), "line1\nline2");
+
+        store.remove("ABC.txt");
+        assert_eq!(reqwest::get(format!("{}ABC.txt", url)).await.unwrap().status(), 404);
+        assert_eq!(reqwest::get(format!("http://{}/ABC.txt", server.local_addr())).await.unwrap().status(), 404);
+    }
+
+    #[tokio::test]
+    async fn validate_test() {
+        let fake = FakeZeroSsl::start(TEST_API_KEY.to_string()).await.unwrap();
+        let client = Client::builder(TEST_API_KEY.to_string())
+            .with_api_url(fake.url())
+            .build()
+            .unwrap();
+        let server = ChallengeServer::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+        fake.set_file_validation_addr(server.local_addr());
+
+        let mut certs = Vec::new();
+        for domain in ["a.example.com", "b.example.com"] {
+            let pkey = generate_rsa_2048_priv_key().unwrap();
+            let mut csr = Csr::new(domain.to_string());
+            let csr = csr.with_alt_names(vec![format!("www.{}", domain)]);
+            let req = CreateCertificateReq::from_csr(&pkey, csr).unwrap();
+            certs.push(client.create_certificate(&req).await.unwrap().certificate().clone());
+        }
+
+        // Nothing served yet
+        let id = certs[0].id.clone().unwrap();
+        let err = client.verify_certificate(id, &VerifyCertificateReq::new(ValidationType::HttpCsrHash, None)).await
+            .unwrap_err();
+        assert!(err.is_api());
+
+        let mut opts = WaitOptions::default();
+        opts.with_initial_interval(Duration::from_millis(1));
+
+        let (a, b) = futures::join!(
+            server.validate(&client, &certs[0], &opts),
+            server.validate(&client, &certs[1], &opts));
+        assert_eq!(a.unwrap().status, Some(CertificateStatus::Issued));
+        assert_eq!(b.unwrap().status, Some(CertificateStatus::Issued));
+        assert!(server.store().is_empty());
+    }
+
+    #[tokio::test]
+    async fn shared_file_name_test() {
+        let fake = FakeZeroSsl::start(TEST_API_KEY.to_string()).await.unwrap();
+        let client = Client::builder(TEST_API_KEY.to_string())
+            .with_api_url(fake.url())
+            .build()
+            .unwrap();
+
+        // Same CSR, so the same file name with different content
+        let pkey = generate_rsa_2048_priv_key().unwrap();
+        let mut csr = Csr::new("example.com".to_string());
+        let csr = csr.with_alt_names(vec!["www.example.com".to_string()]);
+        let req = CreateCertificateReq::from_csr(&pkey, csr).unwrap();
+        let a = client.create_certificate(&req).await.unwrap().certificate().clone();
+        let b = client.create_certificate(&req).await.unwrap().certificate().clone();
+
+        let store = ChallengeStore::new();
+        let file_names = store.add_certificate(&a).unwrap();
+        assert_eq!(file_names.len(), 1);
+        let content = store.get(&file_names[0]).unwrap();
+
+        assert!(store.add_certificate(&b).unwrap_err().is_validation());
+        store.remove_certificate(&b);
+        assert_eq!(store.get(&file_names[0]), Some(content));
+
+        // Served until the last registration is removed
+        store.add_certificate(&a).unwrap();
+        store.remove_certificate(&a);
+        assert_eq!(store.len(), 1);
+        store.remove_certificate(&a);
+        assert!(store.is_empty());
+    }
+}
@@ -0,0 +1,27 @@
+//! Helpers for proving domain control to ZeroSSL without wiring up your own
+//! infrastructure.
+use crate::client::certificates::Certificate;
+use crate::client::validation::FileChallenge;
+use crate::error as error;
+use crate::error::Result;
+
+pub mod dns;
+#[cfg(any(test, feature = "challenge-server"))]
+pub mod http;
+pub mod rfc2136;
+mod wire;
+pub mod webroot;
+
+// The validation file ZeroSSL expects for each domain of `cert`
+pub(crate) fn domain_files(cert: &Certificate) -> Result<Vec<FileChallenge>> {
+    let mut files = Vec::new();
+    for domain in cert.domains() {
+        let file = cert.file_validation(&domain)
+            // Ends up in a file path, so no "..", hidden files or separators
+            .filter(|f| !f.file_name.starts_with('.') && !f.file_name.contains('\\'))
+            .ok_or_else(|| error::validation(format!("no file validation offered for {}", domain)))?;
+        files.push(file);
+    }
+
+    Ok(files)
+}
@@ -0,0 +1,424 @@
+//! A `DnsProvider` sending RFC 2136 dynamic updates signed with TSIG
+//! (RFC 8945, HMAC-SHA256), as accepted by BIND, Knot or hickory-dns.
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::error as error;
+use crate::error::Result;
+use crate::util::hmac_sha256;
+use crate::validation::dns::DnsProvider;
+use crate::validation::wire::{CLASS_ANY, CLASS_IN, encode_name, exchange, FLAG_QR, normalize, now_secs, parse_message,
+                              random_id, rcode_name, TYPE_CNAME, TYPE_SOA, TYPE_TSIG};
+
+pub const DEFAULT_TTL: u32 = 60;
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Allowed clock skew between us and the server, as recommended by RFC 8945
+const FUDGE: u16 = 300;
+
+const TSIG_ALGORITHM: &str = "hmac-sha256";
+
+const OPCODE_UPDATE: u16 = 5;
+
+#[derive(Clone)]
+pub struct Rfc2136Provider {
+    server: SocketAddr,
+    zone: String,
+    key_name: String,
+    key: Vec<u8>,
+    ttl: u32,
+    timeout: Duration,
+    tcp: bool,
+}
+
+impl std::fmt::Debug for Rfc2136Provider {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Rfc2136Provider")
+            .field("server", &self.server)
+            .field("zone", &self.zone)
+            .field("key_name", &self.key_name)
+            .field("key", &"<redacted>")
+            .field("ttl", &self.ttl)
+            .field("timeout", &self.timeout)
+            .field("tcp", &self.tcp)
+            .finish()
+    }
+}
+
+impl Rfc2136Provider {
+    /// Updates `zone` on the primary `server`, signing with the TSIG key
+    /// `key_name` (HMAC-SHA256).
+    pub fn new(server: SocketAddr, zone: String, key_name: String, key: Vec<u8>) -> Self {
+        Self {
+            server,
+            zone: normalize(&zone),
+            key_name: normalize(&key_name),
+            key,
+            ttl: DEFAULT_TTL,
+            timeout: DEFAULT_TIMEOUT,
+            tcp: false,
+        }
+    }
+
+    /// Like `new`, with the secret as found in BIND's `key` statement (base64).
+    pub fn from_base64_secret(server: SocketAddr, zone: String, key_name: String, secret: &str) -> Result<Self> {
+        let key = base64::decode(secret.trim())
+            .map_err(|e| error::decode(e, Some("invalid TSIG secret".to_string())))?;
+
+        Ok(Self::new(server, zone, key_name, key))
+    }
+
+    pub fn with_ttl(&mut self, ttl: u32) -> &mut Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Time allowed for the server to answer an update.
+    pub fn with_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sends updates over TCP instead of UDP. UDP answers that come back
+    /// truncated are retried over TCP either way.
+    pub fn with_tcp(&mut self, tcp: bool) -> &mut Self {
+        self.tcp = tcp;
+        self
+    }
+
+    // Accessors
+    pub fn server(&self) -> SocketAddr {
+        self.server
+    }
+
+    pub fn zone(&self) -> String {
+        self.zone.clone()
+    }
+
+    pub fn ttl(&self) -> u32 {
+        self.ttl
+    }
+
+    // Requests
+    async fn update(&self, updates: &[Update]) -> Result<()> {
+        for update in updates {
+            if !in_zone(update.name(), &self.zone) {
+                return Err(error::validation(format!("{} is not in zone {}", update.name(), self.zone)));
+            }
+        }
+
+        let id = random_id();
+        let mut msg = build_update(id, &self.zone, updates)?;
+        let time = now_secs();
+        let request_mac = tsig_mac(&self.key, &self.key_name, None, &msg, time, FUDGE, 0, &[])?;
+        append_tsig(&mut msg, &self.key_name, time, FUDGE, &request_mac, id, 0)?;
+
+        let res = exchange(self.server, &msg, self.tcp, self.timeout).await?;
+
+        self.check_response(id, &request_mac, &res)
+    }
+
+    fn check_response(&self, id: u16, request_mac: &[u8], res: &[u8]) -> Result<()> {
+        let parsed = parse_message(res)
+            .ok_or_else(|| error::dns("malformed response"))?;
+        if parsed.id != id || parsed.flags & FLAG_QR == 0 {
+            return Err(error::dns("response does not match the update"));
+        }
+
+        let rcode = parsed.rcode();
+        let tsig = match parsed.tsig {
+            Some(tsig) => tsig,
+            // Servers answer some errors (e.g. unknown key) without signing
+            None if rcode != 0 => return Err(error::dns(format!("update failed: {}", rcode_name(rcode)))),
+            None => return Err(error::dns("response is not signed")),
+        };
+        if tsig.key_name != self.key_name {
+            return Err(error::dns(format!("response signed with unexpected key {}", tsig.key_name)));
+        }
+        if tsig.error != 0 {
+            return Err(error::dns(format!("update rejected: {} ({})", rcode_name(rcode), tsig_error_name(tsig.error))));
+        }
+
+        let mut unsigned = res[..tsig.start].to_vec();
+        unsigned[0..2].copy_from_slice(&tsig.original_id.to_be_bytes());
+        let arcount = parsed.counts[3].checked_sub(1)
+            .ok_or_else(|| error::dns("response has a malformed TSIG record"))?;
+        unsigned[10..12].copy_from_slice(&arcount.to_be_bytes());
+        let expected = tsig_mac(&self.key, &self.key_name, Some(request_mac), &unsigned,
+                                tsig.time, tsig.fudge, tsig.error, &tsig.other)?;
+        if expected.len() != tsig.mac.len() || !openssl::memcmp::eq(&expected, &tsig.mac) {
+            return Err(error::dns("response signature is invalid"));
+        }
+        if now_secs().abs_diff(tsig.time) > u64::from(tsig.fudge) {
+            return Err(error::dns("response signature has expired"));
+        }
+
+        match rcode {
+            0 => Ok(()),
+            rcode => Err(error::dns(format!("update failed: {}", rcode_name(rcode)))),
+        }
+    }
+}
+
+impl DnsProvider for Rfc2136Provider {
+    async fn create_cname(&self, name: &str, target: &str) -> Result<()> {
+        let name = normalize(name);
+
+        // Replaces whatever a previous attempt left behind
+        self.update(&[
+            Update::DeleteRrset(name.clone(), TYPE_CNAME),
+            Update::AddCname(name, self.ttl, normalize(target)),
+        ]).await
+    }
+
+    async fn delete_cname(&self, name: &str) -> Result<()> {
+        self.update(&[Update::DeleteRrset(normalize(name), TYPE_CNAME)]).await
+    }
+}
+
+// Wire format
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Update {
+    DeleteRrset(String, u16),
+    AddCname(String, u32, String),
+}
+
+impl Update {
+    fn name(&self) -> &str {
+        match self {
+            Update::DeleteRrset(name, _) => name,
+            Update::AddCname(name, _, _) => name,
+        }
+    }
+}
+
+pub(crate) fn build_update(id: u16, zone: &str, updates: &[Update]) -> Result<Vec<u8>> {
+    let mut msg = Vec::with_capacity(512);
+    msg.extend_from_slice(&id.to_be_bytes());
+    msg.extend_from_slice(&(OPCODE_UPDATE << 11).to_be_bytes());
+    msg.extend_from_slice(&1u16.to_be_bytes()); // ZOCOUNT
+    msg.extend_from_slice(&0u16.to_be_bytes()); // PRCOUNT
+    msg.extend_from_slice(&(updates.len() as u16).to_be_bytes()); // UPCOUNT
+    msg.extend_from_slice(&0u16.to_be_bytes()); // ADCOUNT
+
+    encode_name(zone, &mut msg)?;
+    msg.extend_from_slice(&TYPE_SOA.to_be_bytes());
+    msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+
+    for update in updates {
+        match update {
+            Update::DeleteRrset(name, rtype) => {
+                encode_name(name, &mut msg)?;
+                msg.extend_from_slice(&rtype.to_be_bytes());
+                msg.extend_from_slice(&CLASS_ANY.to_be_bytes());
+                msg.extend_from_slice(&0u32.to_be_bytes());
+                msg.extend_from_slice(&0u16.to_be_bytes());
+            }
+            Update::AddCname(name, ttl, target) => {
+                let mut rdata = Vec::new();
+                encode_name(target, &mut rdata)?;
+
+                encode_name(name, &mut msg)?;
+                msg.extend_from_slice(&TYPE_CNAME.to_be_bytes());
+                msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+                msg.extend_from_slice(&ttl.to_be_bytes());
+                msg.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+                msg.extend_from_slice(&rdata);
+            }
+        }
+    }
+
+    Ok(msg)
+}
+
+/// The MAC over `msg` (without its TSIG record) and the TSIG variables. For
+/// responses, `request_mac` is the MAC of the request.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn tsig_mac(key: &[u8], key_name: &str, request_mac: Option<&[u8]>, msg: &[u8], time: u64,
+                       fudge: u16, tsig_error: u16, other: &[u8]) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(msg.len() + 128);
+    if let Some(request_mac) = request_mac {
+        data.extend_from_slice(&(request_mac.len() as u16).to_be_bytes());
+        data.extend_from_slice(request_mac);
+    }
+    data.extend_from_slice(msg);
+
+    encode_name(key_name, &mut data)?;
+    data.extend_from_slice(&CLASS_ANY.to_be_bytes());
+    data.extend_from_slice(&0u32.to_be_bytes());
+    encode_name(TSIG_ALGORITHM, &mut data)?;
+    data.extend_from_slice(&time.to_be_bytes()[2..]);
+    data.extend_from_slice(&fudge.to_be_bytes());
+    data.extend_from_slice(&tsig_error.to_be_bytes());
+    data.extend_from_slice(&(other.len() as u16).to_be_bytes());
+    data.extend_from_slice(other);
+
+    hmac_sha256(key, &data)
+        .map_err(|e| error::openssl(e, Some("failed to compute TSIG".to_string())))
+}
+
+pub(crate) fn append_tsig(msg: &mut Vec<u8>, key_name: &str, time: u64, fudge: u16, mac: &[u8],
+                          original_id: u16, tsig_error: u16) -> Result<()> {
+    let mut rdata = Vec::new();
+    encode_name(TSIG_ALGORITHM, &mut rdata)?;
+    rdata.extend_from_slice(&time.to_be_bytes()[2..]);
+    rdata.extend_from_slice(&fudge.to_be_bytes());
+    rdata.extend_from_slice(&(mac.len() as u16).to_be_bytes());
+    rdata.extend_from_slice(mac);
+    rdata.extend_from_slice(&original_id.to_be_bytes());
+    rdata.extend_from_slice(&tsig_error.to_be_bytes());
+    rdata.extend_from_slice(&0u16.to_be_bytes());
+
+    encode_name(key_name, msg)?;
+    msg.extend_from_slice(&TYPE_TSIG.to_be_bytes());
+    msg.extend_from_slice(&CLASS_ANY.to_be_bytes());
+    msg.extend_from_slice(&0u32.to_be_bytes());
+    msg.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    msg.extend_from_slice(&rdata);
+
+    let arcount = u16::from_be_bytes([msg[10], msg[11]]) + 1;
+    msg[10..12].copy_from_slice(&arcount.to_be_bytes());
+
+    Ok(())
+}
+
+fn in_zone(name: &str, zone: &str) -> bool {
+    name == zone || name.ends_with(&format!(".{}", zone))
+}
+
+fn tsig_error_name(error: u16) -> String {
+    match error {
+        16 => "BADSIG".to_string(),
+        17 => "BADKEY".to_string(),
+        18 => "BADTIME".to_string(),
+        22 => "BADTRUNC".to_string(),
+        other => format!("TSIG error {}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+
+    use tokio::net::UdpSocket;
+
+    use crate::validation::dns::DnsProvider;
+    use crate::validation::rfc2136::{append_tsig, build_update, Rfc2136Provider, tsig_mac, Update};
+    use crate::validation::wire::{CLASS_ANY, CLASS_IN, FLAG_QR, parse_message, read_name, TYPE_CNAME};
+
+    const KEY_NAME: &str = "update-key";
+    const KEY: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+    // Answers updates for example.com like a primary would, applying them to `records`
+    async fn fake_server(records: Arc<Mutex<HashMap<String, String>>>) -> SocketAddr {
+        let socket = UdpSocket::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 65535];
+            loop {
+                let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+                let req = buf[..len].to_vec();
+                let parsed = parse_message(&req).unwrap();
+                let tsig = parsed.tsig.as_ref().unwrap();
+
+                let mut unsigned = req[..tsig.start].to_vec();
+                unsigned[10..12].copy_from_slice(&parsed.counts[3].checked_sub(1).unwrap().to_be_bytes());
+                let key = if tsig.key_name == KEY_NAME { KEY } else { b"unknown".as_slice() };
+                let expected = tsig_mac(key, &tsig.key_name, None, &unsigned, tsig.time, tsig.fudge, 0, &[]).unwrap();
+
+                let rcode = if expected != tsig.mac {
+                    9 // NOTAUTH
+                } else {
+                    let mut records = records.lock().unwrap();
+                    for record in parsed.records.iter() {
+                        match (record.rtype, record.class) {
+                            (TYPE_CNAME, CLASS_ANY) => { records.remove(&record.name); }
+                            (TYPE_CNAME, CLASS_IN) => { records.insert(record.name.clone(), record.target.clone().unwrap()); }
+                            _ => {}
+                        }
+                    }
+                    0
+                };
+
+                // Header and zone section echoed back
+                let zone_end = read_name(&req, 12).unwrap().1 + 4;
+                let mut res = req[..zone_end].to_vec();
+                res[2..4].copy_from_slice(&(FLAG_QR | (5 << 11) | rcode).to_be_bytes());
+                res[6..12].copy_from_slice(&[0; 6]);
+                if rcode == 0 {
+                    let mac = tsig_mac(KEY, KEY_NAME, Some(&tsig.mac), &res, tsig.time, tsig.fudge, 0, &[]).unwrap();
+                    append_tsig(&mut res, KEY_NAME, tsig.time, tsig.fudge, &mac, parsed.id, 0).unwrap();
+                }
+
+                socket.send_to(&res, peer).await.unwrap();
+            }
+        });
+
+        addr
+    }
+
+    #[test]
+    fn build_update_test() {
+        let msg = build_update(0x1234, "Example.com.", &[
+            Update::DeleteRrset("_a.example.com".to_string(), TYPE_CNAME),
+            Update::AddCname("_a.example.com".to_string(), 60, "t.comodoca.com".to_string()),
+        ]).unwrap();
+
+        assert_eq!(&msg[..12], &[0x12, 0x34, 0x28, 0x00, 0, 1, 0, 0, 0, 2, 0, 0]);
+        assert_eq!(&msg[12..25], b"\x07example\x03com\x00");
+
+        let parsed = parse_message(&msg).unwrap();
+        assert_eq!(parsed.records.len(), 2);
+        assert_eq!((parsed.records[0].name.as_str(), parsed.records[0].rtype, parsed.records[0].class),
+                   ("_a.example.com", TYPE_CNAME, CLASS_ANY));
+        assert_eq!(parsed.records[1].target.as_deref(), Some("t.comodoca.com"));
+        assert!(parsed.tsig.is_none());
+
+        // TSIG outside the additional section is just another record
+        let mut msg = build_update(0x1234, "example.com", &[]).unwrap();
+        append_tsig(&mut msg, KEY_NAME, 0, 300, &[0; 32], 0x1234, 0).unwrap();
+        assert!(parse_message(&msg).unwrap().tsig.is_some());
+        msg[8..12].copy_from_slice(&[0, 1, 0, 0]);
+        let parsed = parse_message(&msg).unwrap();
+        assert!(parsed.tsig.is_none());
+        assert_eq!(parsed.records.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn update_test() {
+        let records = Arc::new(Mutex::new(HashMap::new()));
+        let addr = fake_server(records.clone()).await;
+        let provider = Rfc2136Provider::new(addr, "example.com".to_string(), KEY_NAME.to_string(), KEY.to_vec());
+
+        provider.create_cname("_ABC.www.example.com", "x.y.comodoca.com.").await.unwrap();
+        assert_eq!(records.lock().unwrap().get("_abc.www.example.com").map(|s| s.as_str()), Some("x.y.comodoca.com"));
+
+        provider.delete_cname("_abc.www.example.com").await.unwrap();
+        assert!(records.lock().unwrap().is_empty());
+
+        assert!(provider.create_cname("_abc.other.com", "x.comodoca.com").await.unwrap_err().is_validation());
+
+        let wrong_key = Rfc2136Provider::new(addr, "example.com".to_string(), KEY_NAME.to_string(), b"wrong".to_vec());
+        let err = wrong_key.create_cname("_abc.example.com", "x.comodoca.com").await.unwrap_err();
+        assert!(err.is_dns());
+    }
+
+    // Needs a primary accepting updates, e.g. BIND in a container:
+    // RFC2136_SERVER=127.0.0.1:53 RFC2136_ZONE=example.com RFC2136_KEY_NAME=update-key RFC2136_SECRET=<base64>
+    #[tokio::test]
+    #[ignore]
+    async fn live_test() {
+        let var = |name: &str| std::env::var(name).unwrap_or_else(|_| panic!("{} must be set", name));
+        let zone = var("RFC2136_ZONE");
+        let provider = Rfc2136Provider::from_base64_secret(var("RFC2136_SERVER").parse().unwrap(), zone.clone(),
+                                                          var("RFC2136_KEY_NAME"), &var("RFC2136_SECRET")).unwrap();
+
+        let name = format!("_zerossl-test.{}", zone);
+        provider.create_cname(&name, "target.comodoca.com").await.expect("failed to create record");
+        provider.delete_cname(&name).await.expect("failed to delete record");
+    }
+}
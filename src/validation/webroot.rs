@@ -0,0 +1,287 @@
+//! Writes `HTTP_CSR_HASH` validation files into the webroot of an existing
+//! web server (nginx, Apache, ...).
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::client::Client;
+use crate::client::certificates::{Certificate, CertificateStatus, VerifyCertificateReq};
+use crate::client::validation::ValidationType;
+use crate::client::wait::WaitOptions;
+use crate::error as error;
+use crate::error::Result;
+use crate::validation::domain_files;
+
+pub const PKI_VALIDATION_DIR: &str = ".well-known/pki-validation";
+
+// World readable, so the web server user can serve them
+pub const DEFAULT_FILE_MODE: u32 = 0o644;
+pub const DEFAULT_DIR_MODE: u32 = 0o755;
+
+/// Writes validation files and removes them again after `validate`, on
+/// `cleanup` or when dropped.
+#[derive(Debug, Default)]
+pub struct WebrootValidator {
+    webroot: Option<PathBuf>,
+    domain_webroots: HashMap<String, PathBuf>,
+    file_mode: Option<u32>,
+    written: Vec<PathBuf>,
+    created_dirs: Vec<PathBuf>,
+}
+
+impl WebrootValidator {
+    /// Uses `webroot` for every domain without a webroot of its own.
+    pub fn new(webroot: PathBuf) -> Self {
+        let mut validator = Self::default();
+        validator.webroot = Some(webroot);
+        validator
+    }
+
+    /// Serves `domain` from `webroot` (multi-site setups).
+    pub fn with_domain_webroot(&mut self, domain: String, webroot: PathBuf) -> &mut Self {
+        self.domain_webroots.insert(domain.to_lowercase(), webroot);
+        self
+    }
+
+    /// Unix permissions of the written files, `DEFAULT_FILE_MODE` by default.
+    pub fn with_file_mode(&mut self, file_mode: u32) -> &mut Self {
+        self.file_mode = Some(file_mode);
+        self
+    }
+
+    // Accessors
+    pub fn webroot(&self, domain: &str) -> Option<&Path> {
+        self.domain_webroots.get(&domain.to_lowercase())
+            .or(self.webroot.as_ref())
+            .map(|p| p.as_path())
+    }
+
+    /// The files written and not yet removed.
+    pub fn written(&self) -> &[PathBuf] {
+        &self.written
+    }
+
+    // Actions
+    /// Writes the validation file of every domain of `cert` and returns their paths.
+    pub fn write(&mut self, cert: &Certificate) -> Result<Vec<PathBuf>> {
+        let mut targets: Vec<(PathBuf, String)> = Vec::new();
+        for file in domain_files(cert)? {
+            let webroot = self.webroot(&file.domain)
+                .ok_or_else(|| error::validation(format!("no webroot for {}", file.domain)))?;
+            let path = webroot.join(PKI_VALIDATION_DIR).join(&file.file_name);
+
+            if !targets.iter().any(|(p, _)| *p == path) {
+                targets.push((path, file.body()));
+            }
+        }
+
+        let mut paths = Vec::new();
+        for (path, content) in targets {
+            self.write_file(&path, &content)
+                .map_err(|e| error::io(e, Some(format!("failed to write {}", path.display()))))?;
+            paths.push(path);
+        }
+
+        Ok(paths)
+    }
+
+    /// Removes the written files, and the directories created for them if
+    /// they are empty.
+    pub fn cleanup(&mut self) -> Result<()> {
+        let mut res = Ok(());
+
+        for path in std::mem::take(&mut self.written) {
+            match fs::remove_file(&path) {
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => res = Err(error::io(e, Some(format!("failed to remove {}", path.display())))),
+            }
+        }
+
+        // Deepest first; fails harmlessly if something else lives there
+        for dir in std::mem::take(&mut self.created_dirs).into_iter().rev() {
+            let _ = fs::remove_dir(dir);
+        }
+
+        res
+    }
+
+    /// Writes the files, asks ZeroSSL to validate via `HTTP_CSR_HASH` and
+    /// waits until the certificate is issued. The files are removed again
+    /// whatever the outcome.
+    pub async fn validate(&mut self, client: &Client, cert: &Certificate, opts: &WaitOptions) -> Result<Certificate> {
+        let id = cert.id.clone()
+            .ok_or_else(|| error::validation("certificate has no id"))?;
+
+        let res = match self.write(cert) {
+            Ok(_) => async {
+                client.verify_certificate(id.clone(), &VerifyCertificateReq::new(ValidationType::HttpCsrHash, None)).await?;
+                client.wait_for_status(id.clone(), CertificateStatus::Issued, opts).await
+            }.await,
+            Err(e) => Err(e),
+        };
+
+        let cleaned = self.cleanup();
+        let cert = res?;
+        cleaned?;
+
+        Ok(cert)
+    }
+
+    fn write_file(&mut self, path: &Path, content: &str) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            self.create_dirs(dir)?;
+        }
+
+        // Written next to the target and renamed, so it's never served half written
+        let tmp = path.with_extension("txt.tmp");
+        fs::write(&tmp, content)?;
+        set_mode(&tmp, self.file_mode.unwrap_or(DEFAULT_FILE_MODE))?;
+        fs::rename(&tmp, path)?;
+
+        if !self.written.iter().any(|p| p == path) {
+            self.written.push(path.to_path_buf());
+        }
+
+        Ok(())
+    }
+
+    // Like `fs::create_dir_all`, remembering which directories were created
+    fn create_dirs(&mut self, dir: &Path) -> io::Result<()> {
+        let mut missing = Vec::new();
+        let mut current = Some(dir);
+        while let Some(d) = current {
+            if d.exists() {
+                break;
+            }
+            missing.push(d.to_path_buf());
+            current = d.parent();
+        }
+
+        for d in missing.into_iter().rev() {
+            fs::create_dir(&d)?;
+            set_mode(&d, DEFAULT_DIR_MODE)?;
+            self.created_dirs.push(d);
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for WebrootValidator {
+    fn drop(&mut self) {
+        let _ = self.cleanup();
+    }
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use crate::certs::csr::{Csr, generate_rsa_2048_priv_key};
+    use crate::client::Client;
+    use crate::client::certificates::{Certificate, CertificateStatus, CreateCertificateReq};
+    use crate::client::wait::WaitOptions;
+    use crate::testing::FakeZeroSsl;
+    use crate::validation::webroot::{PKI_VALIDATION_DIR, WebrootValidator};
+
+    const TEST_API_KEY: &str = "test-api-key";
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let mut suffix = [0u8; 8];
+        openssl::rand::rand_bytes(&mut suffix).unwrap();
+        let dir = std::env::temp_dir().join(format!("zerossl-{}-{:x}", name, u64::from_le_bytes(suffix)));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    async fn create_certificate(client: &Client) -> Certificate {
+        let pkey = generate_rsa_2048_priv_key().unwrap();
+        let mut csr = Csr::new("example.com".to_string());
+        let csr = csr.with_alt_names(vec!["blog.example.com".to_string()]);
+        let req = CreateCertificateReq::from_csr(&pkey, csr).unwrap();
+
+        client.create_certificate(&req).await.unwrap().certificate().clone()
+    }
+
+    async fn fake_client() -> (FakeZeroSsl, Client) {
+        let fake = FakeZeroSsl::start(TEST_API_KEY.to_string()).await.unwrap();
+        let client = Client::builder(TEST_API_KEY.to_string())
+            .with_api_url(fake.url())
+            .build()
+            .unwrap();
+
+        (fake, client)
+    }
+
+    #[tokio::test]
+    async fn write_cleanup_test() {
+        let (_fake, client) = fake_client().await;
+        let cert = create_certificate(&client).await;
+        let (main, blog) = (temp_dir("main"), temp_dir("blog"));
+
+        let mut validator = WebrootValidator::new(main.clone());
+        validator.with_domain_webroot("blog.example.com".to_string(), blog.clone());
+        let paths = validator.write(&cert).unwrap();
+        assert_eq!(paths.len(), 2);
+        assert!(paths[0].starts_with(main.join(PKI_VALIDATION_DIR)));
+        assert!(paths[1].starts_with(blog.join(PKI_VALIDATION_DIR)));
+
+        let file = cert.file_validation("example.com").unwrap();
+        assert_eq!(fs::read_to_string(&paths[0]).unwrap(), file.body());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&paths[0]).unwrap().permissions().mode() & 0o777, 0o644);
+        }
+
+        // Directories still in use are left alone
+        fs::write(blog.join(PKI_VALIDATION_DIR).join("other.txt"), "other").unwrap();
+
+        drop(validator);
+        assert!(!paths[0].exists());
+        assert!(!main.join(".well-known").exists());
+        assert!(!paths[1].exists());
+        assert!(blog.join(PKI_VALIDATION_DIR).join("other.txt").exists());
+
+        fs::remove_dir_all(main).unwrap();
+        fs::remove_dir_all(blog).unwrap();
+    }
+
+    #[tokio::test]
+    async fn validate_test() {
+        let (_fake, client) = fake_client().await;
+        let cert = create_certificate(&client).await;
+        let webroot = temp_dir("validate");
+
+        let mut opts = WaitOptions::default();
+        opts.with_initial_interval(Duration::from_millis(1));
+        let mut validator = WebrootValidator::new(webroot.clone());
+        let issued = validator.validate(&client, &cert, &opts).await.unwrap();
+
+        assert_eq!(issued.status, Some(CertificateStatus::Issued));
+        assert!(validator.written().is_empty());
+        assert!(!webroot.join(".well-known").exists());
+
+        let mut validator = WebrootValidator::default();
+        assert!(validator.write(&cert).unwrap_err().is_validation());
+
+        fs::remove_dir_all(webroot).unwrap();
+    }
+}
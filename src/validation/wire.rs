@@ -0,0 +1,311 @@
+// Just enough of the DNS wire format (RFC 1035) for dynamic updates and
+// record lookups
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+
+use crate::error as error;
+use crate::error::Result;
+
+pub(crate) const TYPE_NS: u16 = 2;
+pub(crate) const TYPE_CNAME: u16 = 5;
+pub(crate) const TYPE_SOA: u16 = 6;
+pub(crate) const TYPE_TSIG: u16 = 250;
+pub(crate) const CLASS_IN: u16 = 1;
+pub(crate) const CLASS_ANY: u16 = 255;
+
+pub(crate) const FLAG_QR: u16 = 0x8000;
+pub(crate) const FLAG_TC: u16 = 0x0200;
+pub(crate) const FLAG_RD: u16 = 0x0100;
+
+pub(crate) const RCODE_NXDOMAIN: u16 = 3;
+
+pub(crate) struct Message {
+    pub(crate) id: u16,
+    pub(crate) flags: u16,
+    /// Question (zone), answer (prerequisite), authority (update) and
+    /// additional counts
+    pub(crate) counts: [u16; 4],
+    /// Records of all sections but the question, without TSIG
+    pub(crate) records: Vec<Record>,
+    pub(crate) tsig: Option<Tsig>,
+}
+
+impl Message {
+    pub(crate) fn rcode(&self) -> u16 {
+        self.flags & 0x000f
+    }
+
+    pub(crate) fn answers(&self) -> &[Record] {
+        let len = usize::from(self.counts[1]).min(self.records.len());
+        &self.records[..len]
+    }
+}
+
+pub(crate) struct Record {
+    pub(crate) name: String,
+    pub(crate) rtype: u16,
+    pub(crate) class: u16,
+    /// The name held by CNAME and NS records, decompressed
+    pub(crate) target: Option<String>,
+}
+
+pub(crate) struct Tsig {
+    /// Offset of the TSIG record in the message
+    pub(crate) start: usize,
+    pub(crate) key_name: String,
+    pub(crate) time: u64,
+    pub(crate) fudge: u16,
+    pub(crate) mac: Vec<u8>,
+    pub(crate) original_id: u16,
+    pub(crate) error: u16,
+    pub(crate) other: Vec<u8>,
+}
+
+pub(crate) fn build_query(id: u16, name: &str, rtype: u16) -> Result<Vec<u8>> {
+    let mut msg = Vec::with_capacity(64);
+    msg.extend_from_slice(&id.to_be_bytes());
+    msg.extend_from_slice(&FLAG_RD.to_be_bytes());
+    msg.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+
+    encode_name(name, &mut msg)?;
+    msg.extend_from_slice(&rtype.to_be_bytes());
+    msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+
+    Ok(msg)
+}
+
+pub(crate) fn parse_message(msg: &[u8]) -> Option<Message> {
+    let u16_at = |pos: usize| msg.get(pos..pos + 2).map(|b| u16::from_be_bytes([b[0], b[1]]));
+
+    let id = u16_at(0)?;
+    let flags = u16_at(2)?;
+    let counts = [u16_at(4)?, u16_at(6)?, u16_at(8)?, u16_at(10)?];
+    let mut pos = 12;
+
+    for _ in 0..counts[0] {
+        pos = read_name(msg, pos)?.1 + 4;
+    }
+
+    let mut records = Vec::new();
+    let mut tsig = None;
+    let before_additional = usize::from(counts[1]) + usize::from(counts[2]);
+    let total = before_additional + usize::from(counts[3]);
+    for i in 0..total {
+        let start = pos;
+        let (name, next) = read_name(msg, pos)?;
+        let rtype = u16_at(next)?;
+        let class = u16_at(next + 2)?;
+        let rdlength = usize::from(u16_at(next + 8)?);
+        let rdata_start = next + 10;
+        pos = rdata_start + rdlength;
+        if pos > msg.len() {
+            return None;
+        }
+
+        // Only valid as the very last record, in the additional section
+        if rtype == TYPE_TSIG && i == total - 1 && i >= before_additional {
+            tsig = Some(parse_tsig(msg, start, name, rdata_start)?);
+            continue;
+        }
+
+        let target = match rtype {
+            TYPE_CNAME | TYPE_NS if rdlength > 0 => Some(read_name(msg, rdata_start)?.0),
+            _ => None,
+        };
+        records.push(Record {
+            name,
+            rtype,
+            class,
+            target,
+        });
+    }
+
+    Some(Message {
+        id,
+        flags,
+        counts,
+        records,
+        tsig,
+    })
+}
+
+fn parse_tsig(msg: &[u8], start: usize, key_name: String, rdata_start: usize) -> Option<Tsig> {
+    let u16_at = |pos: usize| msg.get(pos..pos + 2).map(|b| u16::from_be_bytes([b[0], b[1]]));
+
+    let (_, pos) = read_name(msg, rdata_start)?;
+    let time = msg.get(pos..pos + 6)?
+        .iter()
+        .fold(0u64, |acc, b| (acc << 8) | u64::from(*b));
+    let fudge = u16_at(pos + 6)?;
+    let mac_size = usize::from(u16_at(pos + 8)?);
+    let mac = msg.get(pos + 10..pos + 10 + mac_size)?.to_vec();
+    let pos = pos + 10 + mac_size;
+    let original_id = u16_at(pos)?;
+    let error = u16_at(pos + 2)?;
+    let other_len = usize::from(u16_at(pos + 4)?);
+    let other = msg.get(pos + 6..pos + 6 + other_len)?.to_vec();
+
+    Some(Tsig {
+        start,
+        key_name,
+        time,
+        fudge,
+        mac,
+        original_id,
+        error,
+        other,
+    })
+}
+
+// Reads a possibly compressed name, returning it and the offset after it
+pub(crate) fn read_name(msg: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut end = None;
+    let mut jumps = 0;
+
+    loop {
+        let len = *msg.get(pos)?;
+        match len {
+            0 => {
+                end.get_or_insert(pos + 1);
+                break;
+            }
+            len if len & 0xc0 == 0xc0 => {
+                let pointer = (usize::from(len & 0x3f) << 8) | usize::from(*msg.get(pos + 1)?);
+                end.get_or_insert(pos + 2);
+                jumps += 1;
+                if jumps > 64 {
+                    return None;
+                }
+                pos = pointer;
+            }
+            len if len < 64 => {
+                let label = msg.get(pos + 1..pos + 1 + usize::from(len))?;
+                labels.push(String::from_utf8_lossy(label).to_lowercase());
+                pos += 1 + usize::from(len);
+            }
+            _ => return None,
+        }
+    }
+
+    Some((labels.join("."), end?))
+}
+
+// Uncompressed and lower case, which is also the canonical form TSIG needs
+pub(crate) fn encode_name(name: &str, out: &mut Vec<u8>) -> Result<()> {
+    let name = normalize(name);
+    if name.len() > 253 {
+        return Err(error::validation(format!("dns name too long: {}", name)));
+    }
+
+    for label in name.split('.').filter(|l| !l.is_empty()) {
+        if label.len() > 63 {
+            return Err(error::validation(format!("dns label too long: {}", label)));
+        }
+        out.push(label.len() as u8);
+        out.extend_from_slice(label.as_bytes());
+    }
+    out.push(0);
+
+    Ok(())
+}
+
+pub(crate) fn normalize(name: &str) -> String {
+    name.trim().trim_end_matches('.').to_lowercase()
+}
+
+pub(crate) fn rcode_name(rcode: u16) -> String {
+    match rcode {
+        0 => "NOERROR".to_string(),
+        1 => "FORMERR".to_string(),
+        2 => "SERVFAIL".to_string(),
+        3 => "NXDOMAIN".to_string(),
+        4 => "NOTIMP".to_string(),
+        5 => "REFUSED".to_string(),
+        6 => "YXDOMAIN".to_string(),
+        7 => "YXRRSET".to_string(),
+        8 => "NXRRSET".to_string(),
+        9 => "NOTAUTH".to_string(),
+        10 => "NOTZONE".to_string(),
+        other => format!("RCODE {}", other),
+    }
+}
+
+pub(crate) fn random_id() -> u16 {
+    let mut buf = [0u8; 2];
+    if openssl::rand::rand_bytes(&mut buf).is_err() {
+        return (now_secs() & 0xffff) as u16;
+    }
+
+    u16::from_be_bytes(buf)
+}
+
+pub(crate) fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Sends `msg` to `server` and returns the answer. UDP answers that come back
+/// truncated are retried over TCP.
+pub(crate) async fn exchange(server: SocketAddr, msg: &[u8], tcp: bool, timeout: Duration) -> Result<Vec<u8>> {
+    if !tcp {
+        let res = exchange_udp(server, msg, timeout).await?;
+        if res.len() < 4 || u16::from_be_bytes([res[2], res[3]]) & FLAG_TC == 0 {
+            return Ok(res);
+        }
+    }
+
+    exchange_tcp(server, msg, timeout).await
+}
+
+async fn exchange_udp(server: SocketAddr, msg: &[u8], timeout: Duration) -> Result<Vec<u8>> {
+    let bind: SocketAddr = match server {
+        SocketAddr::V4(_) => SocketAddr::from(([0, 0, 0, 0], 0)),
+        SocketAddr::V6(_) => SocketAddr::from(([0u16; 8], 0)),
+    };
+    let socket = UdpSocket::bind(bind).await
+        .map_err(|e| error::io(e, Some("failed to bind udp socket".to_string())))?;
+    socket.connect(server).await
+        .map_err(|e| error::io(e, Some(format!("failed to connect to {}", server))))?;
+    socket.send(msg).await
+        .map_err(|e| error::io(e, Some(format!("failed to send to {}", server))))?;
+
+    let recv = async {
+        let mut buf = vec![0u8; 65535];
+        loop {
+            let len = socket.recv(&mut buf).await?;
+            // Ignore stray datagrams
+            if len >= 2 && buf[..2] == msg[..2] {
+                return Ok::<_, std::io::Error>(buf[..len].to_vec());
+            }
+        }
+    };
+
+    tokio::time::timeout(timeout, recv).await
+        .map_err(|_| error::timeout(format!("no answer from {} after {:?}", server, timeout)))?
+        .map_err(|e| error::io(e, Some(format!("failed to receive from {}", server))))
+}
+
+async fn exchange_tcp(server: SocketAddr, msg: &[u8], timeout: Duration) -> Result<Vec<u8>> {
+    let exchange = async {
+        let mut stream = TcpStream::connect(server).await?;
+
+        let mut framed = (msg.len() as u16).to_be_bytes().to_vec();
+        framed.extend_from_slice(msg);
+        stream.write_all(&framed).await?;
+
+        let len = stream.read_u16().await?;
+        let mut buf = vec![0u8; usize::from(len)];
+        stream.read_exact(&mut buf).await?;
+
+        Ok::<_, std::io::Error>(buf)
+    };
+
+    tokio::time::timeout(timeout, exchange).await
+        .map_err(|_| error::timeout(format!("no answer from {} after {:?}", server, timeout)))?
+        .map_err(|e| error::io(e, Some(format!("tcp exchange with {} failed", server))))
+}